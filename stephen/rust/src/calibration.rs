@@ -0,0 +1,418 @@
+//!
+//! Calibrate the probabilities a model gives, so that of the rows given a
+//! probability of 0.8 about 80% really do have the label.
+//!
+//! A deep tree puts most rows in leaves that are all one label, so it is
+//! 100% sure about nearly everything. Calibration learns a mapping from
+//! the model's probability of each label to a better one. It is fitted on
+//! out of fold predictions, where no row is predicted by a model that was
+//! trained on it, otherwise it would learn to trust the model's
+//! overconfidence.
+//!
+//! Platt scaling fits a sigmoid, which is smooth and needs few rows.
+//! Isotonic regression fits any increasing step function, which can
+//! correct more but needs more rows not to overfit.
+//!
+
+use cross_validation::out_of_fold;
+use error::{DecisionsError, Result};
+use model::Model;
+use row::DataRow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+
+/// How to calibrate the probabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Method {
+    Platt,
+    Isotonic,
+}
+
+impl Method {
+    /// The names the methods can be given on the command line.
+    pub const NAMES: &'static [&'static str] = &["platt", "isotonic"];
+}
+
+impl FromStr for Method {
+    type Err = DecisionsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "platt" => Ok(Method::Platt),
+            "isotonic" => Ok(Method::Isotonic),
+            _ => Err(DecisionsError::Config(format!(
+                "Unknown calibration method {}, expected one of {}",
+                s,
+                Method::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+///
+/// Maps the probability of one label to a calibrated one.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Calibrator {
+    /// `1 / (1 + exp(a * p + b))`
+    Platt { a: f64, b: f64 },
+    /// Interpolates between the calibrated value at each of the points,
+    /// which are in increasing order.
+    Isotonic { points: Vec<(f64, f64)> },
+}
+
+impl Calibrator {
+    /// Fit a calibrator to the probabilities the model gave and whether the rows really had the label.
+    pub fn fit(method: Method, probabilities: &[f64], targets: &[bool]) -> Self {
+        match method {
+            Method::Platt => platt(probabilities, targets),
+            Method::Isotonic => isotonic(probabilities, targets),
+        }
+    }
+
+    pub fn apply(&self, p: f64) -> f64 {
+        match self {
+            Calibrator::Platt { a, b } => 1.0 / (1.0 + (a * p + b).exp()),
+            Calibrator::Isotonic { points } => {
+                let after = points.iter().position(|(x, _)| *x >= p);
+                match after {
+                    None => points.last().map_or(p, |(_, y)| *y),
+                    Some(0) => points[0].1,
+                    Some(idx) => {
+                        let (x0, y0) = points[idx - 1];
+                        let (x1, y1) = points[idx];
+                        y0 + (y1 - y0) * (p - x0) / (x1 - x0)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The cross entropy of the sigmoid, written so it doesn't overflow.
+fn platt_loss(probabilities: &[f64], targets: &[f64], a: f64, b: f64) -> f64 {
+    probabilities
+        .iter()
+        .zip(targets.iter())
+        .map(|(p, t)| {
+            let z = a * p + b;
+            if z >= 0.0 {
+                t * z + (-z).exp().ln_1p()
+            } else {
+                (t - 1.0) * z + z.exp().ln_1p()
+            }
+        })
+        .sum()
+}
+
+///
+/// Fit Platt's sigmoid with Newton's method, following Lin, Lin and Weng's
+/// "A note on Platt's probabilistic outputs for support vector machines".
+/// The targets are moved in a little from 0 and 1 so a perfectly separated
+/// set of rows doesn't send the sigmoid off to a step.
+///
+fn platt(probabilities: &[f64], targets: &[bool]) -> Calibrator {
+    let positive = targets.iter().filter(|&&t| t).count() as f64;
+    let negative = targets.len() as f64 - positive;
+    let hi = (positive + 1.0) / (positive + 2.0);
+    let lo = 1.0 / (negative + 2.0);
+    let targets: Vec<f64> = targets.iter().map(|&t| if t { hi } else { lo }).collect();
+
+    let mut a = 0.0;
+    let mut b = ((negative + 1.0) / (positive + 1.0)).ln();
+    let mut loss = platt_loss(probabilities, &targets, a, b);
+
+    for _ in 0..100 {
+        let (mut h11, mut h22, mut h21, mut g1, mut g2) = (1e-12, 1e-12, 0.0, 0.0, 0.0);
+        for (p, t) in probabilities.iter().zip(targets.iter()) {
+            let z = a * p + b;
+            let (q, r) = if z >= 0.0 {
+                ((-z).exp() / (1.0 + (-z).exp()), 1.0 / (1.0 + (-z).exp()))
+            } else {
+                (1.0 / (1.0 + z.exp()), z.exp() / (1.0 + z.exp()))
+            };
+            let d2 = q * r;
+            h11 += p * p * d2;
+            h22 += d2;
+            h21 += p * d2;
+            let d1 = t - q;
+            g1 += p * d1;
+            g2 += d1;
+        }
+        if g1.abs() < 1e-5 && g2.abs() < 1e-5 {
+            break;
+        }
+
+        let det = h11 * h22 - h21 * h21;
+        let da = -(h22 * g1 - h21 * g2) / det;
+        let db = -(-h21 * g1 + h11 * g2) / det;
+        let gd = g1 * da + g2 * db;
+
+        // Halve the step until the loss goes down enough.
+        let mut step = 1.0;
+        while step >= 1e-10 {
+            let (new_a, new_b) = (a + step * da, b + step * db);
+            let new_loss = platt_loss(probabilities, &targets, new_a, new_b);
+            if new_loss < loss + 1e-4 * step * gd {
+                a = new_a;
+                b = new_b;
+                loss = new_loss;
+                break;
+            }
+            step /= 2.0;
+        }
+        if step < 1e-10 {
+            break;
+        }
+    }
+
+    Calibrator::Platt { a, b }
+}
+
+///
+/// Fit isotonic regression with the pool adjacent violators algorithm.
+/// Rows with the same probability are pooled first, then neighbouring
+/// blocks are pooled while they go down.
+///
+fn isotonic(probabilities: &[f64], targets: &[bool]) -> Calibrator {
+    let mut pairs: Vec<(f64, f64)> = probabilities
+        .iter()
+        .zip(targets.iter())
+        .map(|(&p, &t)| (p, if t { 1.0 } else { 0.0 }))
+        .collect();
+    pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    // Each block is the probabilities it covers, its mean target and its size.
+    let mut blocks: Vec<(Vec<f64>, f64, f64)> = Vec::new();
+    for (p, t) in pairs {
+        match blocks.last_mut() {
+            Some((ps, mean, size)) if ps.last() == Some(&p) => {
+                *mean = (*mean * *size + t) / (*size + 1.0);
+                *size += 1.0;
+            }
+            _ => blocks.push((vec![p], t, 1.0)),
+        }
+        while blocks.len() > 1 && blocks[blocks.len() - 2].1 > blocks[blocks.len() - 1].1 {
+            let (ps, mean, size) = blocks.pop().unwrap();
+            let last = blocks.last_mut().unwrap();
+            last.1 = (last.1 * last.2 + mean * size) / (last.2 + size);
+            last.2 += size;
+            last.0.extend(ps);
+        }
+    }
+
+    // Keep the ends of each block, so values between blocks are interpolated.
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for (ps, mean, _) in blocks {
+        points.push((ps[0], mean));
+        if ps.len() > 1 {
+            points.push((ps[ps.len() - 1], mean));
+        }
+    }
+    Calibrator::Isotonic { points }
+}
+
+///
+/// A calibrator for each label. The calibrated probabilities are scaled to add up to 1.
+///
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Calibration {
+    pub calibrators: BTreeMap<String, Calibrator>,
+}
+
+impl Calibration {
+    ///
+    /// Fit a calibrator for each label, one label against the rest, from
+    /// the probabilities given to each row and the rows' labels.
+    ///
+    pub fn fit(method: Method, probabilities: &[HashMap<String, f64>], labels: &[String]) -> Self {
+        let classes: BTreeSet<&String> = labels.iter().chain(probabilities.iter().flat_map(|p| p.keys())).collect();
+        let calibrators = classes
+            .into_iter()
+            .map(|class| {
+                let scores: Vec<f64> = probabilities.iter().map(|p| *p.get(class).unwrap_or(&0.0)).collect();
+                let targets: Vec<bool> = labels.iter().map(|label| label == class).collect();
+                (class.clone(), Calibrator::fit(method, &scores, &targets))
+            })
+            .collect();
+        Calibration { calibrators }
+    }
+
+    /// Calibrate the probability of each label.
+    pub fn apply(&self, probabilities: &HashMap<String, f64>) -> HashMap<String, f64> {
+        let calibrated: HashMap<String, f64> = self
+            .calibrators
+            .iter()
+            .map(|(class, calibrator)| (class.clone(), calibrator.apply(*probabilities.get(class).unwrap_or(&0.0))))
+            .collect();
+        let total: f64 = calibrated.values().sum();
+        if total > 0.0 {
+            calibrated.into_iter().map(|(class, p)| (class, p / total)).collect()
+        } else {
+            probabilities.clone()
+        }
+    }
+}
+
+///
+/// A model with calibrated probabilities.
+///
+#[derive(Debug)]
+pub struct Calibrated<M> {
+    pub model: M,
+    pub calibration: Calibration,
+}
+
+impl<M> Model for Calibrated<M>
+where
+    M: Model,
+{
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        Ok(self.calibration.apply(&self.model.predict_proba(row)?))
+    }
+}
+
+///
+/// Fit a calibration on the out of fold predictions of the model from
+/// `train_fn`, then train the model on all the rows and calibrate it.
+///
+pub fn calibrate<T, M, F>(rows: &[&T], folds: usize, method: Method, train_fn: F) -> Result<Calibrated<M>>
+where
+    T: DataRow,
+    M: Model,
+    F: Fn(Vec<&T>) -> Result<M>,
+{
+    let probabilities = out_of_fold(rows, folds, &train_fn)?;
+    let labels = rows.iter().map(|row| row.label()).collect::<Result<Vec<String>>>()?;
+    Ok(Calibrated {
+        model: train_fn(rows.to_vec())?,
+        calibration: Calibration::fit(method, &probabilities, &labels),
+    })
+}
+
+/// The rows with predicted probabilities in one bin of a reliability diagram.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+    /// The mean probability the rows were given.
+    pub predicted: f64,
+    /// The proportion of the rows that really had the label.
+    pub observed: f64,
+}
+
+///
+/// Split the predicted probabilities of a label into equal width bins, and
+/// compare the mean prediction in each with how often the label turned up.
+/// A calibrated model has them about the same. Empty bins are left out.
+///
+pub fn reliability(probabilities: &[f64], targets: &[bool], bins: usize) -> Result<Vec<Bin>> {
+    if bins == 0 {
+        return Err(DecisionsError::Config("A reliability diagram needs at least one bin".to_string()));
+    }
+    let mut sums = vec![(0, 0.0, 0.0); bins];
+    for (p, &t) in probabilities.iter().zip(targets.iter()) {
+        let bin = ((p * bins as f64) as usize).min(bins - 1);
+        sums[bin].0 += 1;
+        sums[bin].1 += p;
+        sums[bin].2 += if t { 1.0 } else { 0.0 };
+    }
+    Ok(sums.into_iter()
+        .enumerate()
+        .filter(|(_, (count, _, _))| *count > 0)
+        .map(|(bin, (count, predicted, observed))| Bin {
+            lower: bin as f64 / bins as f64,
+            upper: (bin + 1) as f64 / bins as f64,
+            count,
+            predicted: predicted / count as f64,
+            observed: observed / count as f64,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metrics::brier;
+    use model::Estimator;
+    use random::Rng;
+    use row::test::{points, Point};
+    use tree::TreeParams;
+
+    #[test]
+    fn test_platt() {
+        // Labels drawn from a known sigmoid of the score.
+        let mut rng = Rng::new(8);
+        let scores: Vec<f64> = (0..5000).map(|_| rng.float()).collect();
+        let targets: Vec<bool> = scores
+            .iter()
+            .map(|s| rng.float() < 1.0 / (1.0 + (-6.0 * s + 3.0).exp()))
+            .collect();
+
+        match Calibrator::fit(Method::Platt, &scores, &targets) {
+            Calibrator::Platt { a, b } => {
+                assert!((a + 6.0).abs() < 0.5, "a = {}", a);
+                assert!((b - 3.0).abs() < 0.3, "b = {}", b);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_isotonic() {
+        let scores = [0.1, 0.2, 0.2, 0.3, 0.4, 0.5, 0.9];
+        let targets = [false, true, false, false, true, true, true];
+        let calibrator = Calibrator::fit(Method::Isotonic, &scores, &targets);
+
+        // 0.2 and 0.3 average out to 1/3, which is already more than 0.1 gets.
+        assert_eq!(
+            Calibrator::Isotonic {
+                points: vec![(0.1, 0.0), (0.2, 1.0 / 3.0), (0.3, 1.0 / 3.0), (0.4, 1.0), (0.5, 1.0), (0.9, 1.0)],
+            },
+            calibrator
+        );
+        assert_eq!(0.0, calibrator.apply(0.0));
+        assert!((calibrator.apply(0.15) - 1.0 / 6.0).abs() < 1e-12);
+        assert_eq!(1.0, calibrator.apply(1.0));
+
+        let mut last = 0.0;
+        for p in 0..=100 {
+            let calibrated = calibrator.apply(p as f64 / 100.0);
+            assert!(calibrated >= last);
+            last = calibrated;
+        }
+    }
+
+    #[test]
+    fn test_reliability() {
+        let bins = reliability(&[0.05, 0.15, 0.15, 1.0], &[false, true, false, true], 10).unwrap();
+        assert_eq!(3, bins.len());
+        assert_eq!(2, bins[1].count);
+        assert_eq!(0.15, bins[1].predicted);
+        assert_eq!(0.5, bins[1].observed);
+        assert_eq!(0.9, bins[2].lower);
+
+        assert!(reliability(&[0.5], &[true], 0).is_err());
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let data = points(600, 9);
+        let data: Vec<&Point> = data.iter().collect();
+        let (train, test): (Vec<&Point>, Vec<&Point>) = data.iter().partition(|row| row.id % 3 != 0);
+
+        let tree = TreeParams::default().fit(&train).unwrap();
+        for method in &[Method::Platt, Method::Isotonic] {
+            let calibrated = calibrate(&train, 5, *method, |rows| TreeParams::default().fit(&rows)).unwrap();
+            // A full depth tree is sure of everything, calibrating it makes it less wrong on new rows.
+            assert!(brier(&calibrated, &test).unwrap() < brier(&tree, &test).unwrap());
+            let p = calibrated.predict_proba(test[0]).unwrap();
+            assert!((p.values().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//!
+//! Misclassification costs, for when some mistakes are worse than others.
+//!
+//! A cost matrix gives the cost of predicting one label when the row
+//! really has another. It can be read from a csv file with a row for
+//! each actual label and a column for each predicted label :
+//!
+//! ```text
+//! actual,Lived,Died
+//! Lived,0,1
+//! Died,5,0
+//! ```
+//!
+//! or from a TOML file with a table for each actual label :
+//!
+//! ```toml
+//! [Died]
+//! Lived = 5
+//!
+//! [Lived]
+//! Died = 1
+//! ```
+//!
+//! Both of these say that predicting Lived for someone who died costs five
+//! times as much as the other way round. Getting a row right costs nothing
+//! and any mistake that isn't given costs 1.
+//!
+
+use csv;
+use error::{open, DecisionsError, Result};
+use model::Model;
+use row::DataRow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Read, Write};
+use toml;
+
+///
+/// The cost of each prediction for each actual label.
+///
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CostMatrix {
+    /// The costs for each actual label, then each predicted label.
+    costs: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl CostMatrix {
+    pub fn new() -> Self {
+        CostMatrix::default()
+    }
+
+    /// Set the cost of predicting `predicted` for a row that is really `actual`.
+    pub fn set(&mut self, actual: &str, predicted: &str, cost: f64) -> Result<()> {
+        if !cost.is_finite() || cost < 0.0 {
+            return Err(DecisionsError::Config(format!(
+                "The cost of predicting {} for {} is {}, costs can't be negative",
+                predicted, actual, cost
+            )));
+        }
+        self.costs
+            .entry(actual.to_string())
+            .or_default()
+            .insert(predicted.to_string(), cost);
+        Ok(())
+    }
+
+    /// Read the costs from a TOML file if it ends in `.toml`, otherwise from a csv file.
+    pub fn load(file: &str) -> Result<Self> {
+        let mut reader = open(file)?;
+        if file.ends_with(".toml") {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            CostMatrix::from_toml(&text)
+        } else {
+            CostMatrix::from_csv(reader)
+        }
+    }
+
+    /// Read the costs from a csv with a row for each actual label and a column for each predicted one.
+    /// The first column of the header is ignored.
+    pub fn from_csv<R>(reader: R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut rdr = csv::Reader::from_reader(reader);
+        let predicted: Vec<String> = rdr.headers()?.iter().skip(1).map(|h| h.to_string()).collect();
+        let mut matrix = CostMatrix::new();
+        for record in rdr.records() {
+            let record = record?;
+            let actual = record.get(0).unwrap_or_default();
+            for (predicted, cost) in predicted.iter().zip(record.iter().skip(1)) {
+                let cost = cost.trim().parse().map_err(|_| {
+                    DecisionsError::Config(format!(
+                        "The cost of predicting {} for {} should be a number, not {}",
+                        predicted, actual, cost
+                    ))
+                })?;
+                matrix.set(actual, predicted, cost)?;
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Read the costs from TOML with a table for each actual label.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let costs: BTreeMap<String, BTreeMap<String, f64>> = toml::from_str(text)?;
+        let mut matrix = CostMatrix::new();
+        for (actual, row) in &costs {
+            for (predicted, cost) in row {
+                matrix.set(actual, predicted, *cost)?;
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Write the costs out as a csv that `from_csv` can read back in.
+    pub fn to_csv<W>(&self, out: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let labels = self.labels();
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(Some("actual").into_iter().chain(labels.iter().map(|l| l.as_str())))?;
+        for actual in &labels {
+            let costs = labels.iter().map(|predicted| self.cost(actual, predicted).to_string());
+            writer.write_record(Some(actual.clone()).into_iter().chain(costs))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Every label that turns up in the matrix, as either the actual or predicted label.
+    pub fn labels(&self) -> BTreeSet<String> {
+        self.costs
+            .iter()
+            .flat_map(|(actual, row)| Some(actual).into_iter().chain(row.keys()))
+            .cloned()
+            .collect()
+    }
+
+    /// The cost of predicting `predicted` for a row that is really `actual`.
+    pub fn cost(&self, actual: &str, predicted: &str) -> f64 {
+        match self.costs.get(actual).and_then(|row| row.get(predicted)) {
+            Some(cost) => *cost,
+            None if actual == predicted => 0.0,
+            None => 1.0,
+        }
+    }
+
+    /// The most it can cost to get a row with this label wrong, used to weight
+    /// the rows when growing a tree.
+    pub fn weight(&self, actual: &str) -> f64 {
+        self.costs
+            .get(actual)
+            .and_then(|row| {
+                row.iter()
+                    .filter(|(predicted, _)| *predicted != actual)
+                    .map(|(_, cost)| *cost)
+                    .fold(None, |max: Option<f64>, cost| Some(max.map_or(cost, |max| max.max(cost))))
+            })
+            .unwrap_or(1.0)
+    }
+
+    /// The expected cost of predicting `predicted` given the probability of each label.
+    pub fn expected(&self, probabilities: &HashMap<String, f64>, predicted: &str) -> f64 {
+        // Add them up in order so the same probabilities always give the same cost.
+        let probabilities: BTreeMap<&String, &f64> = probabilities.iter().collect();
+        probabilities
+            .into_iter()
+            .map(|(actual, p)| p * self.cost(actual, predicted))
+            .sum()
+    }
+
+    /// The label with the lowest expected cost, the first one alphabetically if there is a tie.
+    pub fn decide(&self, probabilities: &HashMap<String, f64>) -> String {
+        let labels: BTreeSet<String> = probabilities.keys().cloned().chain(self.labels()).collect();
+        labels
+            .into_iter()
+            .map(|label| {
+                let cost = self.expected(probabilities, &label);
+                (label, cost)
+            })
+            .fold(None, |best: Option<(String, f64)>, (label, cost)| match best {
+                Some((_, best_cost)) if best_cost <= cost => best,
+                _ => Some((label, cost)),
+            })
+            .map(|(label, _)| label)
+            .unwrap_or_default()
+    }
+}
+
+///
+/// A model that predicts the label with the lowest expected cost rather than
+/// the most likely one.
+///
+#[derive(Debug)]
+pub struct MinimumCost<M> {
+    pub model: M,
+    pub costs: CostMatrix,
+}
+
+impl<M> MinimumCost<M>
+where
+    M: Model,
+{
+    pub fn new(model: M, costs: CostMatrix) -> Self {
+        MinimumCost { model, costs }
+    }
+}
+
+impl<M> Model for MinimumCost<M>
+where
+    M: Model,
+{
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        self.model.predict_proba(row)
+    }
+
+    fn predict<T>(&self, row: &T) -> Result<String>
+    where
+        T: DataRow,
+    {
+        Ok(self.costs.decide(&self.model.predict_proba(row)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metrics::{accuracy, cost};
+    use model::Estimator;
+    use row::test::{points, Point};
+    use tree::TreeParams;
+
+    fn costs() -> CostMatrix {
+        CostMatrix::from_csv("actual,Yes,No\nYes,0,1\nNo,5,0\n".as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_load() {
+        let toml = CostMatrix::from_toml("[No]\nYes = 5\n\n[Yes]\nNo = 1.0\n").unwrap();
+        assert_eq!(5.0, toml.cost("No", "Yes"));
+        assert_eq!(1.0, toml.cost("Yes", "No"));
+        assert_eq!(0.0, toml.cost("Yes", "Yes"));
+        // Mistakes that aren't given cost 1.
+        assert_eq!(1.0, toml.cost("Maybe", "Yes"));
+
+        let mut out = Vec::new();
+        toml.to_csv(&mut out).unwrap();
+        assert_eq!("actual,No,Yes\nNo,0,5\nYes,1,0\n", String::from_utf8(out).unwrap());
+        let csv = costs();
+        for (actual, predicted) in &[("No", "Yes"), ("Yes", "No"), ("No", "No")] {
+            assert_eq!(toml.cost(actual, predicted), csv.cost(actual, predicted));
+        }
+
+        assert_eq!(5.0, toml.weight("No"));
+        assert_eq!(1.0, toml.weight("Yes"));
+
+        assert!(CostMatrix::from_csv("actual,Yes\nNo,lots\n".as_bytes()).is_err());
+        assert!(CostMatrix::from_toml("[No]\nYes = -1\n").is_err());
+    }
+
+    #[test]
+    fn test_decide() {
+        let costs = costs();
+        let mut probabilities = HashMap::new();
+        probabilities.insert("Yes".to_string(), 0.75);
+        probabilities.insert("No".to_string(), 0.25);
+
+        // Yes is more likely, but if it's wrong it costs 5 rather than 1.
+        assert_eq!(1.25, costs.expected(&probabilities, "Yes"));
+        assert_eq!(0.75, costs.expected(&probabilities, "No"));
+        assert_eq!("No", costs.decide(&probabilities));
+
+        // With the default costs it's the most likely label.
+        assert_eq!("Yes", CostMatrix::new().decide(&probabilities));
+    }
+
+    #[test]
+    fn test_minimum_cost() {
+        let data = points(300, 5);
+        let data: Vec<&Point> = data.iter().collect();
+
+        let plain = TreeParams {
+            max_depth: Some(3),
+            ..TreeParams::default()
+        };
+        let costly = TreeParams {
+            costs: Some(costs()),
+            ..plain.clone()
+        };
+        let plain = plain.fit(&data).unwrap();
+        let costly = MinimumCost::new(costly.fit(&data).unwrap(), costs());
+
+        // Minimising the cost gives up some accuracy to make fewer of the expensive mistakes.
+        let plain_cost = cost(&plain, &data, &costs()).unwrap();
+        let costly_cost = cost(&costly, &data, &costs()).unwrap();
+        assert!(costly_cost < plain_cost);
+        assert!(accuracy(&costly, &data).unwrap() <= accuracy(&plain, &data).unwrap());
+    }
+}
@@ -1,5 +1,25 @@
-use tree::{Node, classify};
+use error::{DecisionsError, Result};
+use metrics::accuracy;
+use model::{Estimator, Model};
+use random::Rng;
 use row::DataRow;
+use sampling::{Sampled, Sampling};
+use std::collections::HashMap;
+
+///
+/// Check we can split this many rows into the folds. With fewer than two
+/// folds there is nothing to train on, and with more folds than rows some
+/// of them would be empty.
+///
+pub fn check_folds(rows: usize, folds: usize) -> Result<()> {
+    if folds < 2 {
+        return Err(DecisionsError::Config(format!("We need at least 2 folds, not {}", folds)));
+    }
+    if folds > rows {
+        return Err(DecisionsError::Config(format!("There are only {} rows to split into {} folds", rows, folds)));
+    }
+    Ok(())
+}
 
 ///
 /// Split the dataset into the training and test rows.
@@ -9,9 +29,13 @@ use row::DataRow;
 /// * folds - the number of folds we split the dataset up into.
 /// * current - the number of the current testing fold.
 ///
-fn fold_dataset<'a, T>(rows: &[&'a T], folds: usize, current: usize) -> (Vec<&'a T>, Vec<&'a T>) {
+pub fn fold_dataset<'a, T>(rows: &[&'a T], folds: usize, current: usize) -> Result<(Vec<&'a T>, Vec<&'a T>)> {
+    check_folds(rows.len(), folds)?;
+    if current >= folds {
+        return Err(DecisionsError::Config(format!("There is no fold {} of {}", current, folds)));
+    }
 
-    rows.iter()
+    Ok(rows.iter()
         .enumerate()
         .fold((Vec::new(), Vec::new()), |mut result, (idx, row)| {
             if idx % folds == current {
@@ -20,14 +44,14 @@ fn fold_dataset<'a, T>(rows: &[&'a T], folds: usize, current: usize) -> (Vec<&'a
                 result.0.push(*row);
             }
             result
-        })
+        }))
 }
 
 ///
 /// Cross validates the dataset with the given training function.
 /// Split the dataset up into a number of sections (specified by folds).
 /// Then take the first section. Treat that as test data. Use the remaining
-/// folds to train our decision tree. Test the tree against the test data.
+/// folds to train our model. Test the model against the test data.
 /// See what portion of the test set we predicted correctly.
 ///
 /// Repeat for each of the folds.
@@ -38,39 +62,207 @@ fn fold_dataset<'a, T>(rows: &[&'a T], folds: usize, current: usize) -> (Vec<&'a
 ///
 /// * rows - The rows of our data set to train against.
 /// * folds - The number of sections we should split our dataset into
-/// * train_fn - A function we should call to return the decision tree.
-pub fn validate<T, F>(rows: &[&T], folds: usize, train_fn: F ) -> f64 
+/// * train_fn - A function we should call to return the model, such as a decision tree.
+pub fn validate<T, M, F>(rows: &[&T], folds: usize, train_fn: F ) -> Result<f64> 
 where T: DataRow,
-      F: Fn(Vec<&T>) -> Node {
+      M: Model,
+      F: Fn(Vec<&T>) -> Result<M> {
+
+    let scores = fold_scores(rows, folds, train_fn)?;
+    Ok(scores.iter().sum::<f64>() / folds as f64)
+}
 
-    let score = (0..folds).fold(0.0, |score, test| {
-        let (train, test) = fold_dataset(rows, folds, test);
+///
+/// Cross validates like `validate`, but returns the score for each of the
+/// folds so we can see how much they vary.
+///
+pub fn fold_scores<T, M, F>(rows: &[&T], folds: usize, train_fn: F) -> Result<Vec<f64>>
+where T: DataRow,
+      M: Model,
+      F: Fn(Vec<&T>) -> Result<M> {
 
-        // Build the tree with our training folds.
-        let tree = train_fn(train);
+    check_folds(rows.len(), folds)?;
+    (0..folds)
+        .map(|fold| fold_score(rows, folds, fold, &train_fn))
+        .collect()
+}
 
-        // Test our tree against the test set.
-        let correct = test.iter().filter(|t| {
-            t.label() == classify(**t, &tree)
-        }).count();
-    
-        score + correct as f64 / test.len() as f64
-    });
-    
-    score / folds as f64
+///
+/// Train on all but one of the folds and score against that fold.
+///
+/// #Arguments
+///
+/// * rows - The rows of our data set to train against.
+/// * folds - The number of sections we should split our dataset into
+/// * fold - The fold to test against.
+/// * train_fn - A function we should call to return the model, such as a decision tree.
+pub fn fold_score<T, M, F>(rows: &[&T], folds: usize, fold: usize, train_fn: F) -> Result<f64>
+where T: DataRow,
+      M: Model,
+      F: Fn(Vec<&T>) -> Result<M> {
+
+    let (train, test) = fold_dataset(rows, folds, fold)?;
+
+    // Build the model with our training folds.
+    let model = train_fn(train)?;
+
+    // Test our model against the test set.
+    accuracy(&model, &test)
+}
+
+///
+/// The probabilities each row is given by the model trained on the other
+/// folds, in the same order as the rows. As no row is predicted by a model
+/// that saw it, these are what we fit calibrations and stacked models on.
+///
+pub fn out_of_fold<T, M, F>(rows: &[&T], folds: usize, train_fn: F) -> Result<Vec<HashMap<String, f64>>>
+where T: DataRow,
+      M: Model,
+      F: Fn(Vec<&T>) -> Result<M> {
+
+    check_folds(rows.len(), folds)?;
+    let mut probabilities: Vec<Option<HashMap<String, f64>>> = vec![None; rows.len()];
+    for fold in 0..folds {
+        let (train, _) = fold_dataset(rows, folds, fold)?;
+        let model = train_fn(train)?;
+        // The same rows `fold_dataset` puts in the test fold.
+        for idx in (fold..rows.len()).step_by(folds) {
+            probabilities[idx] = Some(model.predict_proba(rows[idx])?);
+        }
+    }
+    Ok(probabilities.into_iter().map(|p| p.unwrap_or_default()).collect())
+}
+
+///
+/// Cross validates the estimator, training a model with it for each fold.
+///
+pub fn cross_validate<T, E>(rows: &[&T], folds: usize, estimator: &E) -> Result<f64>
+where T: DataRow,
+      E: Estimator {
+
+    validate(rows, folds, |train| estimator.fit(&train))
+}
+
+///
+/// The settings to resample each fold with, each with its own seed worked
+/// out from the one in the settings.
+///
+pub fn fold_samplings(sampling: &Sampling, folds: usize) -> Vec<Sampling> {
+    let mut rng = Rng::new(sampling.seed);
+    (0..folds)
+        .map(|_| Sampling { seed: rng.next_u64(), ..sampling.clone() })
+        .collect()
+}
+
+///
+/// Cross validates like `validate`, but resamples the training folds
+/// before training on them. The test fold is always scored as it is, so
+/// none of the made up or repeated rows are ever tested on. Each fold is
+/// resampled with its own settings from `fold_samplings`.
+///
+pub fn validate_resampled<T, M, F>(rows: &[&T], folds: usize, sampling: &Sampling, train_fn: F) -> Result<f64>
+where T: DataRow,
+      M: Model,
+      F: Fn(Vec<&Sampled<T>>) -> Result<M> {
+
+    check_folds(rows.len(), folds)?;
+    let mut total = 0.0;
+    for (fold, sampling) in fold_samplings(sampling, folds).iter().enumerate() {
+        total += fold_score(rows, folds, fold, |train| {
+            let train = sampling.resample(&train)?;
+            train_fn(train.iter().collect())
+        })?;
+    }
+    Ok(total / folds as f64)
+}
+
+///
+/// Cross validates the estimator, resampling the training folds.
+///
+pub fn cross_validate_resampled<T, E>(rows: &[&T], folds: usize, estimator: &E, sampling: &Sampling) -> Result<f64>
+where T: DataRow,
+      E: Estimator {
+
+    validate_resampled(rows, folds, sampling, |train| estimator.fit(&train))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use row::test::{points, Point};
+    use sampling::Method;
+    use std::sync::Mutex;
+    use tree::TreeParams;
     
     #[test]
     fn test_fold() {
         let data = vec![&1, &2, &3, &4, &5, &6];
-        let folded = fold_dataset(&data, 3, 2);
+        let folded = fold_dataset(&data, 3, 2).unwrap();
         
         assert_eq!((vec![&1, &2, &4, &5],
                     vec![&3, &6]), folded);
     }
- 
+
+    #[test]
+    fn test_bad_folds() {
+        let data = points(10, 2);
+        let data: Vec<&Point> = data.iter().collect();
+        let train = |train: Vec<&Point>| TreeParams::default().fit(&train);
+
+        for folds in &[0, 1, 11] {
+            assert!(validate(&data, *folds, train).is_err());
+            assert!(out_of_fold(&data, *folds, train).is_err());
+            assert!(fold_dataset(&data, *folds, 0).is_err());
+        }
+        assert!(fold_dataset(&data, 2, 2).is_err());
+        assert!(validate(&data, 10, train).is_ok());
+    }
+
+    #[test]
+    fn test_out_of_fold() {
+        let data = points(50, 6);
+        let data: Vec<&Point> = data.iter().collect();
+
+        // A tree that has seen a row is sure about it, so these are only right if the folds are kept apart.
+        let probabilities = out_of_fold(&data, 5, |train| TreeParams::default().fit(&train)).unwrap();
+        assert_eq!(data.len(), probabilities.len());
+        let tree = TreeParams::default().fit(&data).unwrap();
+        for fold in 0..5 {
+            let (train, test) = fold_dataset(&data, 5, fold).unwrap();
+            let model = TreeParams::default().fit(&train).unwrap();
+            for (idx, row) in data.iter().enumerate().filter(|(idx, _)| idx % 5 == fold) {
+                assert_eq!(model.predict_proba(*row).unwrap(), probabilities[idx]);
+                assert!(test.iter().any(|t| t.id() == row.id()));
+            }
+        }
+        assert!(data
+            .iter()
+            .zip(probabilities.iter())
+            .any(|(row, p)| tree.predict_proba(*row).unwrap() != *p));
+    }
+
+    #[test]
+    fn test_resampled_folds_dont_leak() {
+        let data = points(200, 4);
+        let data: Vec<&Point> = data.iter().filter(|row| row.label == "No" || row.id % 4 == 0).collect();
+        let sampling = Sampling {
+            method: Method::Smote,
+            ..Sampling::default()
+        };
+
+        let trained = Mutex::new(Vec::new());
+        let score = validate_resampled(&data, 5, &sampling, |train| {
+            trained.lock().unwrap().push(train.iter().map(|row| row.id()).collect::<Vec<i64>>());
+            TreeParams::default().fit(&train)
+        })
+        .unwrap();
+        assert!(score > 0.5);
+
+        // Each fold trains on the other folds' rows and made up ones, never its own test rows.
+        for (fold, ids) in trained.into_inner().unwrap().iter().enumerate() {
+            let (_, test) = fold_dataset(&data, 5, fold).unwrap();
+            assert!(test.iter().all(|row| !ids.contains(&row.id())));
+            assert!(ids.iter().any(|&id| id < 0));
+        }
+    }
 }
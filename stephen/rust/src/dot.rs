@@ -0,0 +1,57 @@
+//!
+//! Drawing trees in the dot format, for graphviz to turn into pictures.
+//!
+
+///
+/// A node of a tree that can be drawn.
+///
+pub trait DotNode {
+    /// The text inside the node.
+    fn label(&self) -> String;
+    /// The node's children, each with the text for the edge leading to it.
+    /// Leaves have none.
+    fn children(&self) -> Vec<(String, &Self)>;
+}
+
+///
+/// Create a dot format for a tree. Leaves are circles and decisions boxes.
+///
+pub fn to_dot<N>(root: &N) -> String
+where
+    N: DotNode,
+{
+    let mut id = 0;
+    let mut next_id = || {
+        id += 1;
+        id
+    };
+
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+
+    // We won't use recursion which risks blowing up the stack.
+    let mut traversal = vec![(next_id(), root)];
+
+    while !traversal.is_empty() {
+        let mut next = Vec::new();
+        for (id, node) in &traversal {
+            let children = node.children();
+            let shape = if children.is_empty() { "circle" } else { "box" };
+            nodes.push(format!("\t{}[shape={},label=\"{}\"];", id, shape, node.label()));
+
+            for (label, child) in children {
+                let child_id = next_id();
+                edges.push(format!("\t{}->{}[fontsize=32,label=\"{}\"];", id, child_id, label));
+                next.push((child_id, child));
+            }
+        }
+
+        traversal = next;
+    }
+
+    format!(
+        "digraph Tree {{\n{}\n{}\n}}",
+        nodes.join("\n"),
+        edges.join("\n")
+    )
+}
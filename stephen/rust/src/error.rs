@@ -0,0 +1,178 @@
+use csv;
+use regex;
+use serde_json;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use toml;
+
+#[derive(Debug)]
+pub enum DecisionsError {
+    /// A file couldn't be opened or created.
+    File { path: String, err: io::Error },
+    /// Reading or writing failed.
+    Io(io::Error),
+    /// A csv file couldn't be read. The line and column are 1 based, when we know them.
+    Csv {
+        line: Option<u64>,
+        column: Option<u64>,
+        message: String,
+    },
+    /// A json model or tree dump couldn't be read.
+    Json(serde_json::Error),
+    /// A config file, or something in it, isn't right.
+    Config(String),
+    /// A saved or imported model doesn't make sense.
+    Model(String),
+    /// The data doesn't have the shape we expected, a missing column for example.
+    Schema(String),
+    /// We needed the label for a row, but it doesn't have one.
+    MissingLabel { id: i64 },
+    /// A question compared a column against a value of a different type.
+    TypeMismatch {
+        column: String,
+        expected: String,
+        found: String,
+    },
+}
+
+pub type Result<T> = ::std::result::Result<T, DecisionsError>;
+
+/// Open a file, saying which file it was if we can't.
+pub fn open(path: &str) -> Result<File> {
+    File::open(path).map_err(|err| DecisionsError::File {
+        path: path.to_string(),
+        err,
+    })
+}
+
+/// Create a file, saying which file it was if we can't.
+pub fn create(path: &str) -> Result<File> {
+    File::create(path).map_err(|err| DecisionsError::File {
+        path: path.to_string(),
+        err,
+    })
+}
+
+impl fmt::Display for DecisionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecisionsError::File { path, err } => write!(f, "{}: {}", path, err),
+            DecisionsError::Io(err) => write!(f, "{}", err),
+            DecisionsError::Csv {
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "Bad csv")?;
+                if let Some(line) = line {
+                    write!(f, " at line {}", line)?;
+                }
+                if let Some(column) = column {
+                    write!(f, " column {}", column)?;
+                }
+                write!(f, ": {}", message)
+            }
+            DecisionsError::Json(err) => write!(f, "Bad json: {}", err),
+            DecisionsError::Config(message) => write!(f, "Bad config: {}", message),
+            DecisionsError::Model(message) => write!(f, "Bad model: {}", message),
+            DecisionsError::Schema(message) => write!(f, "Schema mismatch: {}", message),
+            DecisionsError::MissingLabel { id } => write!(f, "Row {} doesn't have a label", id),
+            DecisionsError::TypeMismatch {
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Column {} should hold {} but has {}",
+                column, expected, found
+            ),
+        }
+    }
+}
+
+impl Error for DecisionsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecisionsError::File { err, .. } => Some(err),
+            DecisionsError::Io(err) => Some(err),
+            DecisionsError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DecisionsError {
+    fn from(err: io::Error) -> Self {
+        DecisionsError::Io(err)
+    }
+}
+
+impl From<csv::Error> for DecisionsError {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map(|pos| pos.line());
+        match err.into_kind() {
+            csv::ErrorKind::Io(err) => DecisionsError::Io(err),
+            csv::ErrorKind::Deserialize { err, .. } => DecisionsError::Csv {
+                line,
+                column: err.field().map(|field| field + 1),
+                message: err.kind().to_string(),
+            },
+            kind => DecisionsError::Csv {
+                line,
+                column: None,
+                message: format!("{:?}", kind),
+            },
+        }
+    }
+}
+
+impl From<serde_json::Error> for DecisionsError {
+    fn from(err: serde_json::Error) -> Self {
+        DecisionsError::Json(err)
+    }
+}
+
+impl From<toml::de::Error> for DecisionsError {
+    fn from(err: toml::de::Error) -> Self {
+        DecisionsError::Config(err.to_string())
+    }
+}
+
+impl From<regex::Error> for DecisionsError {
+    fn from(err: regex::Error) -> Self {
+        DecisionsError::Config(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Row {
+        #[allow(dead_code)]
+        a: i64,
+        #[allow(dead_code)]
+        b: i64,
+    }
+
+    #[test]
+    fn test_csv_error_position() {
+        let data = "a,b\n1,2\n3,x\n";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let err = rdr
+            .deserialize::<Row>()
+            .collect::<::std::result::Result<Vec<Row>, csv::Error>>()
+            .unwrap_err();
+
+        match DecisionsError::from(err) {
+            DecisionsError::Csv { line, column, .. } => {
+                assert_eq!(Some(3), line);
+                assert_eq!(Some(2), column);
+            }
+            err => panic!("Expected a csv error, got {:?}", err),
+        }
+    }
+}
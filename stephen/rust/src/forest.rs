@@ -0,0 +1,479 @@
+//!
+//! Ensembles of extremely randomised trees (Geurts, Ernst and Wehenkel, 2006).
+//!
+//! Each tree is grown on all of the training rows, but each node only tries
+//! one random split for each of a few random columns. The trees vary a lot
+//! on their own, and averaging their probabilities smooths that out.
+//!
+//! With `bootstrap` each tree is grown on a sample of the rows drawn with
+//! replacement instead, and remembers the rows it didn't see. Predicting
+//! each training row with just the trees that didn't see it gives an out of
+//! bag estimate of how well the forest does on new rows, without having to
+//! cross validate.
+//!
+
+use error::{DecisionsError, Result};
+use metrics::brier_score;
+use model::{most_likely, Estimator, Model};
+use random::Rng;
+use rayon::prelude::*;
+use row::{Col, DataRow, Replaced};
+use std::collections::HashMap;
+use tree::{build_extra_tree, Node, TreeParams};
+
+///
+/// A number of trees whose probabilities are averaged.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Forest {
+    pub trees: Vec<Node>,
+    /// The positions of the training rows each tree didn't see, if the
+    /// trees were grown on bootstrap samples.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub out_of_bag: Vec<Vec<usize>>,
+}
+
+///
+/// How well the forest does on the training rows, scored with the trees
+/// that didn't see each row.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfBag {
+    /// The number of rows that at least one tree didn't see. The others aren't scored.
+    pub rows: usize,
+    /// The proportion of those rows predicted right, weighted by the rows' weights.
+    pub accuracy: f64,
+    /// The Brier score of their probabilities.
+    pub brier: f64,
+}
+
+impl Forest {
+    /// The depth of the deepest tree.
+    pub fn depth(&self) -> usize {
+        self.trees.iter().map(Node::depth).max().unwrap_or(0)
+    }
+
+    /// The number of nodes in all of the trees.
+    pub fn node_count(&self) -> usize {
+        self.trees.iter().map(Node::node_count).sum()
+    }
+
+    /// The number of leaves in all of the trees.
+    pub fn leaf_count(&self) -> usize {
+        self.trees.iter().map(Node::leaf_count).sum()
+    }
+
+    /// The rows each tree didn't see, checking they are for this many training rows.
+    fn out_of_bag(&self, rows: usize) -> Result<&[Vec<usize>]> {
+        if self.out_of_bag.is_empty() {
+            return Err(DecisionsError::Model(
+                "The forest wasn't grown on bootstrap samples, so every tree saw every row".to_string(),
+            ));
+        }
+        if self.out_of_bag.iter().flatten().any(|&idx| idx >= rows) {
+            return Err(DecisionsError::Model(format!(
+                "The forest was trained on more than {} rows, out of bag scores need the rows it was trained on",
+                rows
+            )));
+        }
+        Ok(&self.out_of_bag)
+    }
+
+    ///
+    /// The mean probabilities for each training row from the trees that didn't
+    /// see it, or None if every tree saw it. The rows must be the ones the
+    /// forest was trained on, in the same order.
+    ///
+    pub fn oob_predict_proba<T>(&self, rows: &[&T]) -> Result<Vec<Option<HashMap<String, f64>>>>
+    where
+        T: DataRow,
+    {
+        let mut totals: Vec<(usize, HashMap<String, f64>)> = vec![(0, HashMap::new()); rows.len()];
+        for (tree, unseen) in self.trees.iter().zip(self.out_of_bag(rows.len())?) {
+            for &idx in unseen {
+                let (count, total) = &mut totals[idx];
+                *count += 1;
+                for (label, p) in tree.predict_proba(rows[idx])? {
+                    *total.entry(label).or_insert(0.0) += p;
+                }
+            }
+        }
+        Ok(totals
+            .into_iter()
+            .map(|(count, total)| match count {
+                0 => None,
+                _ => Some(total.into_iter().map(|(label, p)| (label, p / count as f64)).collect()),
+            })
+            .collect())
+    }
+
+    /// The most likely label for each training row from the trees that didn't see it.
+    pub fn oob_predict<T>(&self, rows: &[&T]) -> Result<Vec<Option<String>>>
+    where
+        T: DataRow,
+    {
+        Ok(self
+            .oob_predict_proba(rows)?
+            .into_iter()
+            .map(|probabilities| probabilities.map(most_likely))
+            .collect())
+    }
+
+    /// The out of bag accuracy and Brier score over the training rows.
+    pub fn oob_score<T>(&self, rows: &[&T]) -> Result<OutOfBag>
+    where
+        T: DataRow,
+    {
+        let mut correct = 0.0;
+        let mut total = 0.0;
+        let mut probabilities = Vec::new();
+        let mut labels = Vec::new();
+        for (row, oob) in rows.iter().zip(self.oob_predict_proba(rows)?) {
+            if let Some(oob) = oob {
+                let label = row.label()?;
+                if most_likely(oob.clone()) == label {
+                    correct += row.weight();
+                }
+                total += row.weight();
+                probabilities.push(oob);
+                labels.push(label);
+            }
+        }
+        if probabilities.is_empty() {
+            return Err(DecisionsError::Model("Every tree saw every row, so there is nothing to score".to_string()));
+        }
+
+        Ok(OutOfBag {
+            rows: probabilities.len(),
+            accuracy: correct / total,
+            brier: brier_score(&probabilities, &labels),
+        })
+    }
+
+    ///
+    /// How much each column matters, as the mean over the trees of how much
+    /// the tree's accuracy on the rows it didn't see drops when the column's
+    /// values are shuffled between those rows (Breiman, 2001). The columns
+    /// are scored in parallel, and the seed makes the shuffles repeatable.
+    ///
+    pub fn oob_importance<T>(&self, rows: &[&T], seed: u64) -> Result<Vec<(String, f64)>>
+    where
+        T: DataRow + Sync,
+    {
+        let out_of_bag = self.out_of_bag(rows.len())?;
+        let columns = rows.first().map_or(0, |row| row.len());
+        let mut rng = Rng::new(seed);
+        let rngs: Vec<Rng> = (0..columns).map(|_| rng.split()).collect();
+
+        rngs.into_par_iter()
+            .enumerate()
+            .map(|(col, mut rng)| {
+                let mut drop = 0.0;
+                for (tree, unseen) in self.trees.iter().zip(out_of_bag) {
+                    let unseen: Vec<&T> = unseen.iter().map(|&idx| rows[idx]).collect();
+                    let mut values = unseen.iter().map(|row| row.value(col)).collect::<Result<Vec<Col>>>()?;
+                    rng.shuffle(&mut values);
+                    let permuted: Vec<Replaced<T>> = unseen
+                        .iter()
+                        .zip(values)
+                        .map(|(row, value)| Replaced::new(*row, col, value))
+                        .collect();
+                    let permuted: Vec<&Replaced<T>> = permuted.iter().collect();
+                    drop += weighted_accuracy(tree, &unseen)? - weighted_accuracy(tree, &permuted)?;
+                }
+                Ok((rows[0].name(col)?, drop / self.trees.len() as f64))
+            })
+            .collect()
+    }
+}
+
+/// The accuracy of the tree on the rows, or 0 if there aren't any.
+fn weighted_accuracy<T>(tree: &Node, rows: &[&T]) -> Result<f64>
+where
+    T: DataRow,
+{
+    let mut correct = 0.0;
+    let mut total = 0.0;
+    for row in rows {
+        if tree.predict(*row)? == row.label()? {
+            correct += row.weight();
+        }
+        total += row.weight();
+    }
+    Ok(if total > 0.0 { correct / total } else { 0.0 })
+}
+
+impl Model for Forest {
+    /// The mean over the trees of the probability of each label.
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        let mut probabilities = HashMap::new();
+        for tree in &self.trees {
+            for (label, p) in tree.predict_proba(row)? {
+                *probabilities.entry(label).or_insert(0.0) += p / self.trees.len() as f64;
+            }
+        }
+        Ok(probabilities)
+    }
+}
+
+///
+/// The settings for training a forest of extremely randomised trees.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtraTreesParams {
+    /// The number of trees.
+    pub trees: usize,
+    /// The settings each tree is grown with.
+    pub tree: TreeParams,
+    /// The number of columns to draw a split for at each node. None for the
+    /// square root of the number of columns.
+    pub max_features: Option<usize>,
+    /// The seed for the random splits, the same seed grows the same forest.
+    pub seed: u64,
+    /// Grow each tree on a bootstrap sample of the rows, so the forest can
+    /// be scored on the rows each tree didn't see.
+    #[serde(default)]
+    pub bootstrap: bool,
+}
+
+impl Default for ExtraTreesParams {
+    fn default() -> Self {
+        ExtraTreesParams {
+            trees: 100,
+            tree: TreeParams::default(),
+            max_features: None,
+            seed: 0,
+            bootstrap: false,
+        }
+    }
+}
+
+impl ExtraTreesParams {
+    /// The number of columns to try at each node for rows with this many columns.
+    fn features(&self, columns: usize) -> usize {
+        self.max_features
+            .unwrap_or_else(|| (columns as f64).sqrt() as usize)
+            .clamp(1, columns.max(1))
+    }
+
+    /// A generator for each tree, so the trees are the same however they are shared out between threads.
+    fn rngs<T>(&self, rows: &[&T]) -> Result<Vec<Rng>>
+    where
+        T: DataRow,
+    {
+        if self.trees == 0 {
+            return Err(DecisionsError::Config("A forest needs at least one tree".to_string()));
+        }
+        if rows.is_empty() {
+            return Err(DecisionsError::Config("A forest needs at least one row to train on".to_string()));
+        }
+        let mut rng = Rng::new(self.seed);
+        Ok((0..self.trees).map(|_| rng.split()).collect())
+    }
+
+    /// Grow one tree, on a bootstrap sample if we are bagging, and return it with the rows it didn't see.
+    fn grow<T>(&self, rows: &[&T], features: usize, rng: &mut Rng) -> Result<(Node, Vec<usize>)>
+    where
+        T: DataRow,
+    {
+        if !self.bootstrap {
+            return Ok((build_extra_tree(rows, &self.tree, features, rng)?, Vec::new()));
+        }
+        let mut counts = vec![0; rows.len()];
+        for _ in 0..rows.len() {
+            counts[rng.below(rows.len())] += 1;
+        }
+        let sample: Vec<&T> = rows
+            .iter()
+            .zip(counts.iter())
+            .flat_map(|(row, &count)| std::iter::repeat_n(*row, count))
+            .collect();
+        let unseen = (0..rows.len()).filter(|&idx| counts[idx] == 0).collect();
+        Ok((build_extra_tree(&sample, &self.tree, features, rng)?, unseen))
+    }
+
+    /// Put the trees together, keeping the rows they didn't see if they were bagged.
+    fn forest(&self, grown: Vec<(Node, Vec<usize>)>) -> Forest {
+        let (trees, out_of_bag): (Vec<Node>, Vec<Vec<usize>>) = grown.into_iter().unzip();
+        Forest {
+            trees,
+            out_of_bag: if self.bootstrap { out_of_bag } else { Vec::new() },
+        }
+    }
+
+    ///
+    /// Train the trees in parallel. This gives the same forest as `fit`,
+    /// which can't share the rows between threads unless they are `Sync`.
+    ///
+    pub fn fit_parallel<T>(&self, rows: &[&T]) -> Result<Forest>
+    where
+        T: DataRow + Sync,
+    {
+        let features = self.features(rows.first().map_or(0, |row| row.len()));
+        let grown = self
+            .rngs(rows)?
+            .into_par_iter()
+            .map(|mut rng| self.grow(rows, features, &mut rng))
+            .collect::<Result<Vec<(Node, Vec<usize>)>>>()?;
+        Ok(self.forest(grown))
+    }
+}
+
+impl Estimator for ExtraTreesParams {
+    type Model = Forest;
+
+    fn fit<T>(&self, rows: &[&T]) -> Result<Forest>
+    where
+        T: DataRow,
+    {
+        let features = self.features(rows.first().map_or(0, |row| row.len()));
+        let grown = self
+            .rngs(rows)?
+            .into_iter()
+            .map(|mut rng| self.grow(rows, features, &mut rng))
+            .collect::<Result<Vec<(Node, Vec<usize>)>>>()?;
+        Ok(self.forest(grown))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metrics::accuracy;
+    use row::test::{points, training_data, Fruit, Point};
+    use tree::build_tree;
+
+    fn same_forest(a: &Forest, b: &Forest) -> bool {
+        serde_json::to_value(a).unwrap() == serde_json::to_value(b).unwrap()
+    }
+
+    #[test]
+    fn test_repeatable() {
+        let data = points(100, 1);
+        let data: Vec<&Point> = data.iter().collect();
+        let params = ExtraTreesParams {
+            trees: 10,
+            ..ExtraTreesParams::default()
+        };
+
+        let forest = params.fit(&data).unwrap();
+        assert_eq!(10, forest.trees.len());
+        assert!(same_forest(&forest, &params.fit_parallel(&data).unwrap()));
+
+        let other = ExtraTreesParams { seed: 1, ..params.clone() };
+        assert!(!same_forest(&forest, &other.fit(&data).unwrap()));
+    }
+
+    #[test]
+    fn test_random_splits() {
+        let data = points(200, 2);
+        let data: Vec<&Point> = data.iter().collect();
+        let forest = ExtraTreesParams {
+            trees: 20,
+            max_features: Some(2),
+            ..ExtraTreesParams::default()
+        }
+        .fit(&data)
+        .unwrap();
+
+        // Grown all the way down the trees nearly fit the training rows, but the random thresholds make them differ.
+        let single = build_tree(&data, None).unwrap();
+        assert!(accuracy(&forest, &data).unwrap() > accuracy(&single, &data).unwrap() - 0.05);
+        assert!(forest.trees.iter().all(|tree| tree.leaf_count() >= single.leaf_count() / 2));
+        assert!(forest.trees.windows(2).any(|pair| pair[0].node_count() != pair[1].node_count()));
+
+        let probabilities = forest.predict_proba(data[0]).unwrap();
+        assert!((probabilities.values().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_text_columns() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let forest = ExtraTreesParams {
+            trees: 5,
+            max_features: Some(3),
+            ..ExtraTreesParams::default()
+        }
+        .fit(&data)
+        .unwrap();
+
+        assert_eq!(1.0, accuracy(&forest, &data).unwrap());
+        let grape = Fruit::new(6, "Red".to_string(), 1, "Grape".to_string());
+        assert_eq!("Grape", forest.predict(&grape).unwrap());
+    }
+
+    #[test]
+    fn test_out_of_bag() {
+        let data = points(200, 4);
+        let data: Vec<&Point> = data.iter().collect();
+        let params = ExtraTreesParams {
+            trees: 30,
+            max_features: Some(2),
+            bootstrap: true,
+            ..ExtraTreesParams::default()
+        };
+        let forest = params.fit(&data).unwrap();
+
+        // A bootstrap sample leaves out about a third of the rows.
+        assert_eq!(30, forest.out_of_bag.len());
+        assert!(forest.out_of_bag.iter().all(|unseen| unseen.len() > 50 && unseen.len() < 100));
+        assert!(same_forest(&forest, &params.fit_parallel(&data).unwrap()));
+
+        // With this many trees every row is left out by some of them.
+        let predictions = forest.oob_predict(&data).unwrap();
+        assert!(predictions.iter().all(Option::is_some));
+        let score = forest.oob_score(&data).unwrap();
+        assert_eq!(200, score.rows);
+        let correct = predictions
+            .iter()
+            .zip(data.iter())
+            .filter(|(predicted, row)| predicted.as_ref() == Some(&row.label))
+            .count();
+        assert_eq!(correct as f64 / 200.0, score.accuracy);
+
+        // Scoring rows the trees didn't see is worse than the training rows, but still does well.
+        assert!(score.accuracy < accuracy(&forest, &data).unwrap());
+        assert!(score.accuracy > 0.7);
+        assert!(score.brier > 0.0 && score.brier < 0.5);
+
+        // It has to be given the rows it was trained on.
+        assert!(forest.oob_score(&data[..100]).is_err());
+        let unbagged = ExtraTreesParams { bootstrap: false, ..params }.fit(&data).unwrap();
+        assert!(unbagged.out_of_bag.is_empty());
+        assert!(unbagged.oob_score(&data).is_err());
+    }
+
+    #[test]
+    fn test_oob_importance() {
+        let data = points(200, 5);
+        let data: Vec<&Point> = data.iter().collect();
+        let forest = ExtraTreesParams {
+            trees: 20,
+            max_features: Some(2),
+            bootstrap: true,
+            ..ExtraTreesParams::default()
+        }
+        .fit(&data)
+        .unwrap();
+
+        let importance = forest.oob_importance(&data, 1).unwrap();
+        assert_eq!(vec!["X", "Y"], importance.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>());
+        assert!(importance.iter().all(|(_, drop)| *drop > 0.05));
+        assert_eq!(importance, forest.oob_importance(&data, 1).unwrap());
+    }
+
+    #[test]
+    fn test_no_trees() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let params = ExtraTreesParams {
+            trees: 0,
+            ..ExtraTreesParams::default()
+        };
+        assert!(params.fit(&data).is_err());
+    }
+}
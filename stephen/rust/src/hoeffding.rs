@@ -0,0 +1,590 @@
+//!
+//! Hoeffding trees, or Very Fast Decision Trees (Domingos and Hulten, 2000),
+//! which learn from a stream of rows one at a time.
+//!
+//! Each leaf keeps running totals of what it has seen rather than the rows
+//! themselves: the weight of each label, a normal distribution of each
+//! numeric column for each label, and the weight of each label for each
+//! category of a text column. Every so often a leaf estimates how good the
+//! best split on each column would be from those totals. The Hoeffding bound
+//! says how far the best can be from the truth after that many rows, so once
+//! the best column is ahead of the next by more than that, it is almost
+//! certainly the one a tree grown on all the rows would have chosen.
+//!
+//! The memory used depends on the number of leaves, columns, labels and
+//! categories, but not on the number of rows, so a stream of any length can
+//! be learnt from. `snapshot` turns the tree into a regular `Node`.
+//!
+
+use error::Result;
+use model::{Estimator, Model};
+use question::{Predicate, Question};
+use row::{Col, DataRow};
+use std::collections::{BTreeMap, HashMap};
+use tree::{entropy, gini, Criterion, Node};
+
+///
+/// The settings that control how a Hoeffding tree grows.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoeffdingParams {
+    /// The number of rows a leaf sees between each time it tries to split.
+    pub grace_period: usize,
+    /// The chance we are willing to take of choosing a different split from
+    /// the one all of the rows would give. The delta of the Hoeffding bound.
+    pub split_confidence: f64,
+    /// Split anyway once the bound is smaller than this, as the best columns
+    /// are then so close that it doesn't matter which we choose.
+    pub tie_threshold: f64,
+    /// The maximum depth of the tree. None if it can keep growing.
+    pub max_depth: Option<u64>,
+    pub criterion: Criterion,
+    /// The number of thresholds tried for each numeric column, spread evenly
+    /// between the smallest and largest values the leaf has seen.
+    pub thresholds: usize,
+}
+
+impl Default for HoeffdingParams {
+    fn default() -> Self {
+        HoeffdingParams {
+            grace_period: 200,
+            split_confidence: 1e-7,
+            tie_threshold: 0.05,
+            max_depth: None,
+            criterion: Criterion::Entropy,
+            thresholds: 10,
+        }
+    }
+}
+
+///
+/// The weighted mean and variance of a column's values for one label,
+/// updated one value at a time (West, 1979).
+///
+#[derive(Debug, Clone)]
+struct Gaussian {
+    weight: f64,
+    mean: f64,
+    squares: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Gaussian {
+    fn default() -> Self {
+        Gaussian {
+            weight: 0.0,
+            mean: 0.0,
+            squares: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Gaussian {
+    fn add(&mut self, value: f64, weight: f64) {
+        self.weight += weight;
+        let delta = value - self.mean;
+        self.mean += delta * weight / self.weight;
+        self.squares += weight * delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// The weight of the values that are estimated to be at least the threshold.
+    fn at_least(&self, threshold: f64) -> f64 {
+        if threshold <= self.min {
+            return self.weight;
+        }
+        if threshold > self.max {
+            return 0.0;
+        }
+        let std = (self.squares / self.weight).sqrt();
+        if std <= 0.0 {
+            return if self.mean >= threshold { self.weight } else { 0.0 };
+        }
+        self.weight * 0.5 * (1.0 - erf((threshold - self.mean) / (std * ::std::f64::consts::SQRT_2)))
+    }
+}
+
+/// The error function, to within 1.5e-7 (Abramowitz and Stegun, 7.1.26).
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 {
+        -y
+    } else {
+        y
+    }
+}
+
+/// What a leaf has seen of one column.
+#[derive(Debug, Clone, Default)]
+struct Column {
+    /// The distribution of the numbers for each label.
+    numbers: BTreeMap<String, Gaussian>,
+    /// The weight of each label for each category.
+    categories: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl Column {
+    fn add(&mut self, value: Col, label: &str, weight: f64) {
+        match value {
+            Col::Text(category) => {
+                *self
+                    .categories
+                    .entry(category)
+                    .or_default()
+                    .entry(label.to_string())
+                    .or_insert(0.0) += weight
+            }
+            value => {
+                if let Some(number) = value.number().filter(|number| !number.is_nan()) {
+                    self.numbers.entry(label.to_string()).or_default().add(number, weight);
+                }
+            }
+        }
+    }
+
+    ///
+    /// The questions worth asking of the column, each with the estimated weight of
+    /// each label that would go down the true branch. Missing values go down the false one.
+    ///
+    fn candidates(&self, thresholds: usize) -> Vec<(Predicate, BTreeMap<String, f64>)> {
+        if !self.categories.is_empty() {
+            return self
+                .categories
+                .iter()
+                .map(|(category, counts)| (Predicate::Equal(Col::Text(category.clone())), counts.clone()))
+                .collect();
+        }
+
+        let low = self.numbers.values().map(|g| g.min).fold(f64::INFINITY, f64::min);
+        let high = self.numbers.values().map(|g| g.max).fold(f64::NEG_INFINITY, f64::max);
+        if low >= high {
+            return Vec::new();
+        }
+        (1..=thresholds)
+            .map(|step| {
+                let threshold = low + (high - low) * step as f64 / (thresholds + 1) as f64;
+                let counts = self
+                    .numbers
+                    .iter()
+                    .map(|(label, gaussian)| (label.clone(), gaussian.at_least(threshold)))
+                    .collect();
+                (Predicate::AtLeast(Col::Float(threshold)), counts)
+            })
+            .collect()
+    }
+}
+
+/// The running totals kept by a leaf.
+#[derive(Debug, Clone, Default)]
+struct Leaf {
+    /// The estimated weight of each label from the split that made the leaf.
+    prior: BTreeMap<String, f64>,
+    /// The weight of each label in the rows the leaf has seen itself.
+    counts: BTreeMap<String, f64>,
+    columns: Vec<Column>,
+    /// The number of rows since the leaf last tried to split.
+    since_split: usize,
+}
+
+#[derive(Debug, Clone)]
+enum HoeffdingNode {
+    Leaf(Leaf),
+    Decision {
+        question: Question,
+        true_branch: Box<HoeffdingNode>,
+        false_branch: Box<HoeffdingNode>,
+    },
+}
+
+/// A split a leaf could make and how good it is.
+struct Candidate {
+    gain: f64,
+    col: usize,
+    predicate: Predicate,
+    true_counts: BTreeMap<String, f64>,
+}
+
+impl Leaf {
+    fn with_prior(prior: BTreeMap<String, f64>) -> Self {
+        Leaf {
+            prior,
+            ..Leaf::default()
+        }
+    }
+
+    /// The weight of each label the leaf predicts, what it has seen on top of what it started with.
+    fn totals(&self) -> BTreeMap<String, f64> {
+        let mut totals = self.prior.clone();
+        for (label, count) in &self.counts {
+            *totals.entry(label.clone()).or_insert(0.0) += count;
+        }
+        totals
+    }
+
+    fn add<T>(&mut self, row: &T) -> Result<()>
+    where
+        T: DataRow,
+    {
+        let label = row.label()?;
+        let weight = row.weight();
+        if self.columns.is_empty() {
+            self.columns = vec![Column::default(); row.len()];
+        }
+        for (col, column) in self.columns.iter_mut().enumerate() {
+            column.add(row.value(col)?, &label, weight);
+        }
+        *self.counts.entry(label).or_insert(0.0) += weight;
+        self.since_split += 1;
+        Ok(())
+    }
+
+    fn impurity(&self, criterion: Criterion, counts: &BTreeMap<String, f64>) -> (f64, f64) {
+        let len = counts.values().sum();
+        match criterion {
+            Criterion::Gini => (len, gini(len, counts)),
+            Criterion::Entropy => (len, entropy(len, counts)),
+        }
+    }
+
+    /// The best split on each column, from the best to the worst, judged on the rows the leaf has seen.
+    fn candidates(&self, params: &HoeffdingParams) -> Vec<Candidate> {
+        let (total, current) = self.impurity(params.criterion, &self.counts);
+
+        let mut best: Vec<Candidate> = Vec::new();
+        for (col, column) in self.columns.iter().enumerate() {
+            let mut column_best: Option<Candidate> = None;
+            for (predicate, true_counts) in column.candidates(params.thresholds) {
+                let false_counts: BTreeMap<String, f64> = self
+                    .counts
+                    .iter()
+                    .map(|(label, count)| (label.clone(), (count - true_counts.get(label).unwrap_or(&0.0)).max(0.0)))
+                    .collect();
+                let (true_len, true_impurity) = self.impurity(params.criterion, &true_counts);
+                let (false_len, false_impurity) = self.impurity(params.criterion, &false_counts);
+                if true_len <= 0.0 || false_len <= 0.0 {
+                    continue;
+                }
+                let gain = current - (true_len * true_impurity + false_len * false_impurity) / total;
+                if column_best.as_ref().is_none_or(|best| gain > best.gain) {
+                    column_best = Some(Candidate {
+                        gain,
+                        col,
+                        predicate,
+                        true_counts,
+                    });
+                }
+            }
+            best.extend(column_best);
+        }
+        best.sort_by(|a, b| b.gain.partial_cmp(&a.gain).unwrap_or(::std::cmp::Ordering::Equal));
+        best
+    }
+}
+
+///
+/// The Hoeffding bound, how far the mean of `n` observations of something
+/// with this range can be from the true mean with probability `1 - delta`.
+///
+pub fn hoeffding_bound(range: f64, delta: f64, n: f64) -> f64 {
+    (range * range * (1.0 / delta).ln() / (2.0 * n)).sqrt()
+}
+
+///
+/// A tree that learns from rows one at a time.
+///
+#[derive(Debug, Clone)]
+pub struct HoeffdingTree {
+    params: HoeffdingParams,
+    root: HoeffdingNode,
+    rows: u64,
+}
+
+impl HoeffdingTree {
+    pub fn new(params: HoeffdingParams) -> Self {
+        HoeffdingTree {
+            params,
+            root: HoeffdingNode::Leaf(Leaf::default()),
+            rows: 0,
+        }
+    }
+
+    /// The number of rows the tree has learnt from.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Learn from another row, splitting its leaf if it has seen enough to be sure of the best split.
+    pub fn learn<T>(&mut self, row: &T) -> Result<()>
+    where
+        T: DataRow,
+    {
+        self.rows += 1;
+        let params = &self.params;
+        let mut node = &mut self.root;
+        let mut depth = 1;
+        loop {
+            match node {
+                HoeffdingNode::Decision {
+                    question,
+                    true_branch,
+                    false_branch,
+                } => {
+                    node = if question.matchit(row)? { true_branch } else { false_branch };
+                    depth += 1;
+                }
+                HoeffdingNode::Leaf(leaf) => {
+                    leaf.add(row)?;
+                    if leaf.since_split < params.grace_period || params.max_depth.is_some_and(|max| depth >= max) {
+                        return Ok(());
+                    }
+                    leaf.since_split = 0;
+                    match try_split(leaf, params, row)? {
+                        Some(split) => *node = split,
+                        None => return Ok(()),
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    ///
+    /// The tree as it stands, as a regular tree. Each leaf predicts the weight
+    /// of each label it has seen, or that its parent had if it hasn't seen any.
+    ///
+    pub fn snapshot(&self) -> Node {
+        snapshot(&self.root, &BTreeMap::new())
+    }
+}
+
+/// Split the leaf if the best split is clearly better than the next.
+fn try_split<T>(leaf: &Leaf, params: &HoeffdingParams, row: &T) -> Result<Option<HoeffdingNode>>
+where
+    T: DataRow,
+{
+    if leaf.counts.values().filter(|&&count| count > 0.0).count() < 2 {
+        return Ok(None);
+    }
+    let candidates = leaf.candidates(params);
+    let best = match candidates.first() {
+        Some(best) if best.gain > 0.0 => best,
+        _ => return Ok(None),
+    };
+    // Not splitting at all has no gain, so the best always has to beat that.
+    let second = candidates.get(1).map_or(0.0, |second| second.gain.max(0.0));
+    let range = match params.criterion {
+        Criterion::Gini => 1.0,
+        Criterion::Entropy => (leaf.counts.len() as f64).log2().max(1.0),
+    };
+    let bound = hoeffding_bound(range, params.split_confidence, leaf.counts.values().sum());
+    if best.gain - second <= bound && bound >= params.tie_threshold {
+        return Ok(None);
+    }
+
+    let false_counts = leaf
+        .counts
+        .iter()
+        .map(|(label, count)| (label.clone(), (count - best.true_counts.get(label).unwrap_or(&0.0)).max(0.0)))
+        .collect();
+    Ok(Some(HoeffdingNode::Decision {
+        question: Question::with_predicate(row.name(best.col)?, best.col, best.predicate.clone()),
+        true_branch: Box::new(HoeffdingNode::Leaf(Leaf::with_prior(best.true_counts.clone()))),
+        false_branch: Box::new(HoeffdingNode::Leaf(Leaf::with_prior(false_counts))),
+    }))
+}
+
+fn snapshot(node: &HoeffdingNode, parent: &BTreeMap<String, f64>) -> Node {
+    match node {
+        HoeffdingNode::Leaf(leaf) => {
+            let totals = leaf.totals();
+            let counts = if totals.values().any(|&count| count > 0.0) { totals } else { parent.clone() };
+            Node::Leaf {
+                predictions: counts.into_iter().collect(),
+            }
+        }
+        HoeffdingNode::Decision {
+            question,
+            true_branch,
+            false_branch,
+        } => {
+            let counts = counts(node);
+            Node::new_decision(question.clone(), snapshot(true_branch, &counts), snapshot(false_branch, &counts))
+        }
+    }
+}
+
+/// The weight of each label in the leaves under the node.
+fn counts(node: &HoeffdingNode) -> BTreeMap<String, f64> {
+    match node {
+        HoeffdingNode::Leaf(leaf) => leaf.totals(),
+        HoeffdingNode::Decision {
+            true_branch,
+            false_branch,
+            ..
+        } => {
+            let mut total = counts(true_branch);
+            for (label, count) in counts(false_branch) {
+                *total.entry(label).or_insert(0.0) += count;
+            }
+            total
+        }
+    }
+}
+
+impl Model for HoeffdingTree {
+    /// The share of each label in the row's leaf, so the tree can be scored on each row before it learns from it.
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        let mut node = &self.root;
+        let mut parent: Option<&HoeffdingNode> = None;
+        loop {
+            match node {
+                HoeffdingNode::Decision {
+                    question,
+                    true_branch,
+                    false_branch,
+                } => {
+                    parent = Some(node);
+                    node = if question.matchit(row)? { true_branch } else { false_branch };
+                }
+                HoeffdingNode::Leaf(leaf) => {
+                    let totals = leaf.totals();
+                    let counts = match parent {
+                        Some(parent) if totals.values().all(|&count| count <= 0.0) => counts(parent),
+                        _ => totals,
+                    };
+                    let total: f64 = counts.values().sum();
+                    if total <= 0.0 {
+                        return Ok(HashMap::new());
+                    }
+                    return Ok(counts.into_iter().map(|(label, count)| (label, count / total)).collect());
+                }
+            }
+        }
+    }
+}
+
+impl Estimator for HoeffdingParams {
+    type Model = Node;
+
+    /// Learn from the rows one at a time, and return the snapshot of the tree.
+    fn fit<T>(&self, rows: &[&T]) -> Result<Node>
+    where
+        T: DataRow,
+    {
+        let mut tree = HoeffdingTree::new(self.clone());
+        for row in rows {
+            tree.learn(*row)?;
+        }
+        Ok(tree.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metrics::accuracy;
+    use row::test::{points, Fruit, Point};
+
+    #[test]
+    fn test_gaussian() {
+        let mut gaussian = Gaussian::default();
+        for value in &[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            gaussian.add(*value, 1.0);
+        }
+        assert_eq!(5.0, gaussian.mean);
+        assert_eq!(4.0, gaussian.squares / gaussian.weight);
+        assert_eq!(8.0, gaussian.at_least(2.0));
+        assert_eq!(0.0, gaussian.at_least(9.5));
+        assert!((gaussian.at_least(5.0) - 4.0).abs() < 1e-6);
+        // One standard deviation above the mean leaves about 16% above it.
+        assert!((gaussian.at_least(7.0) - 8.0 * 0.158_655).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_hoeffding_bound() {
+        assert!((hoeffding_bound(1.0, 1e-7, 200.0) - 0.2007).abs() < 1e-4);
+        assert!(hoeffding_bound(1.0, 1e-7, 2000.0) < hoeffding_bound(1.0, 1e-7, 200.0));
+    }
+
+    #[test]
+    fn test_learns_from_stream() {
+        let data = points(21000, 1);
+        let mut tree = HoeffdingTree::new(HoeffdingParams::default());
+
+        // Score each row before learning from it.
+        let mut correct = 0;
+        for row in &data[..20000] {
+            if tree.rows() > 0 && tree.predict(row).unwrap() == row.label {
+                correct += 1;
+            }
+            tree.learn(row).unwrap();
+        }
+        assert_eq!(20000, tree.rows());
+        assert!(correct as f64 / 20000.0 > 0.7);
+
+        let snapshot = tree.snapshot();
+        assert!(snapshot.node_count() > 3);
+        let test: Vec<&Point> = data[20000..].iter().collect();
+        assert!(accuracy(&snapshot, &test).unwrap() > 0.75);
+        for row in &test {
+            assert_eq!(tree.predict(*row).unwrap(), snapshot.predict(*row).unwrap());
+        }
+        assert!(snapshot.to_dot().contains(">="));
+    }
+
+    #[test]
+    fn test_waits_for_grace_period() {
+        let data = points(5000, 2);
+        let data: Vec<&Point> = data.iter().collect();
+        let params = HoeffdingParams {
+            grace_period: 6000,
+            ..HoeffdingParams::default()
+        };
+        assert_eq!(1, params.fit(&data).unwrap().node_count());
+
+        let stump = HoeffdingParams {
+            max_depth: Some(2),
+            ..HoeffdingParams::default()
+        }
+        .fit(&data)
+        .unwrap();
+        assert_eq!(2, stump.depth());
+    }
+
+    #[test]
+    fn test_categories() {
+        let fruit: Vec<Fruit> = (0..3000)
+            .map(|id| {
+                let (colour, name) = match id % 3 {
+                    0 => ("Red", "Apple"),
+                    1 => ("Green", "Grape"),
+                    _ => ("Yellow", "Lemon"),
+                };
+                Fruit::new(id, colour.to_string(), 1, name.to_string())
+            })
+            .collect();
+        let fruit: Vec<&Fruit> = fruit.iter().collect();
+
+        // The colour and the fruit column are as good as each other, so it has to split on a tie.
+        let tree = HoeffdingParams {
+            tie_threshold: 0.2,
+            ..HoeffdingParams::default()
+        }
+        .fit(&fruit)
+        .unwrap();
+        assert_eq!(1.0, accuracy(&tree, &fruit).unwrap());
+        match tree {
+            Node::Decision { ref question, .. } => assert!(matches!(question.predicate(), Predicate::Equal(Col::Text(_)))),
+            _ => panic!("Expected a decision"),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//!
+//! Isolation forests (Liu, Ting and Zhou, 2008), for finding the rows that
+//! aren't like the others.
+//!
+//! Each tree splits a small random sample of the rows on random columns at
+//! random thresholds, or random groups of categories for text columns, until
+//! every row is on its own. Rows that are unusual are isolated after only a
+//! few splits, so the shorter the average path down to a row's leaf the more
+//! of an anomaly it is. The labels aren't used.
+//!
+
+use dot::{self, DotNode};
+use error::{DecisionsError, Result};
+use question::Question;
+use random::Rng;
+use rayon::prelude::*;
+use row::DataRow;
+use tree::{column_values, partition, random_predicate};
+
+/// The Euler-Mascheroni constant.
+const EULER: f64 = 0.577_215_664_901_532_9;
+
+///
+/// The average length of a path down to a leaf in a random binary tree of
+/// this many rows, which is the same as an unsuccessful search of a binary
+/// search tree. It is used to normalise path lengths.
+///
+pub fn average_path(rows: usize) -> f64 {
+    match rows {
+        0 | 1 => 0.0,
+        2 => 1.0,
+        _ => {
+            let n = rows as f64;
+            2.0 * ((n - 1.0).ln() + EULER) - 2.0 * (n - 1.0) / n
+        }
+    }
+}
+
+/// A node in an isolation tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum IsolationNode {
+    /// The number of sampled rows that weren't split any further.
+    Leaf { rows: usize },
+    Decision {
+        question: Question,
+        true_branch: Box<IsolationNode>,
+        false_branch: Box<IsolationNode>,
+    },
+}
+
+impl IsolationNode {
+    /// The number of nodes on the longest path from here down to a leaf.
+    pub fn depth(&self) -> usize {
+        match self {
+            IsolationNode::Leaf { .. } => 1,
+            IsolationNode::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } => 1 + true_branch.depth().max(false_branch.depth()),
+        }
+    }
+
+    ///
+    /// The number of splits it takes to get down to the row's leaf, plus the
+    /// average path for the rows that were left in the leaf when we stopped.
+    ///
+    pub fn path_length<T>(&self, row: &T) -> Result<f64>
+    where
+        T: DataRow,
+    {
+        match self {
+            IsolationNode::Leaf { rows } => Ok(average_path(*rows)),
+            IsolationNode::Decision {
+                question,
+                true_branch,
+                false_branch,
+            } => {
+                let branch = if question.matchit(row)? { true_branch } else { false_branch };
+                Ok(1.0 + branch.path_length(row)?)
+            }
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        dot::to_dot(self)
+    }
+}
+
+impl DotNode for IsolationNode {
+    fn label(&self) -> String {
+        match self {
+            IsolationNode::Leaf { rows } => format!("{} rows", rows),
+            IsolationNode::Decision { question, .. } => question.to_string(),
+        }
+    }
+
+    fn children(&self) -> Vec<(String, &IsolationNode)> {
+        match self {
+            IsolationNode::Leaf { .. } => Vec::new(),
+            IsolationNode::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } => vec![("yes".to_string(), &**true_branch), ("no".to_string(), &**false_branch)],
+        }
+    }
+}
+
+/// Split the rows at random until each is on its own or we reach the maximum depth.
+fn isolate<T>(rows: &[&T], depth: usize, rng: &mut Rng) -> Result<IsolationNode>
+where
+    T: DataRow,
+{
+    if depth == 0 || rows.len() <= 1 {
+        return Ok(IsolationNode::Leaf { rows: rows.len() });
+    }
+
+    // Try the columns in a random order until one of them has more than one value.
+    let mut columns: Vec<usize> = (0..rows[0].len()).collect();
+    rng.shuffle(&mut columns);
+    for col in columns {
+        if let Some(predicate) = random_predicate(&column_values(rows.iter(), col)?, rng) {
+            let question = Question::with_predicate(rows[0].name(col)?, col, predicate);
+            let (true_rows, false_rows) = partition(rows, &question)?;
+            return Ok(IsolationNode::Decision {
+                question,
+                true_branch: Box::new(isolate(&true_rows, depth - 1, rng)?),
+                false_branch: Box::new(isolate(&false_rows, depth - 1, rng)?),
+            });
+        }
+    }
+
+    // The rows are all the same, so there is nothing to split them on.
+    Ok(IsolationNode::Leaf { rows: rows.len() })
+}
+
+///
+/// The settings for growing an isolation forest.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IsolationParams {
+    /// The number of trees.
+    pub trees: usize,
+    /// The number of rows sampled, without replacement, for each tree.
+    pub sample_size: usize,
+    /// The maximum number of splits down to a leaf. None for the average
+    /// depth of a balanced tree on the sample, past which the rows are
+    /// normal enough that it doesn't matter how much further they go.
+    pub max_depth: Option<usize>,
+    /// The seed for the samples and splits, the same seed grows the same forest.
+    pub seed: u64,
+}
+
+impl Default for IsolationParams {
+    fn default() -> Self {
+        IsolationParams {
+            trees: 100,
+            sample_size: 256,
+            max_depth: None,
+            seed: 0,
+        }
+    }
+}
+
+impl IsolationParams {
+    ///
+    /// Grow the trees in parallel, each on its own sample of the rows.
+    /// The rows aren't changed, so the same rows can then be scored.
+    ///
+    pub fn fit<T>(&self, rows: &[&T]) -> Result<IsolationForest>
+    where
+        T: DataRow + Sync,
+    {
+        if self.trees == 0 || self.sample_size < 2 {
+            return Err(DecisionsError::Config(
+                "An isolation forest needs at least one tree and samples of at least two rows".to_string(),
+            ));
+        }
+        if rows.is_empty() {
+            return Err(DecisionsError::Config("An isolation forest needs some rows to sample".to_string()));
+        }
+
+        let sample_size = self.sample_size.min(rows.len());
+        let max_depth = self
+            .max_depth
+            .unwrap_or_else(|| (sample_size as f64).log2().ceil() as usize);
+        let mut rng = Rng::new(self.seed);
+        let rngs: Vec<Rng> = (0..self.trees).map(|_| rng.split()).collect();
+
+        let trees = rngs
+            .into_par_iter()
+            .map(|mut rng| {
+                let mut sample: Vec<&T> = rows.to_vec();
+                rng.shuffle(&mut sample);
+                sample.truncate(sample_size);
+                isolate(&sample, max_depth, &mut rng)
+            })
+            .collect::<Result<Vec<IsolationNode>>>()?;
+
+        Ok(IsolationForest { trees, sample_size })
+    }
+}
+
+/// The trees of an isolation forest, and the size of the samples they were grown on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IsolationForest {
+    pub trees: Vec<IsolationNode>,
+    pub sample_size: usize,
+}
+
+impl IsolationForest {
+    /// The average path length down to the row's leaf over the trees.
+    pub fn path_length<T>(&self, row: &T) -> Result<f64>
+    where
+        T: DataRow,
+    {
+        let mut total = 0.0;
+        for tree in &self.trees {
+            total += tree.path_length(row)?;
+        }
+        Ok(total / self.trees.len() as f64)
+    }
+
+    ///
+    /// The anomaly score of the row, between 0 and 1. Rows scoring close to 1
+    /// are anomalies, and if every row scores around 0.5 or less there aren't
+    /// any clear ones.
+    ///
+    pub fn score<T>(&self, row: &T) -> Result<f64>
+    where
+        T: DataRow,
+    {
+        Ok(2f64.powf(-self.path_length(row)? / average_path(self.sample_size)))
+    }
+
+    /// The score of each of the rows.
+    pub fn scores<T>(&self, rows: &[&T]) -> Result<Vec<f64>>
+    where
+        T: DataRow + Sync,
+    {
+        rows.par_iter().map(|row| self.score(*row)).collect()
+    }
+}
+
+///
+/// The positions of the `k` highest scores, highest first. Ties go to the
+/// earlier row.
+///
+pub fn top_k(scores: &[f64], k: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap_or(::std::cmp::Ordering::Equal));
+    order.truncate(k);
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{points, Fruit, Point};
+
+    #[test]
+    fn test_average_path() {
+        assert_eq!(0.0, average_path(1));
+        assert_eq!(1.0, average_path(2));
+        // 2 * H(2) - 4 / 3, with H(2) = 1.5 approximated by ln(2) + the Euler-Mascheroni constant.
+        assert!((average_path(3) - (2.0 * (2f64.ln() + EULER) - 4.0 / 3.0)).abs() < 1e-12);
+        assert!((average_path(256) - 10.24).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_outlier() {
+        let mut data = points(300, 1);
+        data.push(Point {
+            id: 300,
+            x: 40,
+            y: -25.0,
+            label: "No".to_string(),
+        });
+        let data: Vec<&Point> = data.iter().collect();
+
+        let params = IsolationParams::default();
+        let forest = params.fit(&data).unwrap();
+        assert_eq!(100, forest.trees.len());
+        assert!(forest.trees.iter().all(|tree| tree.depth() <= 9));
+
+        let scores = forest.scores(&data).unwrap();
+        assert!(scores.iter().all(|score| *score > 0.0 && *score <= 1.0));
+        assert_eq!(vec![300], top_k(&scores, 1));
+        assert!(scores[300] > 0.7);
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        assert!(mean < 0.5);
+
+        // The same seed grows the same trees however they are shared out between threads.
+        assert_eq!(scores, params.fit(&data).unwrap().scores(&data).unwrap());
+    }
+
+    #[test]
+    fn test_rare_category() {
+        let mut fruit: Vec<Fruit> = (0..100)
+            .map(|id| {
+                let colour = if id % 2 == 0 { "Red" } else { "Green" };
+                Fruit::new(id, colour.to_string(), 1 + id % 3, "Apple".to_string())
+            })
+            .collect();
+        fruit.push(Fruit::new(100, "Purple".to_string(), 2, "Apple".to_string()));
+        let fruit: Vec<&Fruit> = fruit.iter().collect();
+
+        let forest = IsolationParams {
+            trees: 50,
+            sample_size: 64,
+            ..IsolationParams::default()
+        }
+        .fit(&fruit)
+        .unwrap();
+        let scores = forest.scores(&fruit).unwrap();
+        assert_eq!(vec![100], top_k(&scores, 1));
+    }
+
+    #[test]
+    fn test_top_k() {
+        assert_eq!(vec![2, 0], top_k(&[0.5, 0.2, 0.9, 0.5], 2));
+        assert_eq!(vec![2, 0, 3, 1], top_k(&[0.5, 0.2, 0.9, 0.5], 10));
+    }
+}
@@ -0,0 +1,108 @@
+//!
+//! Decision trees for any data that implements `DataRow`.
+//!
+//! ```
+//! use decisions::{build_tree, classify, Col, DataRow, Result};
+//! use decisions::row::no_column;
+//!
+//! struct Fruit {
+//!     colour: &'static str,
+//!     diameter: i64,
+//!     name: &'static str,
+//! }
+//!
+//! impl DataRow for Fruit {
+//!     fn id(&self) -> i64 {
+//!         0
+//!     }
+//!     fn name(&self, col: usize) -> Result<String> {
+//!         match col {
+//!             0 => Ok("Colour".to_string()),
+//!             1 => Ok("Diameter".to_string()),
+//!             _ => Err(no_column(self, col)),
+//!         }
+//!     }
+//!     fn value(&self, col: usize) -> Result<Col> {
+//!         match col {
+//!             0 => Ok(Col::Text(self.colour.to_string())),
+//!             1 => Ok(Col::Int(self.diameter)),
+//!             _ => Err(no_column(self, col)),
+//!         }
+//!     }
+//!     fn label(&self) -> Result<String> {
+//!         Ok(self.name.to_string())
+//!     }
+//!     fn len(&self) -> usize {
+//!         2
+//!     }
+//! }
+//!
+//! let fruit = vec![
+//!     Fruit { colour: "Green", diameter: 3, name: "Apple" },
+//!     Fruit { colour: "Red", diameter: 1, name: "Grape" },
+//!     Fruit { colour: "Yellow", diameter: 3, name: "Lemon" },
+//! ];
+//! let rows: Vec<&Fruit> = fruit.iter().collect();
+//!
+//! let tree = build_tree(&rows, None)?;
+//! let grape = Fruit { colour: "Red", diameter: 1, name: "" };
+//! assert_eq!("Grape", classify(&grape, &tree)?);
+//! # Ok::<(), decisions::DecisionsError>(())
+//! ```
+//!
+//! # Stability
+//!
+//! The items exported from the root of the crate are the stable API and
+//! follow semantic versioning: they won't change in a breaking way without
+//! a new major version (or minor version while we are on 0.x). The modules
+//! are public so the exporters, importers and transforms can be used, but
+//! their contents may change between minor versions.
+//!
+
+#[macro_use]
+extern crate serde_derive;
+extern crate csv;
+extern crate prost;
+extern crate rayon;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+#[cfg(test)]
+extern crate quick_xml;
+
+pub mod calibration;
+pub mod cost;
+pub mod cross_validation;
+pub mod dot;
+pub mod error;
+pub mod forest;
+pub mod hoeffding;
+pub mod isolation;
+pub mod metrics;
+pub mod multioutput;
+pub mod multiway;
+pub mod model;
+pub mod monotonic;
+pub mod onnx;
+pub mod optimise;
+pub mod pmml;
+pub mod question;
+pub mod random;
+pub mod report;
+pub mod row;
+pub mod sampling;
+pub mod saved;
+pub mod schema;
+pub mod sklearn;
+pub mod titanic;
+pub mod transform;
+pub mod tree;
+pub mod tuning;
+
+pub use cross_validation::{cross_validate, validate};
+pub use error::{DecisionsError, Result};
+pub use model::{Estimator, Model};
+pub use question::{Predicate, Question};
+pub use row::{Col, DataRow, Weighted};
+pub use tree::{build_tree, build_tree_with, classify, ClassWeight, Criterion, Node, TreeParams};
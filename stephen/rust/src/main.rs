@@ -1,126 +1,1267 @@
 #[macro_use]
-extern crate serde_derive;
-extern crate serde;
-extern crate csv;
 extern crate clap;
-extern crate rayon;
-extern crate itertools;
+extern crate decisions;
+extern crate serde_json;
 
-use std::fs::File;
-use std::io::Write;
-use clap::{App, Arg};
-use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use serde_json::Value;
 
-mod row;
-mod tree;
-mod question;
-mod titanic;
-mod cross_validation;
-
-use row::DataRow;
+use decisions::{calibration, cross_validation, metrics, onnx, optimise, pmml, schema, sklearn, titanic, transform, tuning};
+use decisions::cost::{CostMatrix, MinimumCost};
+use decisions::error::{create, open};
+use decisions::forest::ExtraTreesParams;
+use decisions::hoeffding::{HoeffdingParams, HoeffdingTree};
+use decisions::isolation::{self, IsolationParams};
+use decisions::monotonic::{self, Monotonic};
+use decisions::report::{Format, Report};
+use decisions::sampling::{self, Sampling};
+use decisions::saved::{Learner, SavedModel};
+use decisions::titanic::{Tickets, Titanic};
+use decisions::transform::{Pipeline, Transformed};
+use decisions::{DataRow, DecisionsError, Estimator, Model, Result, TreeParams};
 
 fn main() {
-    // Setup args
-    let matches = App::new("decisions")
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn train_arg() -> Arg<'static, 'static> {
+    Arg::with_name("training")
+        .short("t")
+        .long("train")
+        .help("Training file")
+        .required(true)
+        .takes_value(true)
+}
+
+fn transform_arg() -> Arg<'static, 'static> {
+    Arg::with_name("transform")
+        .long("transform")
+        .help("Feature transform pipeline config to fit on the training file")
+        .takes_value(true)
+}
+
+fn depth_arg() -> Arg<'static, 'static> {
+    Arg::with_name("depth")
+        .long("depth")
+        .help("Maximum depth of the tree, as deep as it will go if not given")
+        .takes_value(true)
+}
+
+fn class_weight_arg() -> Arg<'static, 'static> {
+    Arg::with_name("class_weight")
+        .long("class-weight")
+        .help("How much each class counts: uniform, balanced or a weight for each like Lived=2,Died=1")
+        .takes_value(true)
+}
+
+fn costs_arg() -> Arg<'static, 'static> {
+    Arg::with_name("costs")
+        .long("costs")
+        .help("Cost matrix of each mistake, as csv or toml")
+        .takes_value(true)
+}
+
+fn resample_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("resample")
+            .long("resample")
+            .help("Resample the training rows so the classes are closer to the same size")
+            .possible_values(sampling::Method::NAMES)
+            .takes_value(true),
+        Arg::with_name("resample_ratio")
+            .long("resample-ratio")
+            .help("How big the smaller classes should end up compared to the biggest")
+            .default_value("1")
+            .takes_value(true),
+        Arg::with_name("resample_seed")
+            .long("resample-seed")
+            .help("Seed for the random resampling")
+            .default_value("0")
+            .takes_value(true),
+    ]
+}
+
+fn extra_trees_arg() -> Arg<'static, 'static> {
+    Arg::with_name("extra_trees")
+        .long("extra-trees")
+        .help("Train this many extremely randomised trees and average them, instead of one tree")
+        .takes_value(true)
+}
+
+fn forest_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("max_features")
+            .long("max-features")
+            .help("Number of columns each node of the extra trees draws a random split for, \
+                   the square root of the number of columns if not given")
+            .requires("extra_trees")
+            .takes_value(true),
+        // No default value, clap would count it as given and always ask for --extra-trees.
+        Arg::with_name("seed")
+            .long("seed")
+            .help("Seed for the random splits of the extra trees, 0 if not given")
+            .requires("extra_trees")
+            .takes_value(true),
+    ]
+}
+
+fn bootstrap_arg() -> Arg<'static, 'static> {
+    Arg::with_name("bootstrap")
+        .long("bootstrap")
+        .help("Grow each of the extra trees on a bootstrap sample of the rows")
+        .requires("extra_trees")
+}
+
+fn monotonic_arg() -> Arg<'static, 'static> {
+    Arg::with_name("monotonic")
+        .long("monotonic")
+        .help("Fields the chance of living can only go up or only go down with, like Fare=increasing,Age=decreasing")
+        .takes_value(true)
+}
+
+fn folds_arg() -> Arg<'static, 'static> {
+    Arg::with_name("folds")
+        .long("folds")
+        .help("Number of cross validation folds")
+        .default_value("10")
+        .takes_value(true)
+}
+
+fn model_arg() -> Arg<'static, 'static> {
+    Arg::with_name("model")
+        .short("m")
+        .long("model")
+        .help("Saved model file")
+        .required(true)
+        .takes_value(true)
+}
+
+fn test_arg() -> Arg<'static, 'static> {
+    Arg::with_name("test")
+        .short("s")
+        .long("test")
+        .help("Testing file")
+        .required(true)
+        .takes_value(true)
+}
+
+fn format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .help("Output format")
+        .possible_values(Format::NAMES)
+        .default_value("table")
+        .takes_value(true)
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("decisions")
         .version("1.0")
         .about("Nice decision tree")
         .author("Wakka")
-        .arg(Arg::with_name("training")
-             .short("t")
-             .long("train")
-             .help("Training file")
-             .required(true)
-             .takes_value(true))
-        .arg(Arg::with_name("test")
-             .short("s")
-             .long("test")
-             .help("Testing file")
-             .takes_value(true))
-        .arg(Arg::with_name("check")
-             .short("c")
-             .long("check")
-             .help("File to check the test")
-             .takes_value(true))
-        .arg(Arg::with_name("dot")
-             .short("d")
-             .long("dot")
-             .help("Output dot file")
-             .takes_value(true))
-        .arg(Arg::with_name("depth")
-             .long("depth")
-             .help("Cross validate to get the depth")
-             .takes_value(false))
-        .get_matches();
-    
-    let train = titanic::load_titanic(matches.value_of("training").unwrap()).unwrap();
-    let train = train.iter().collect();
-    let depth = if matches.is_present("depth") {
-        Some(get_best_depth(&train))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("train")
+             .about("Train a tree and save it")
+             .arg(train_arg())
+             .arg(transform_arg())
+             .arg(depth_arg())
+             .arg(class_weight_arg())
+             .arg(costs_arg())
+             .args(&resample_args())
+             .arg(extra_trees_arg())
+             .args(&forest_args())
+             .arg(bootstrap_arg())
+             .arg(monotonic_arg())
+             .arg(Arg::with_name("import")
+                  .long("import")
+                  .help("Use a scikit-learn tree dumped as json instead of training one")
+                  .conflicts_with_all(&["depth", "class_weight", "extra_trees", "monotonic"])
+                  .takes_value(true))
+             .arg(Arg::with_name("save")
+                  .long("save")
+                  .help("File to save the trained model to")
+                  .required(true)
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("predict")
+             .about("Predict who survived in the test file")
+             .arg(model_arg())
+             .arg(test_arg())
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("evaluate")
+             .about("Check the predictions for the test file against the right answers")
+             .arg(model_arg())
+             .arg(test_arg())
+             .arg(Arg::with_name("check")
+                  .short("c")
+                  .long("check")
+                  .help("File with the right answers for the test file")
+                  .required(true)
+                  .takes_value(true))
+             .arg(costs_arg().help("Cost matrix to predict and score with, instead of the one the model was trained with"))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("cv")
+             .about("Cross validate training a tree")
+             .arg(train_arg())
+             .arg(transform_arg())
+             .arg(depth_arg())
+             .arg(class_weight_arg())
+             .arg(costs_arg())
+             .args(&resample_args())
+             .arg(extra_trees_arg())
+             .args(&forest_args())
+             .arg(bootstrap_arg())
+             .arg(monotonic_arg())
+             .arg(folds_arg())
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("oob")
+             .about("Score bagged extra trees on the rows each tree didn't see, instead of cross validating")
+             .arg(train_arg())
+             .arg(depth_arg())
+             .arg(class_weight_arg())
+             .arg(extra_trees_arg().help("Number of extra trees to train").default_value("100"))
+             .args(&forest_args())
+             .arg(monotonic_arg())
+             .arg(Arg::with_name("show")
+                  .long("show")
+                  .help("Show the out of bag scores, the prediction for each row or the permutation importance of each field")
+                  .possible_values(&["score", "predictions", "importance"])
+                  .default_value("score")
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("anomalies")
+             .about("Score how unusual each row is with an isolation forest, and flag the most unusual")
+             .arg(Arg::with_name("data")
+                  .short("d")
+                  .long("data")
+                  .help("File of rows to score")
+                  .required(true)
+                  .takes_value(true))
+             .arg(transform_arg().help("Feature transform pipeline config to fit on the rows before scoring them"))
+             .arg(Arg::with_name("trees")
+                  .long("trees")
+                  .help("Number of trees in the isolation forest")
+                  .default_value("100")
+                  .takes_value(true))
+             .arg(Arg::with_name("sample_size")
+                  .long("sample-size")
+                  .help("Number of rows each tree is grown on")
+                  .default_value("256")
+                  .takes_value(true))
+             .arg(Arg::with_name("seed")
+                  .long("seed")
+                  .help("Seed for the samples and random splits")
+                  .default_value("0")
+                  .takes_value(true))
+             .arg(Arg::with_name("top")
+                  .short("k")
+                  .long("top")
+                  .help("Number of the highest scoring rows to flag as anomalies")
+                  .default_value("10")
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("stream")
+             .about("Learn a Hoeffding tree a row at a time, without holding the training file in memory")
+             .arg(train_arg().help("Training file, or - to read it from stdin"))
+             .arg(depth_arg())
+             .arg(Arg::with_name("grace_period")
+                  .long("grace-period")
+                  .help("Number of rows a leaf sees between each time it tries to split")
+                  .default_value("200")
+                  .takes_value(true))
+             .arg(Arg::with_name("confidence")
+                  .long("confidence")
+                  .help("Chance of choosing a different split from the one all of the rows would give")
+                  .default_value("1e-7")
+                  .takes_value(true))
+             .arg(Arg::with_name("tie_threshold")
+                  .long("tie-threshold")
+                  .help("Split on the best column anyway once the columns are this close")
+                  .default_value("0.05")
+                  .takes_value(true))
+             .arg(Arg::with_name("save")
+                  .long("save")
+                  .help("File to save a snapshot of the tree to")
+                  .required(true)
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("tune")
+             .about("Cross validate to find the best hyperparameters for a tree")
+             .arg(train_arg())
+             .arg(transform_arg())
+             .arg(folds_arg())
+             .arg(Arg::with_name("param")
+                  .short("p")
+                  .long("param")
+                  .help("Hyperparameter values to search, such as depth=1..=10 or criterion=gini,entropy")
+                  .default_value("depth=1..=10")
+                  .multiple(true)
+                  .number_of_values(1)
+                  .takes_value(true))
+             .arg(Arg::with_name("search")
+                  .long("search")
+                  .help("Try every combination, a number of random ones, successive halving or hyperband \
+                         on random ones, or ones proposed by a Tree-structured Parzen Estimator")
+                  .possible_values(&["grid", "random", "halving", "hyperband", "tpe"])
+                  .default_value("grid")
+                  .takes_value(true))
+             .arg(Arg::with_name("trials")
+                  .long("trials")
+                  .help("Number of combinations to try in a random, halving or tpe search")
+                  .default_value("20")
+                  .takes_value(true))
+             .arg(Arg::with_name("seed")
+                  .long("seed")
+                  .help("Seed for the searches other than grid")
+                  .default_value("0")
+                  .takes_value(true))
+             .arg(Arg::with_name("eta")
+                  .long("eta")
+                  .help("Keep 1/eta of the combinations each round of halving or hyperband")
+                  .default_value("3")
+                  .takes_value(true))
+             .arg(Arg::with_name("min-folds")
+                  .long("min-folds")
+                  .help("Number of folds to score every combination on in the first round of halving")
+                  .default_value("1")
+                  .takes_value(true))
+             .arg(Arg::with_name("max-evaluations")
+                  .long("max-evaluations")
+                  .help("Stop after training this many trees, each scored on one fold")
+                  .takes_value(true))
+             .arg(Arg::with_name("max-time")
+                  .long("max-time")
+                  .help("Stop after this many seconds")
+                  .takes_value(true))
+             .arg(Arg::with_name("log")
+                  .long("log")
+                  .help("Log the score of each trial to this file, reading back any scores already in it \
+                         so that a search can be resumed")
+                  .takes_value(true))
+             .arg(Arg::with_name("outer-folds")
+                  .long("outer-folds")
+                  .help("Run the search inside each of this many outer folds, scoring the tree it \
+                         chooses on the held out fold, to see how well the search really does")
+                  .conflicts_with_all(&["save", "log"])
+                  .takes_value(true))
+             .arg(Arg::with_name("save")
+                  .long("save")
+                  .help("Train a tree with the best hyperparameters and save it to this file")
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("calibrate")
+             .about("Calibrate a tree's probabilities on out of fold predictions, and compare them before and after")
+             .arg(train_arg())
+             .arg(transform_arg())
+             .arg(depth_arg())
+             .arg(class_weight_arg())
+             .arg(folds_arg())
+             .arg(Arg::with_name("method")
+                  .long("method")
+                  .help("How to calibrate the probabilities")
+                  .possible_values(calibration::Method::NAMES)
+                  .default_value("platt")
+                  .takes_value(true))
+             .arg(Arg::with_name("bins")
+                  .long("bins")
+                  .help("Number of bins in the reliability diagram")
+                  .default_value("10")
+                  .takes_value(true))
+             .arg(Arg::with_name("class")
+                  .long("class")
+                  .help("The label whose probabilities go in the reliability diagram")
+                  .default_value("Lived")
+                  .takes_value(true))
+             .arg(Arg::with_name("save")
+                  .long("save")
+                  .help("Train a calibrated tree on all the rows and save it to this file")
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("monotonic")
+             .about("Check a saved model keeps to monotonic constraints, moving each row along a grid of values")
+             .arg(model_arg())
+             .arg(Arg::with_name("data")
+                  .short("d")
+                  .long("data")
+                  .help("File of rows to move along the grid")
+                  .required(true)
+                  .takes_value(true))
+             .arg(monotonic_arg().help("Constraints to check, like Fare=increasing,Age=decreasing, \
+                                        the ones the model was trained with if not given"))
+             .arg(Arg::with_name("steps")
+                  .long("steps")
+                  .help("Number of values in the grid for each field, from its smallest value to its biggest")
+                  .default_value("20")
+                  .takes_value(true))
+             .arg(Arg::with_name("show")
+                  .long("show")
+                  .help("Show the number of violations of each constraint, or each violation")
+                  .possible_values(&["summary", "violations"])
+                  .default_value("summary")
+                  .takes_value(true))
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("inspect")
+             .about("Describe a saved model")
+             .arg(model_arg())
+             .arg(format_arg()))
+        .subcommand(SubCommand::with_name("export")
+             .about("Export a saved model to other formats")
+             .arg(model_arg())
+             .arg(Arg::with_name("dot")
+                  .short("d")
+                  .long("dot")
+                  .help("Output dot file")
+                  .takes_value(true))
+             .arg(Arg::with_name("pmml")
+                  .long("pmml")
+                  .help("Output PMML file")
+                  .takes_value(true))
+             .arg(Arg::with_name("onnx")
+                  .long("onnx")
+                  .help("Output ONNX file")
+                  .takes_value(true))
+             .group(clap::ArgGroup::with_name("outputs")
+                  .args(&["dot", "pmml", "onnx"])
+                  .multiple(true)
+                  .required(true)))
+        .subcommand(SubCommand::with_name("completions")
+             .about("Write a shell completion script to stdout")
+             .arg(Arg::with_name("shell")
+                  .help("Shell to write the completions for")
+                  .possible_values(&Shell::variants())
+                  .required(true)))
+}
+
+fn run() -> Result<()> {
+    let matches = app().get_matches();
+
+    match matches.subcommand() {
+        ("train", Some(m)) => train(m),
+        ("predict", Some(m)) => predict(m),
+        ("evaluate", Some(m)) => evaluate(m),
+        ("cv", Some(m)) => cv(m),
+        ("oob", Some(m)) => oob(m),
+        ("anomalies", Some(m)) => anomalies(m),
+        ("stream", Some(m)) => stream(m),
+        ("tune", Some(m)) => tune(m),
+        ("calibrate", Some(m)) => calibrate(m),
+        ("monotonic", Some(m)) => monotonic(m),
+        ("inspect", Some(m)) => inspect(m),
+        ("export", Some(m)) => export(m),
+        ("completions", Some(m)) => {
+            let shell = value_t!(m, "shell", Shell).unwrap_or_else(|e| e.exit());
+            app().gen_completions_to("decisions", shell, &mut io::stdout());
+            Ok(())
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+/// Write the report to stdout in the format asked for.
+fn output(report: &Report, matches: &ArgMatches) -> Result<()> {
+    let format = matches.value_of("format").unwrap_or("table").parse::<Format>()?;
+    report.write(format, io::stdout().lock())
+}
+
+/// Load the training rows, sharing out their fares with the tickets fitted on them.
+fn load_training(file: &str) -> Result<(Vec<Titanic>, Tickets)> {
+    let mut rows = titanic::load_titanic(file)?;
+    let tickets = Tickets::fit(&rows);
+    tickets.apply(&mut rows);
+    Ok((rows, tickets))
+}
+
+/// Load rows for the model, sharing out their fares with the tickets it was trained with.
+fn load_for(model: &SavedModel, file: &str) -> Result<Vec<Titanic>> {
+    let mut rows = titanic::load_titanic(file)?;
+    if let Some(tickets) = &model.tickets {
+        tickets.apply(&mut rows);
+    }
+    Ok(rows)
+}
+
+/// The transform pipeline config, if there is one.
+fn transform_config(matches: &ArgMatches) -> Result<Option<transform::Config>> {
+    matches.value_of("transform").map(transform::load_config).transpose()
+}
+
+/// Fit the transform pipeline from the config, if there is one.
+fn fit_pipeline(train: &[&Titanic], matches: &ArgMatches) -> Result<Option<Pipeline>> {
+    transform_config(matches)?.map(|config| Pipeline::fit(&config, train)).transpose()
+}
+
+/// Transform the rows with the pipeline.
+/// Without one we use the empty pipeline which passes the rows through as they are.
+fn apply<'a, T>(pipeline: &Option<Pipeline>, rows: &[&'a T]) -> Result<Vec<Transformed<'a, T>>>
+where T: DataRow {
+    match pipeline {
+        Some(pipeline) => pipeline.apply(rows),
+        None => Pipeline::default().apply(rows),
+    }
+}
+
+/// The passengers by id, so a `FoldModel` can find the one it is asked about.
+fn by_id<'a>(rows: &[&'a Titanic]) -> HashMap<i64, &'a Titanic> {
+    rows.iter().map(|row| (row.id(), *row)).collect()
+}
+
+///
+/// A model trained on the rows of one fold along with the tickets counted
+/// and the pipeline fitted on them. So nothing is learnt from the rows it
+/// is scored on, not even who else is on their ticket or a median.
+///
+/// It is asked about the passengers as they were loaded, and looks each one
+/// up by id to share out their fare with its own tickets before it
+/// transforms them with its pipeline.
+///
+struct FoldModel<'a, M> {
+    passengers: &'a HashMap<i64, &'a Titanic>,
+    tickets: Tickets,
+    pipeline: Option<Pipeline>,
+    model: M,
+}
+
+impl<'a, M> FoldModel<'a, M> {
+    /// The passenger with the row's id, with their fare shared out with the fold's tickets.
+    fn passenger<T>(&self, row: &T) -> Result<Titanic>
+    where T: DataRow {
+        let mut passenger = (**self.passengers.get(&row.id()).ok_or_else(|| {
+            DecisionsError::Model(format!("Passenger {} isn't one of the rows being cross validated", row.id()))
+        })?).clone();
+        self.tickets.apply(std::slice::from_mut(&mut passenger));
+        Ok(passenger)
+    }
+
+    /// Save a model trained on all of the rows, along with its tickets, pipeline and their schema.
+    fn into_saved<F>(self, rows: &[&Titanic], learner: F) -> Result<SavedModel>
+    where F: FnOnce(M) -> Learner {
+        let rows = rows.iter().map(|row| self.passenger(*row)).collect::<Result<Vec<Titanic>>>()?;
+        let transformed = apply(&self.pipeline, &rows.iter().collect::<Vec<_>>())?;
+        Ok(SavedModel {
+            schema: Some(schema::Schema::from_rows(&transformed.iter().collect::<Vec<_>>(), "Survived")?),
+            pipeline: self.pipeline,
+            learner: learner(self.model),
+            costs: None,
+            calibration: None,
+            monotonic: None,
+            tickets: Some(self.tickets),
+        })
+    }
+}
+
+impl<'a, M> Model for FoldModel<'a, M>
+where M: Model {
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where T: DataRow {
+        let passenger = self.passenger(row)?;
+        self.model.predict_proba(&apply(&self.pipeline, &[&passenger])?[0])
+    }
+
+    fn predict<T>(&self, row: &T) -> Result<String>
+    where T: DataRow {
+        let passenger = self.passenger(row)?;
+        self.model.predict(&apply(&self.pipeline, &[&passenger])?[0])
+    }
+}
+
+///
+/// Count the tickets and fit the pipeline on the training rows of a fold,
+/// and train on them once their fares are shared out and they are transformed.
+///
+fn fit_fold<'a, M, F>(passengers: &'a HashMap<i64, &'a Titanic>, config: Option<&transform::Config>,
+                      train: &[&Titanic], fit: F) -> Result<FoldModel<'a, M>>
+where F: FnOnce(&[&Transformed<Titanic>]) -> Result<M> {
+    let mut train: Vec<Titanic> = train.iter().map(|row| (*row).clone()).collect();
+    let tickets = Tickets::fit(&train);
+    tickets.apply(&mut train);
+    let train: Vec<_> = train.iter().collect();
+
+    let pipeline = config.map(|config| Pipeline::fit(config, &train)).transpose()?;
+    let rows = apply(&pipeline, &train)?;
+    let rows: Vec<_> = rows.iter().collect();
+    let model = fit(&rows)?;
+    Ok(FoldModel { passengers, tickets, pipeline, model })
+}
+
+fn depth(matches: &ArgMatches) -> Option<u64> {
+    if matches.is_present("depth") {
+        Some(value_t!(matches, "depth", u64).unwrap_or_else(|e| e.exit()))
     } else {
         None
-    };
-        
-    let tree = tree::build_tree(&train, depth);
-    
-    if let Some(dotfile) = matches.value_of("dot") {
-        let mut f = File::create(dotfile).unwrap();
-        f.write_all(tree.to_dot().as_bytes()).unwrap();
     }
+}
+
+fn sampling(matches: &ArgMatches) -> Result<Option<Sampling>> {
+    match matches.value_of("resample") {
+        Some(method) => Ok(Some(Sampling {
+            method: method.parse()?,
+            ratio: value_t!(matches, "resample_ratio", f64).unwrap_or_else(|e| e.exit()),
+            seed: value_t!(matches, "resample_seed", u64).unwrap_or_else(|e| e.exit()),
+            ..Sampling::default()
+        })),
+        None => Ok(None),
+    }
+}
+
+fn costs(matches: &ArgMatches) -> Result<Option<CostMatrix>> {
+    matches.value_of("costs").map(CostMatrix::load).transpose()
+}
+
+/// The settings for growing a tree from the depth, class weight and cost options.
+fn tree_params(matches: &ArgMatches) -> Result<TreeParams> {
+    Ok(TreeParams {
+        max_depth: depth(matches),
+        class_weight: matches.value_of("class_weight").unwrap_or("uniform").parse()?,
+        costs: costs(matches)?,
+        monotonic: monotonic_constraints(matches)?,
+        ..TreeParams::default()
+    })
+}
+
+/// The monotonic constraints on the chance of living, if there are any.
+fn monotonic_constraints(matches: &ArgMatches) -> Result<Option<Monotonic>> {
+    matches.value_of("monotonic").map(|directions| Monotonic::parse("Lived", directions)).transpose()
+}
 
-    if let Some(testfile) = matches.value_of("test") {
-        if let Some(checkfile) = matches.value_of("check") {
-            test(&tree, testfile, checkfile);
+/// The settings for the extra trees, if we are training them rather than a single tree.
+fn extra_trees_params(matches: &ArgMatches, tree: &TreeParams) -> Option<ExtraTreesParams> {
+    if !matches.is_present("extra_trees") {
+        return None;
+    }
+    Some(ExtraTreesParams {
+        trees: value_t!(matches, "extra_trees", usize).unwrap_or_else(|e| e.exit()),
+        tree: tree.clone(),
+        max_features: if matches.is_present("max_features") {
+            Some(value_t!(matches, "max_features", usize).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        },
+        seed: if matches.is_present("seed") {
+            value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit())
         } else {
-            predict(&tree, testfile);
+            0
+        },
+        bootstrap: matches.is_present("bootstrap"),
+    })
+}
+
+/// The number of folds, checking we can split this many rows into them.
+fn folds(matches: &ArgMatches, rows: usize) -> Result<usize> {
+    let folds = value_t!(matches, "folds", usize).unwrap_or_else(|e| e.exit());
+    cross_validation::check_folds(rows, folds)?;
+    Ok(folds)
+}
+
+/// Train a tree, or extra trees, on the rows and bundle it up with the pipeline, tickets and schema.
+fn fit_model<T>(pipeline: Option<Pipeline>, tickets: &Tickets, train: &[&T], params: &TreeParams,
+                extra_trees: Option<&ExtraTreesParams>, import: Option<&str>) -> Result<SavedModel>
+where T: DataRow + Sync {
+    let learner = match (import, extra_trees) {
+        (Some(importfile), _) => Learner::Tree(sklearn::load_tree(importfile, train)?),
+        (None, Some(extra_trees)) => Learner::Forest(extra_trees.fit_parallel(train)?),
+        (None, None) => Learner::Tree(params.fit(train)?),
+    };
+
+    Ok(SavedModel {
+        pipeline,
+        schema: Some(schema::Schema::from_rows(train, "Survived")?),
+        learner,
+        costs: params.costs.clone(),
+        calibration: None,
+        monotonic: params.monotonic.clone(),
+        tickets: Some(tickets.clone()),
+    })
+}
+
+fn train(matches: &ArgMatches) -> Result<()> {
+    let (train, tickets) = load_training(matches.value_of("training").unwrap())?;
+    let train: Vec<_> = train.iter().collect();
+    let pipeline = fit_pipeline(&train, matches)?;
+    let rows = apply(&pipeline, &train)?;
+    let rows: Vec<_> = rows.iter().collect();
+
+    let params = tree_params(matches)?;
+    let extra_trees = extra_trees_params(matches, &params);
+    let model = match sampling(matches)? {
+        Some(sampling) => {
+            let sampled = sampling.resample(&rows)?;
+            let sampled: Vec<_> = sampled.iter().collect();
+            fit_model(pipeline, &tickets, &sampled, &params, extra_trees.as_ref(), matches.value_of("import"))?
         }
-    }   
+        None => fit_model(pipeline, &tickets, &rows, &params, extra_trees.as_ref(), matches.value_of("import"))?,
+    };
+    model.save(matches.value_of("save").unwrap())?;
+
+    let trees = model.learner.trees();
+    let mut report = Report::new(&["Rows", "Trees", "Depth", "Nodes", "Leaves", "Training accuracy"]);
+    report.push(vec![
+        Value::from(rows.len()),
+        Value::from(trees.len()),
+        Value::from(trees.iter().map(|tree| tree.depth()).max()),
+        Value::from(trees.iter().map(|tree| tree.node_count()).sum::<usize>()),
+        Value::from(trees.iter().map(|tree| tree.leaf_count()).sum::<usize>()),
+        Value::from(metrics::accuracy(&model, &rows)?),
+    ]);
+    output(&report, matches)
 }
 
-/// Predict the given file and output results
-/// as a csv to stdout.
-fn predict(tree: &tree::Node, testfile: &str) {
-    let test = titanic::load_titanic(testfile).unwrap();
-    println!("PassengerId,Survived");
-    for t in test {
+fn predict(matches: &ArgMatches) -> Result<()> {
+    let model = SavedModel::load(matches.value_of("model").unwrap())?;
+    let test = load_for(&model, matches.value_of("test").unwrap())?;
+    let test: Vec<_> = test.iter().collect();
+    let rows = apply(&model.pipeline, &test)?;
+
+    let mut report = Report::new(&["PassengerId", "Survived"]);
+    for row in &rows {
         // Kaggle wants 1 for lived, 0 for died.
-        println!("{},{}", t.id(), if &tree::classify(&t, &tree) == "Lived" {
-            1
-        } else {
-            0
-        });
+        let survived = if model.predict(row)? == "Lived" { 1 } else { 0 };
+        report.push(vec![Value::from(row.id()), Value::from(survived)]);
     }
+    output(&report, matches)
 }
 
+/// Test the model against the test rows, using the check file to check the results.
+fn evaluate(matches: &ArgMatches) -> Result<()> {
+    let mut model = SavedModel::load(matches.value_of("model").unwrap())?;
+    if let Some(costs) = costs(matches)? {
+        model.costs = Some(costs);
+    }
+    let mut test = load_for(&model, matches.value_of("test").unwrap())?;
+    titanic::add_labels(&mut test, &titanic::load_check(matches.value_of("check").unwrap())?)?;
+    let test: Vec<_> = test.iter().collect();
+    let rows = apply(&model.pipeline, &test)?;
+    let rows: Vec<_> = rows.iter().collect();
 
-/// Test the given decision tree against the test file,
-/// using checkfile to check the results.
-fn test(tree: &tree::Node, testfile: &str, checkfile: &str) {
-    let test = titanic::load_titanic(testfile).unwrap();
-    let check = titanic::load_check(checkfile).unwrap();
+    let accuracy = metrics::accuracy(&model, &rows)?;
+    let correct = (accuracy * rows.len() as f64).round() as usize;
+    let report = match &model.costs {
+        Some(costs) => {
+            let mut report = Report::new(&["Correct", "Total", "Accuracy", "Cost", "Average cost"]);
+            report.push(vec![
+                Value::from(correct),
+                Value::from(rows.len()),
+                Value::from(accuracy),
+                Value::from(metrics::cost(&model, &rows, costs)?),
+                Value::from(metrics::average_cost(&model, &rows, costs)?),
+            ]);
+            report
+        }
+        None => {
+            let mut report = Report::new(&["Correct", "Total", "Accuracy"]);
+            report.push(vec![Value::from(correct), Value::from(rows.len()), Value::from(accuracy)]);
+            report
+        }
+    };
+    output(&report, matches)
+}
+
+fn cv(matches: &ArgMatches) -> Result<()> {
+    let train = titanic::load_titanic(matches.value_of("training").unwrap())?;
+    let rows: Vec<_> = train.iter().collect();
+    let config = transform_config(matches)?;
 
-    // Partition into correct guesses and incorrect guesses
-    let correct = test.iter().filter(|t| {
-        let actual = check.get(&t.id()).unwrap();
-        actual == &tree::classify(&**t, &tree) 
-    }).count();
-    
-    print!("{}/{} = {}%", 
-           correct, 
-           test.len(),
-           correct as f64 / test.len() as f64 * 100.0);
+    let folds = folds(matches, rows.len())?;
+    let estimator = tree_params(matches)?;
+    let sampling = sampling(matches)?;
+    let (trees, (accuracy, cost)) = match extra_trees_params(matches, &estimator) {
+        Some(extra_trees) => (extra_trees.trees, score_folds(&rows, folds, config.as_ref(), &extra_trees, &sampling, &estimator.costs)?),
+        None => (1, score_folds(&rows, folds, config.as_ref(), &estimator, &sampling, &estimator.costs)?),
+    };
+
+    let mut columns = vec!["Folds", "Trees", "Depth", "Class weight", "Accuracy"];
+    let mut values = vec![
+        Value::from(folds),
+        Value::from(trees),
+        Value::from(estimator.max_depth),
+        Value::from(estimator.class_weight.to_string()),
+        Value::from(accuracy),
+    ];
+    if let Some((cost, average)) = cost {
+        columns.extend(&["Cost", "Average cost"]);
+        values.extend(vec![Value::from(cost), Value::from(average)]);
+    }
+    let mut report = Report::new(&columns);
+    report.push(values);
+    output(&report, matches)
 }
 
-/// Using cross validation see which depth gives us the best results.
-fn get_best_depth<T>(train: &Vec<&T>) -> u64 
-where T: DataRow + Sync {
-    let (best_depth, _) = (1_u64..10_u64)
-    // Parallelise the validation.
-        .into_par_iter() 
-        .map(|depth| {
-            (depth, cross_validation::validate(&train, 10, |t| tree::build_tree(&t, Some(depth))))
+///
+/// Cross validate the estimator, counting the tickets, fitting the pipeline
+/// and resampling on each training fold, and predicting with the costs if we have them.
+/// Gives the mean accuracy over the folds and, with costs, the total cost of every
+/// fold's predictions along with the mean of their average costs.
+///
+fn score_folds<E>(rows: &[&Titanic], folds: usize, config: Option<&transform::Config>, estimator: &E,
+                  sampling: &Option<Sampling>, costs: &Option<CostMatrix>) -> Result<(f64, Option<(f64, f64)>)>
+where E: Estimator {
+    let passengers = by_id(rows);
+    let samplings = match sampling {
+        Some(sampling) => cross_validation::fold_samplings(sampling, folds).into_iter().map(Some).collect(),
+        None => vec![None; folds],
+    };
+
+    let mut accuracy = 0.0;
+    let mut cost = 0.0;
+    let mut average_cost = 0.0;
+    for (fold, sampling) in samplings.iter().enumerate() {
+        let (train, test) = cross_validation::fold_dataset(rows, folds, fold)?;
+        let model = fit_fold(&passengers, config, &train, |rows| match sampling {
+            Some(sampling) => {
+                let sampled = sampling.resample(rows)?;
+                estimator.fit(&sampled.iter().collect::<Vec<_>>())
+            }
+            None => estimator.fit(rows),
+        })?;
+        // With costs we score the labels with the lowest cost, as that is what the saved model would predict.
+        match costs {
+            Some(costs) => {
+                let model = MinimumCost::new(model, costs.clone());
+                accuracy += metrics::accuracy(&model, &test)?;
+                cost += metrics::cost(&model, &test, costs)?;
+                average_cost += metrics::average_cost(&model, &test, costs)?;
+            }
+            None => accuracy += metrics::accuracy(&model, &test)?,
+        }
+    }
+    let folds = folds as f64;
+    Ok((accuracy / folds, costs.as_ref().map(|_| (cost, average_cost / folds))))
+}
+
+///
+/// Train bagged extra trees and score them on the rows each tree didn't see.
+/// Each tree sees different rows, so there is no one set of rows to fit a
+/// pipeline on, or to count tickets in, without it seeing the rows that
+/// tree is scored on. So there is no pipeline and no fare per person.
+///
+fn oob(matches: &ArgMatches) -> Result<()> {
+    let train = titanic::load_titanic(matches.value_of("training").unwrap())?;
+    let rows: Vec<_> = train.iter().collect();
+
+    let params = ExtraTreesParams {
+        bootstrap: true,
+        ..extra_trees_params(matches, &tree_params(matches)?).unwrap()
+    };
+    let forest = params.fit_parallel(&rows)?;
+
+    let report = match matches.value_of("show").unwrap() {
+        "predictions" => {
+            let mut report = Report::new(&["PassengerId", "Actual", "Predicted", "Lived"]);
+            for (row, probabilities) in rows.iter().zip(forest.oob_predict_proba(&rows)?) {
+                report.push(vec![
+                    Value::from(row.id()),
+                    Value::from(row.label()?),
+                    Value::from(probabilities.clone().map(decisions::model::most_likely)),
+                    Value::from(probabilities.map(|p| *p.get("Lived").unwrap_or(&0.0))),
+                ]);
+            }
+            report
+        }
+        "importance" => {
+            let mut importance = forest.oob_importance(&rows, params.seed)?;
+            importance.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+            let mut report = Report::new(&["Field", "Importance"]);
+            for (field, drop) in importance {
+                report.push(vec![Value::from(field), Value::from(drop)]);
+            }
+            report
+        }
+        _ => {
+            let score = forest.oob_score(&rows)?;
+            let mut report = Report::new(&["Rows", "Scored", "Trees", "Accuracy", "Brier score"]);
+            report.push(vec![
+                Value::from(rows.len()),
+                Value::from(score.rows),
+                Value::from(forest.trees.len()),
+                Value::from(score.accuracy),
+                Value::from(score.brier),
+            ]);
+            report
+        }
+    };
+    output(&report, matches)
+}
+
+/// Score each row with an isolation forest grown on all of them, flagging the top ones.
+fn anomalies(matches: &ArgMatches) -> Result<()> {
+    let (data, _) = load_training(matches.value_of("data").unwrap())?;
+    let data: Vec<_> = data.iter().collect();
+    let pipeline = fit_pipeline(&data, matches)?;
+    let rows = apply(&pipeline, &data)?;
+    let rows: Vec<_> = rows.iter().collect();
+
+    let params = IsolationParams {
+        trees: value_t!(matches, "trees", usize).unwrap_or_else(|e| e.exit()),
+        sample_size: value_t!(matches, "sample_size", usize).unwrap_or_else(|e| e.exit()),
+        seed: value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit()),
+        ..IsolationParams::default()
+    };
+    let forest = params.fit(&rows)?;
+    let scores = forest.scores(&rows)?;
+    let mut flagged = vec![false; rows.len()];
+    for idx in isolation::top_k(&scores, value_t!(matches, "top", usize).unwrap_or_else(|e| e.exit())) {
+        flagged[idx] = true;
+    }
+
+    let mut report = Report::new(&["PassengerId", "Score", "Anomaly"]);
+    for ((row, score), anomaly) in rows.iter().zip(scores).zip(flagged) {
+        report.push(vec![Value::from(row.id()), Value::from(score), Value::from(anomaly)]);
+    }
+    output(&report, matches)
+}
+
+///
+/// Learn a Hoeffding tree from the training rows as they are read, scoring each
+/// row before learning from it, and save a snapshot of the tree at the end.
+///
+fn stream(matches: &ArgMatches) -> Result<()> {
+    let params = HoeffdingParams {
+        grace_period: value_t!(matches, "grace_period", usize).unwrap_or_else(|e| e.exit()),
+        split_confidence: value_t!(matches, "confidence", f64).unwrap_or_else(|e| e.exit()),
+        tie_threshold: value_t!(matches, "tie_threshold", f64).unwrap_or_else(|e| e.exit()),
+        max_depth: depth(matches),
+        ..HoeffdingParams::default()
+    };
+    let rows: Box<dyn Iterator<Item = Result<Titanic>>> = match matches.value_of("training").unwrap() {
+        "-" => Box::new(titanic::stream_titanic(io::stdin())),
+        file => Box::new(titanic::stream_titanic(open(file)?)),
+    };
+
+    let mut tree = HoeffdingTree::new(params);
+    let mut schema = schema::SchemaBuilder::default();
+    let mut correct = 0;
+    for row in rows {
+        let row = row?;
+        if tree.rows() > 0 && tree.predict(&row)? == row.label()? {
+            correct += 1;
+        }
+        tree.learn(&row)?;
+        schema.add(&row)?;
+    }
+    if tree.rows() == 0 {
+        return Err(DecisionsError::Config("There were no rows to learn from".to_string()));
+    }
+
+    let snapshot = tree.snapshot();
+    let mut report = Report::new(&["Rows", "Depth", "Nodes", "Leaves", "Prequential accuracy"]);
+    report.push(vec![
+        Value::from(tree.rows()),
+        Value::from(snapshot.depth()),
+        Value::from(snapshot.node_count()),
+        Value::from(snapshot.leaf_count()),
+        Value::from(correct as f64 / (tree.rows() - 1).max(1) as f64),
+    ]);
+    SavedModel {
+        pipeline: None,
+        schema: Some(schema.build("Survived")),
+        learner: Learner::Tree(snapshot),
+        costs: None,
+        calibration: None,
+        monotonic: None,
+        tickets: None,
+    }
+    .save(matches.value_of("save").unwrap())?;
+    output(&report, matches)
+}
+
+/// The search `tune` runs, so it can be run on all of the rows or inside each outer fold.
+struct Tuner<'a> {
+    search: &'a str,
+    config: Option<transform::Config>,
+    folds: usize,
+    space: tuning::Space,
+    trials: usize,
+    seed: u64,
+    eta: usize,
+    min_folds: usize,
+    budget: optimise::Budget,
+}
+
+impl<'a> Tuner<'a> {
+    fn new(matches: &'a ArgMatches, rows: usize) -> Result<Self> {
+        Ok(Tuner {
+            search: matches.value_of("search").unwrap_or("grid"),
+            config: transform_config(matches)?,
+            folds: folds(matches, rows)?,
+            space: tuning::Space::parse(&matches.values_of("param").unwrap().collect::<Vec<_>>())?,
+            trials: value_t!(matches, "trials", usize).unwrap_or_else(|e| e.exit()),
+            seed: value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit()),
+            eta: value_t!(matches, "eta", usize).unwrap_or_else(|e| e.exit()).max(2),
+            min_folds: value_t!(matches, "min-folds", usize).unwrap_or_else(|e| e.exit()),
+            budget: optimise::Budget {
+                evaluations: if matches.is_present("max-evaluations") {
+                    Some(value_t!(matches, "max-evaluations", usize).unwrap_or_else(|e| e.exit()))
+                } else {
+                    None
+                },
+                time: if matches.is_present("max-time") {
+                    Some(Duration::from_secs(value_t!(matches, "max-time", u64).unwrap_or_else(|e| e.exit())))
+                } else {
+                    None
+                },
+            },
         })
-        .max_by(|(_, ascore),(_, bscore)| ascore.partial_cmp(bscore).unwrap())
-        .unwrap();
+    }
+
+    /// Search on the rows, which have to be among the passengers.
+    fn search<'p>(&self, passengers: &'p HashMap<i64, &'p Titanic>, rows: &[&Titanic], log: &mut optimise::TrialLog)
+                  -> Result<tuning::Search<FoldModel<'p, Learner>>> {
+        let (space, folds) = (&self.space, self.folds);
+        let train_fn = |params: &tuning::Params, train: Vec<&Titanic>| {
+            fit_fold(passengers, self.config.as_ref(), &train, |rows| tuning::train_learner(params, rows.to_vec()))
+        };
+        match self.search {
+            "random" => tuning::random_search(rows, folds, space, self.trials, self.seed, train_fn),
+            "halving" => {
+                let candidates = space.sample(self.trials, self.seed);
+                optimise::successive_halving(rows, folds, candidates, self.eta, self.min_folds, self.budget, log, train_fn)
+            }
+            "hyperband" => optimise::hyperband(rows, folds, space, self.eta, self.seed, self.budget, log, train_fn),
+            "tpe" => optimise::tpe(rows, folds, space, self.trials, self.seed, self.budget, log, train_fn),
+            _ => tuning::grid_search(rows, folds, space, train_fn),
+        }
+    }
+}
+
+/// Using cross validation see which hyperparameters give us the best results.
+fn tune(matches: &ArgMatches) -> Result<()> {
+    let train = titanic::load_titanic(matches.value_of("training").unwrap())?;
+    let rows: Vec<_> = train.iter().collect();
+    let passengers = by_id(&rows);
+
+    let tuner = Tuner::new(matches, rows.len())?;
+    let names = tuner.space.names();
+
+    if matches.is_present("outer-folds") {
+        let outer = value_t!(matches, "outer-folds", usize).unwrap_or_else(|e| e.exit());
+        cross_validation::check_folds(rows.len(), outer)?;
+        let nested = tuning::nested_cross_validation(&rows, outer, |train| {
+            tuner.search(&passengers, train, &mut optimise::TrialLog::new())
+        })?;
+
+        let mut report = Report::new(&[&["Fold"], &names[..], &["Inner", "Outer"]].concat());
+        for (fold, outer) in nested.folds.iter().enumerate() {
+            let mut row = vec![Value::from((fold + 1).to_string())];
+            row.extend(outer.params.values().map(|v| serde_json::to_value(v).unwrap_or(Value::Null)));
+            row.push(Value::from(outer.inner));
+            row.push(Value::from(outer.score));
+            report.push(row);
+        }
+        for (name, value) in &[("Mean", nested.mean), ("Std", nested.std)] {
+            let mut row = vec![Value::from(*name)];
+            row.extend(names.iter().map(|_| Value::Null));
+            row.push(Value::Null);
+            row.push(Value::from(*value));
+            report.push(row);
+        }
+        return output(&report, matches);
+    }
+
+    let mut log = match matches.value_of("log") {
+        Some(logfile) => optimise::TrialLog::open(logfile, tuner.folds)?,
+        None => optimise::TrialLog::new(),
+    };
+    let search = tuner.search(&passengers, &rows, &mut log)?;
+
+    if let Some(savefile) = matches.value_of("save") {
+        search.best.into_saved(&rows, |learner| learner)?.save(savefile)?;
+    }
+
+    let mut report = Report::new(&[&names[..], &["Folds", "Mean", "Std"]].concat());
+    for trial in search.leaderboard {
+        let mut row: Vec<Value> = trial.params.values().map(|v| serde_json::to_value(v).unwrap_or(Value::Null)).collect();
+        row.push(Value::from(trial.scores.len()));
+        row.push(Value::from(trial.mean));
+        row.push(Value::from(trial.std));
+        report.push(row);
+    }
+    output(&report, matches)
+}
+
+/// Calibrate the tree, and compare the out of fold probabilities before and after.
+fn calibrate(matches: &ArgMatches) -> Result<()> {
+    let train = titanic::load_titanic(matches.value_of("training").unwrap())?;
+    let rows: Vec<_> = train.iter().collect();
+    let passengers = by_id(&rows);
+    let config = transform_config(matches)?;
+
+    let folds = folds(matches, rows.len())?;
+    let params = tree_params(matches)?;
+    let method_name = matches.value_of("method").unwrap();
+    let method: calibration::Method = method_name.parse()?;
+    let bins = value_t!(matches, "bins", usize).unwrap_or_else(|e| e.exit());
+    let class = matches.value_of("class").unwrap();
+
+    // Calibrating inside each fold keeps the rows we score out of the calibration as well as the tree, tickets and pipeline.
+    let train_fn = |train: Vec<&Titanic>| fit_fold(&passengers, config.as_ref(), &train, |rows| params.fit(rows));
+    let labels = rows.iter().map(|row| row.label()).collect::<Result<Vec<String>>>()?;
+    let before = cross_validation::out_of_fold(&rows, folds, train_fn)?;
+    let after = cross_validation::out_of_fold(&rows, folds, |train| {
+        calibration::calibrate(&train, folds, method, train_fn)
+    })?;
+
+    let mut report = Report::new(&["Probabilities", "Bin", "Count", "Predicted", "Observed", "Brier score"]);
+    for (name, probabilities) in &[("uncalibrated", &before), (method_name, &after)] {
+        let predicted: Vec<f64> = probabilities.iter().map(|p| *p.get(class).unwrap_or(&0.0)).collect();
+        let targets: Vec<bool> = labels.iter().map(|label| label == class).collect();
+        for bin in calibration::reliability(&predicted, &targets, bins)? {
+            report.push(vec![
+                Value::from(*name),
+                Value::from(format!("{:.2}-{:.2}", bin.lower, bin.upper)),
+                Value::from(bin.count),
+                Value::from(bin.predicted),
+                Value::from(bin.observed),
+                Value::Null,
+            ]);
+        }
+        report.push(vec![
+            Value::from(*name),
+            Value::from("all"),
+            Value::from(rows.len()),
+            Value::from(predicted.iter().sum::<f64>() / rows.len() as f64),
+            Value::from(targets.iter().filter(|&&t| t).count() as f64 / rows.len() as f64),
+            Value::from(metrics::brier_score(probabilities, &labels)),
+        ]);
+    }
+
+    if let Some(savefile) = matches.value_of("save") {
+        let calibrated = calibration::calibrate(&rows, folds, method, train_fn)?;
+        SavedModel {
+            calibration: Some(calibrated.calibration),
+            ..calibrated.model.into_saved(&rows, Learner::Tree)?
+        }
+        .save(savefile)?;
+    }
+
+    output(&report, matches)
+}
+
+/// Check a saved model against monotonic constraints over a grid of values of each constrained field.
+fn monotonic(matches: &ArgMatches) -> Result<()> {
+    let model = SavedModel::load(matches.value_of("model").unwrap())?;
+    let data = load_for(&model, matches.value_of("data").unwrap())?;
+    let data: Vec<_> = data.iter().collect();
+    let rows = apply(&model.pipeline, &data)?;
+    let rows: Vec<_> = rows.iter().collect();
+
+    let constraints = match monotonic_constraints(matches)? {
+        Some(constraints) => constraints,
+        None => model.monotonic.clone().ok_or_else(|| {
+            DecisionsError::Config("The model wasn't trained with monotonic constraints, so give some to check".to_string())
+        })?,
+    };
+    let steps = value_t!(matches, "steps", usize).unwrap_or_else(|e| e.exit());
+    let violations = monotonic::violations(&model, &rows, &constraints, steps)?;
+
+    let report = match matches.value_of("show").unwrap() {
+        "violations" => {
+            let mut report = Report::new(&["PassengerId", "Field", "From", "To", "Lived before", "Lived after"]);
+            for violation in violations {
+                report.push(vec![
+                    Value::from(violation.id),
+                    Value::from(violation.field),
+                    Value::from(violation.from),
+                    Value::from(violation.to),
+                    Value::from(violation.before),
+                    Value::from(violation.after),
+                ]);
+            }
+            report
+        }
+        _ => {
+            let mut report = Report::new(&["Field", "Direction", "Rows", "Violations", "Largest"]);
+            for (field, direction) in &constraints.directions {
+                let found: Vec<_> = violations.iter().filter(|violation| &violation.field == field).collect();
+                let largest = found.iter().map(|violation| (violation.after - violation.before).abs()).fold(0.0, f64::max);
+                report.push(vec![
+                    Value::from(field.clone()),
+                    Value::from(direction.to_string()),
+                    Value::from(rows.len()),
+                    Value::from(found.len()),
+                    Value::from(largest),
+                ]);
+            }
+            report
+        }
+    };
+    output(&report, matches)
+}
+
+fn inspect(matches: &ArgMatches) -> Result<()> {
+    let model = SavedModel::load(matches.value_of("model").unwrap())?;
+
+    let mut report = Report::new(&["Property", "Value"]);
+    let trees = model.learner.trees();
+    report.push(vec!["Trees".into(), trees.len().into()]);
+    report.push(vec!["Depth".into(), trees.iter().map(|tree| tree.depth()).max().into()]);
+    report.push(vec!["Nodes".into(), trees.iter().map(|tree| tree.node_count()).sum::<usize>().into()]);
+    report.push(vec!["Leaves".into(), trees.iter().map(|tree| tree.leaf_count()).sum::<usize>().into()]);
+    report.push(vec!["Transform steps".into(), model.pipeline.as_ref().map_or(0, |p| p.steps().len()).into()]);
+    if let Some(schema) = &model.schema {
+        let fields: Vec<&str> = schema.fields.iter().map(|f| f.name.as_str()).collect();
+        report.push(vec!["Fields".into(), fields.join(" ").into()]);
+        report.push(vec!["Classes".into(), schema.classes.join(" ").into()]);
+    }
+    if let Some(calibration) = &model.calibration {
+        let method = match calibration.calibrators.values().next() {
+            Some(calibration::Calibrator::Platt { .. }) => "platt",
+            Some(calibration::Calibrator::Isotonic { .. }) => "isotonic",
+            None => "none",
+        };
+        report.push(vec!["Calibration".into(), method.into()]);
+    }
+    if let Some(monotonic) = &model.monotonic {
+        report.push(vec!["Monotonic".into(), monotonic.to_string().into()]);
+    }
+    if let Some(costs) = &model.costs {
+        let mut matrix = Vec::new();
+        costs.to_csv(&mut matrix)?;
+        let matrix = String::from_utf8_lossy(&matrix).trim().replace('\n', " ");
+        report.push(vec!["Costs".into(), matrix.into()]);
+    }
+    output(&report, matches)
+}
+
+fn export(matches: &ArgMatches) -> Result<()> {
+    let model = SavedModel::load(matches.value_of("model").unwrap())?;
+
+    if let Some(dotfile) = matches.value_of("dot") {
+        let tree = match model.learner {
+            Learner::Tree(ref tree) => tree,
+            Learner::Forest(_) => {
+                return Err(DecisionsError::Model("Only a single tree can be drawn as a dot file".to_string()))
+            }
+        };
+        let mut f = create(dotfile)?;
+        f.write_all(tree.to_dot().as_bytes())?;
+    }
+
+    if matches.is_present("pmml") || matches.is_present("onnx") {
+        let schema = model.export_schema()?;
+        if model.tickets.is_some() {
+            eprintln!("Warning: the exported model takes the fare per person, which has to be worked out \
+                       from who shared each ticket in the training rows before it is given to it");
+        }
+
+        if let Some(pmmlfile) = matches.value_of("pmml") {
+            let mut f = create(pmmlfile)?;
+            let pmml = match model.learner {
+                Learner::Tree(ref tree) => pmml::tree_to_pmml(tree, schema),
+                Learner::Forest(ref forest) => pmml::ensemble_to_pmml(&forest.trees, schema),
+            };
+            f.write_all(pmml.as_bytes())?;
+        }
+
+        if let Some(onnxfile) = matches.value_of("onnx") {
+            let mut f = create(onnxfile)?;
+            f.write_all(&onnx::ensemble_to_onnx(model.learner.trees(), schema))?;
+        }
+    }
 
-    best_depth
+    Ok(())
 }
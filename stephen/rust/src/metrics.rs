@@ -0,0 +1,185 @@
+//!
+//! Measures of how well a model does on rows with labels.
+//!
+
+use cost::CostMatrix;
+use error::Result;
+use model::{Model, MultiOutputModel};
+use row::DataRow;
+use std::collections::HashMap;
+
+/// The proportion of the rows the model gets right, weighted by the rows' weights.
+pub fn accuracy<M, T>(model: &M, rows: &[&T]) -> Result<f64>
+where
+    M: Model,
+    T: DataRow,
+{
+    let mut correct = 0.0;
+    let mut total = 0.0;
+    for row in rows {
+        if row.label()? == model.predict(*row)? {
+            correct += row.weight();
+        }
+        total += row.weight();
+    }
+
+    Ok(correct / total)
+}
+
+/// The total cost of the model's mistakes on the rows, weighted by the rows' weights.
+pub fn cost<M, T>(model: &M, rows: &[&T], costs: &CostMatrix) -> Result<f64>
+where
+    M: Model,
+    T: DataRow,
+{
+    let mut total = 0.0;
+    for row in rows {
+        total += row.weight() * costs.cost(&row.label()?, &model.predict(*row)?);
+    }
+
+    Ok(total)
+}
+
+/// The average cost of the model's predictions for each row.
+pub fn average_cost<M, T>(model: &M, rows: &[&T], costs: &CostMatrix) -> Result<f64>
+where
+    M: Model,
+    T: DataRow,
+{
+    let weight: f64 = rows.iter().map(|row| row.weight()).sum();
+    Ok(cost(model, rows, costs)? / weight)
+}
+
+///
+/// The Brier score of the probabilities given to each row, the mean over
+/// the rows of the squared difference between the probability of each
+/// label and 1 for the row's label or 0 for the others. Lower is better.
+///
+/// This is Brier's original score, summed over all the labels, so with two
+/// labels it is twice the score of just the positive label.
+///
+pub fn brier_score(probabilities: &[HashMap<String, f64>], labels: &[String]) -> f64 {
+    let total: f64 = probabilities
+        .iter()
+        .zip(labels.iter())
+        .map(|(probabilities, label)| {
+            let others: f64 = probabilities
+                .iter()
+                .filter(|(class, _)| *class != label)
+                .map(|(_, p)| p * p)
+                .sum();
+            let own = 1.0 - probabilities.get(label).unwrap_or(&0.0);
+            others + own * own
+        })
+        .sum();
+    total / probabilities.len() as f64
+}
+
+/// The Brier score of the model's probabilities for the rows.
+pub fn brier<M, T>(model: &M, rows: &[&T]) -> Result<f64>
+where
+    M: Model,
+    T: DataRow,
+{
+    let probabilities = rows.iter().map(|row| model.predict_proba(*row)).collect::<Result<Vec<_>>>()?;
+    let labels = rows.iter().map(|row| row.label()).collect::<Result<Vec<_>>>()?;
+    Ok(brier_score(&probabilities, &labels))
+}
+
+/// The accuracy of each output of a multi-output model, weighted by the rows' weights.
+pub fn output_accuracy<M, T>(model: &M, rows: &[&T]) -> Result<Vec<f64>>
+where
+    M: MultiOutputModel,
+    T: DataRow,
+{
+    let mut correct: Vec<f64> = Vec::new();
+    let mut total = 0.0;
+    for row in rows {
+        let predicted = model.predict_outputs(*row)?;
+        let labels = row.labels()?;
+        correct.resize(labels.len().max(correct.len()), 0.0);
+        for (output, (label, prediction)) in labels.iter().zip(predicted.iter()).enumerate() {
+            if label == prediction {
+                correct[output] += row.weight();
+            }
+        }
+        total += row.weight();
+    }
+
+    Ok(correct.into_iter().map(|correct| correct / total).collect())
+}
+
+/// The Brier score of each output of a multi-output model.
+pub fn output_brier<M, T>(model: &M, rows: &[&T]) -> Result<Vec<f64>>
+where
+    M: MultiOutputModel,
+    T: DataRow,
+{
+    let mut probabilities: Vec<Vec<HashMap<String, f64>>> = Vec::new();
+    let mut labels: Vec<Vec<String>> = Vec::new();
+    for row in rows {
+        for (output, (p, label)) in model.predict_proba_outputs(*row)?.into_iter().zip(row.labels()?).enumerate() {
+            if output == probabilities.len() {
+                probabilities.push(Vec::new());
+                labels.push(Vec::new());
+            }
+            probabilities[output].push(p);
+            labels[output].push(label);
+        }
+    }
+
+    Ok(probabilities
+        .iter()
+        .zip(labels.iter())
+        .map(|(probabilities, labels)| brier_score(probabilities, labels))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{training_data, Fruit};
+    use row::Weighted;
+    use tree::build_tree;
+
+    #[test]
+    fn test_accuracy() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+
+        // The full tree can use the fruit column, so gets everything right.
+        let tree = build_tree(&data, None).unwrap();
+        assert_eq!(1.0, accuracy(&tree, &data).unwrap());
+
+        // Splitting on colour alone gets the grapes and apples but not the lemon.
+        let tree = build_tree(&data, Some(2)).unwrap();
+        assert_eq!(0.8, accuracy(&tree, &data).unwrap());
+
+        let stump = build_tree(&data, Some(1)).unwrap();
+        assert_eq!(0.4, accuracy(&stump, &data).unwrap());
+
+        // Getting the lemon wrong costs more when it counts for more.
+        let weighted: Vec<Weighted<Fruit>> = data
+            .iter()
+            .map(|row| Weighted::new(*row, if row.label().unwrap() == "Lemon" { 4.0 } else { 1.0 }))
+            .collect();
+        let weighted: Vec<&Weighted<Fruit>> = weighted.iter().collect();
+        assert_eq!(0.5, accuracy(&tree, &weighted).unwrap());
+    }
+
+    #[test]
+    fn test_brier() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+
+        let tree = build_tree(&data, None).unwrap();
+        assert_eq!(0.0, brier(&tree, &data).unwrap());
+
+        // The lemon's leaf is 2/3 apple and 1/3 lemon.
+        let tree = build_tree(&data, Some(2)).unwrap();
+        let apple = (1.0f64 / 3.0).powi(2) + (1.0f64 / 3.0).powi(2);
+        let lemon = (2.0f64 / 3.0).powi(2) + (2.0f64 / 3.0).powi(2);
+        let expected = (2.0 * apple + lemon) / 5.0;
+        assert!((expected - brier(&tree, &data).unwrap()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,72 @@
+//!
+//! The traits a learner implements so that it can be cross validated,
+//! tuned, scored and used from the command line.
+//!
+//! An `Estimator` holds the settings for training, and `fit` trains a
+//! `Model` on some rows. The decision tree's estimator is `TreeParams`
+//! and its model is `Node`.
+//!
+
+use error::Result;
+use row::DataRow;
+use std::collections::HashMap;
+
+/// A trained model that makes predictions for rows.
+pub trait Model {
+    /// The probability of each label for the row. Labels that can't happen may be left out.
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow;
+
+    /// The most likely label for the row.
+    fn predict<T>(&self, row: &T) -> Result<String>
+    where
+        T: DataRow,
+    {
+        Ok(most_likely(self.predict_proba(row)?))
+    }
+}
+
+///
+/// A trained model that predicts each of the labels of a row with more
+/// than one, in the order `DataRow::labels` gives them.
+///
+pub trait MultiOutputModel {
+    /// The probability of each label for each of the outputs.
+    fn predict_proba_outputs<T>(&self, row: &T) -> Result<Vec<HashMap<String, f64>>>
+    where
+        T: DataRow;
+
+    /// The most likely label for each of the outputs.
+    fn predict_outputs<T>(&self, row: &T) -> Result<Vec<String>>
+    where
+        T: DataRow,
+    {
+        Ok(self.predict_proba_outputs(row)?.into_iter().map(most_likely).collect())
+    }
+}
+
+/// The label with the highest probability, the first alphabetically if there is a tie.
+pub fn most_likely(probabilities: HashMap<String, f64>) -> String {
+    let mut probabilities: Vec<(String, f64)> = probabilities.into_iter().collect();
+    // Break ties by the label so we always give the same answer.
+    probabilities.sort_by(|(a, _), (b, _)| a.cmp(b));
+    probabilities
+        .into_iter()
+        .fold(None, |best: Option<(String, f64)>, (label, p)| match best {
+            Some((_, best_p)) if best_p >= p => best,
+            _ => Some((label, p)),
+        })
+        .map(|(label, _)| label)
+        .unwrap_or_default()
+}
+
+/// The settings for training a model.
+pub trait Estimator {
+    type Model: Model;
+
+    /// Train a model on the rows.
+    fn fit<T>(&self, rows: &[&T]) -> Result<Self::Model>
+    where
+        T: DataRow;
+}
@@ -0,0 +1,428 @@
+//!
+//! Monotonic constraints, for when the probability of a label should only
+//! go up, or only go down, as a number gets bigger, such as a higher fare
+//! never making it less likely that a passenger lived.
+//!
+//! A constrained tree only splits a constrained column when the rows with
+//! the bigger values are at least as likely to have the label, for an
+//! increasing constraint, or at most as likely for a decreasing one. Then,
+//! as in XGBoost and LightGBM, everything under the lower branch is kept at
+//! or below the midpoint of the two branches' probabilities and everything
+//! under the upper branch at or above it, clamping the leaves that would
+//! otherwise cross it. Constraints are for two labels, and a text column
+//! with a constraint is never split on.
+//!
+//! Forests of constrained trees are constrained too, as they average them.
+//! `violations` checks any model by moving each row along a grid of values
+//! of each constrained column and looking for the label going the wrong way.
+//!
+
+use error::{DecisionsError, Result};
+use model::Model;
+use question::{Predicate, Question};
+use row::{Col, DataRow, Replaced};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use tree::class_counts_iter;
+
+/// Which way the probability of the label goes as the value of a column goes up.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    Increasing,
+    Decreasing,
+}
+
+impl FromStr for Direction {
+    type Err = DecisionsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "increasing" => Ok(Direction::Increasing),
+            "decreasing" => Ok(Direction::Decreasing),
+            _ => Err(DecisionsError::Config(format!(
+                "Unknown direction {}, expected increasing or decreasing",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Increasing => write!(f, "increasing"),
+            Direction::Decreasing => write!(f, "decreasing"),
+        }
+    }
+}
+
+///
+/// The columns whose effect on the probability of a label only goes one way.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Monotonic {
+    /// The label whose probability is constrained.
+    pub label: String,
+    /// The direction of each constrained column, by its name.
+    pub directions: BTreeMap<String, Direction>,
+}
+
+impl Monotonic {
+    /// Parse a direction for each column such as `Fare=increasing,Age=decreasing`.
+    pub fn parse(label: &str, directions: &str) -> Result<Self> {
+        let directions = directions
+            .split(',')
+            .map(|pair| match pair.find('=') {
+                Some(idx) => Ok((pair[..idx].trim().to_string(), pair[idx + 1..].trim().parse()?)),
+                None => Err(DecisionsError::Config(format!(
+                    "Bad monotonic constraint {}, expected field=increasing or field=decreasing,...",
+                    pair
+                ))),
+            })
+            .collect::<Result<BTreeMap<String, Direction>>>()?;
+        Ok(Monotonic {
+            label: label.to_string(),
+            directions,
+        })
+    }
+
+    ///
+    /// Check the constraints can be used on the rows, returning the other
+    /// label. The rows need to have just two labels, one of them ours, and
+    /// every constrained column.
+    ///
+    pub fn check<T>(&self, rows: &[&T]) -> Result<String>
+    where
+        T: DataRow,
+    {
+        if let Some(row) = rows.first() {
+            let names = (0..row.len()).map(|col| row.name(col)).collect::<Result<BTreeSet<String>>>()?;
+            if let Some(field) = self.directions.keys().find(|field| !names.contains(*field)) {
+                return Err(DecisionsError::Config(format!(
+                    "There is no field {} for a monotonic constraint",
+                    field
+                )));
+            }
+        }
+        let (_, counts) = class_counts_iter(rows.iter())?;
+        let other = counts.keys().find(|label| **label != self.label);
+        match other {
+            Some(other) if counts.len() == 2 && counts.contains_key(&self.label) => Ok(other.clone()),
+            _ => Err(DecisionsError::Config(format!(
+                "Monotonic constraints need two labels, one of them {}, but the rows have {}",
+                self.label,
+                counts.keys().cloned().collect::<Vec<String>>().join(", ")
+            ))),
+        }
+    }
+
+    /// The share of the weight of the rows that have the label, 0 if there aren't any.
+    pub fn probability<T>(&self, rows: &[&T]) -> Result<f64>
+    where
+        T: DataRow,
+    {
+        let (len, counts) = class_counts_iter(rows.iter())?;
+        Ok(if len > 0.0 {
+            counts.get(&self.label).unwrap_or(&0.0) / len
+        } else {
+            0.0
+        })
+    }
+
+    ///
+    /// Does the split keep to the constraint on its column? Splits on other
+    /// columns always do. The true branch of a threshold has the bigger values,
+    /// and a constrained column can't be split any other way.
+    ///
+    pub fn allows<T>(&self, question: &Question, true_rows: &[&T], false_rows: &[&T]) -> Result<bool>
+    where
+        T: DataRow,
+    {
+        let direction = match self.directions.get(question.field_name()) {
+            Some(direction) => direction,
+            None => return Ok(true),
+        };
+        match question.predicate() {
+            Predicate::AtLeast(_) => {
+                let (upper, lower) = (self.probability(true_rows)?, self.probability(false_rows)?);
+                Ok(match direction {
+                    Direction::Increasing => upper >= lower,
+                    Direction::Decreasing => upper <= lower,
+                })
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// A direction for each column such as `Fare=increasing,Age=decreasing`.
+impl fmt::Display for Monotonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .directions
+            .iter()
+            .map(|(field, direction)| format!("{}={}", field, direction))
+            .collect();
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+///
+/// How low and how high the probability of the label can go in a node of a
+/// constrained tree, from the splits on constrained columns above it.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds { lower: 0.0, upper: 1.0 }
+    }
+}
+
+impl Bounds {
+    pub fn clamp(&self, probability: f64) -> f64 {
+        probability.max(self.lower).min(self.upper)
+    }
+
+    ///
+    /// The bounds for the true and false branches of a split, given the
+    /// probability of the label in each. A split on a constrained column
+    /// divides the bounds at the midpoint of the two, so neither branch can
+    /// cross the other.
+    ///
+    pub fn split(&self, monotonic: &Monotonic, question: &Question, true_p: f64, false_p: f64) -> (Bounds, Bounds) {
+        let mid = (self.clamp(true_p) + self.clamp(false_p)) / 2.0;
+        let (below, above) = (Bounds { upper: mid, ..*self }, Bounds { lower: mid, ..*self });
+        match monotonic.directions.get(question.field_name()) {
+            Some(Direction::Increasing) => (above, below),
+            Some(Direction::Decreasing) => (below, above),
+            None => (*self, *self),
+        }
+    }
+
+    ///
+    /// The counts of a leaf with the probability of the label moved inside
+    /// the bounds, keeping the total. `other` is the other label.
+    ///
+    pub fn clamp_counts(&self, monotonic: &Monotonic, other: &str, counts: HashMap<String, f64>) -> HashMap<String, f64> {
+        let total: f64 = counts.values().sum();
+        if total <= 0.0 {
+            return counts;
+        }
+        let probability = counts.get(&monotonic.label).unwrap_or(&0.0) / total;
+        let clamped = self.clamp(probability);
+        if clamped == probability {
+            return counts;
+        }
+        let mut clamped_counts = HashMap::new();
+        clamped_counts.insert(monotonic.label.clone(), clamped * total);
+        clamped_counts.insert(other.to_string(), (1.0 - clamped) * total);
+        clamped_counts
+    }
+}
+
+/// Leaves clamped to the same bound can differ by a rounding error when they have different totals.
+const TOLERANCE: f64 = 1e-9;
+
+///
+/// A row for which moving a constrained column from one value on the grid
+/// to the next sends the probability of the label the wrong way.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub id: i64,
+    pub field: String,
+    pub from: f64,
+    pub to: f64,
+    /// The probability of the label at `from`.
+    pub before: f64,
+    /// The probability of the label at `to`.
+    pub after: f64,
+}
+
+///
+/// `steps` evenly spaced values from the smallest value in the column to the
+/// biggest, or just the one if they are the same or there is only one step.
+/// Empty if the column has no numbers.
+///
+pub fn grid<T>(rows: &[&T], col: usize, steps: usize) -> Result<Vec<f64>>
+where
+    T: DataRow,
+{
+    let mut numbers = Vec::new();
+    for row in rows {
+        match row.value(col)?.number() {
+            Some(number) if !number.is_nan() => numbers.push(number),
+            _ => {}
+        }
+    }
+    let (low, high) = numbers.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), &number| {
+        (low.min(number), high.max(number))
+    });
+    Ok(match steps {
+        _ if numbers.is_empty() => Vec::new(),
+        0 | 1 => vec![low],
+        _ if low == high => vec![low],
+        _ => (0..steps)
+            .map(|step| low + (high - low) * step as f64 / (steps - 1) as f64)
+            .collect(),
+    })
+}
+
+///
+/// Check the model keeps to the constraints, by moving each of the rows
+/// along a grid of `steps` values of each constrained column and comparing
+/// the probability of the label at each value with the one before.
+///
+pub fn violations<M, T>(model: &M, rows: &[&T], monotonic: &Monotonic, steps: usize) -> Result<Vec<Violation>>
+where
+    M: Model,
+    T: DataRow,
+{
+    let first = match rows.first() {
+        Some(row) => row,
+        None => return Ok(Vec::new()),
+    };
+    let mut found = Vec::new();
+    for (field, direction) in &monotonic.directions {
+        let col = (0..first.len())
+            .find(|col| first.name(*col).ok().as_ref() == Some(field))
+            .ok_or_else(|| DecisionsError::Config(format!("There is no field {} for a monotonic constraint", field)))?;
+        let grid = grid(rows, col, steps)?;
+
+        for row in rows {
+            let mut previous: Option<(f64, f64)> = None;
+            for &value in &grid {
+                let moved = Replaced::new(*row, col, Col::Float(value));
+                let probability = *model.predict_proba(&moved)?.get(&monotonic.label).unwrap_or(&0.0);
+                if let Some((from, before)) = previous {
+                    let wrong = match direction {
+                        Direction::Increasing => probability < before - TOLERANCE,
+                        Direction::Decreasing => probability > before + TOLERANCE,
+                    };
+                    if wrong {
+                        found.push(Violation {
+                            id: row.id(),
+                            field: field.clone(),
+                            from,
+                            to: value,
+                            before,
+                            after: probability,
+                        });
+                    }
+                }
+                previous = Some((value, probability));
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use forest::ExtraTreesParams;
+    use model::Estimator;
+    use row::test::{points, training_data, Fruit, Point};
+    use tree::TreeParams;
+
+    fn constrained(directions: &str) -> TreeParams {
+        TreeParams {
+            monotonic: Some(Monotonic::parse("Yes", directions).unwrap()),
+            ..TreeParams::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let monotonic = Monotonic::parse("Lived", "Fare=increasing, Age = decreasing").unwrap();
+        assert_eq!(Some(&Direction::Increasing), monotonic.directions.get("Fare"));
+        assert_eq!(Some(&Direction::Decreasing), monotonic.directions.get("Age"));
+        assert_eq!("Age=decreasing,Fare=increasing", monotonic.to_string());
+        assert!(Monotonic::parse("Lived", "Fare").is_err());
+        assert!(Monotonic::parse("Lived", "Fare=up").is_err());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let monotonic = Monotonic::parse("Yes", "X=increasing").unwrap();
+        let question = Question::with_predicate("X".to_string(), 0, Predicate::AtLeast(Col::Float(5.0)));
+        let (upper, lower) = Bounds::default().split(&monotonic, &question, 0.75, 0.25);
+        assert_eq!(Bounds { lower: 0.5, upper: 1.0 }, upper);
+        assert_eq!(Bounds { lower: 0.0, upper: 0.5 }, lower);
+
+        // Other columns keep the bounds they had.
+        let question = Question::with_predicate("Y".to_string(), 1, Predicate::AtLeast(Col::Float(5.0)));
+        assert_eq!((upper, upper), upper.split(&monotonic, &question, 0.8, 0.4));
+
+        let mut counts = HashMap::new();
+        counts.insert("No".to_string(), 4.0);
+        let clamped = upper.clamp_counts(&monotonic, "No", counts);
+        assert_eq!(Some(&2.0), clamped.get("Yes"));
+        assert_eq!(Some(&2.0), clamped.get("No"));
+    }
+
+    #[test]
+    fn test_check() {
+        let data = points(10, 1);
+        let data: Vec<&Point> = data.iter().collect();
+        assert_eq!("No", Monotonic::parse("Yes", "X=increasing").unwrap().check(&data).unwrap());
+        assert!(Monotonic::parse("Yes", "Z=increasing").unwrap().check(&data).is_err());
+        assert!(Monotonic::parse("Maybe", "X=increasing").unwrap().check(&data).is_err());
+
+        // Three fruit is too many labels.
+        let fruit = training_data();
+        let fruit: Vec<&Fruit> = fruit.iter().collect();
+        assert!(Monotonic::parse("Apple", "Things=increasing").unwrap().check(&fruit).is_err());
+    }
+
+    #[test]
+    fn test_constrained_tree() {
+        let data = points(300, 3);
+        let data: Vec<&Point> = data.iter().collect();
+
+        // The noise makes a full depth tree go the wrong way in places.
+        let monotonic = Monotonic::parse("Yes", "X=increasing,Y=increasing").unwrap();
+        let tree = TreeParams::default().fit(&data).unwrap();
+        assert!(!violations(&tree, &data, &monotonic, 20).unwrap().is_empty());
+
+        let tree = constrained("X=increasing,Y=increasing").fit(&data).unwrap();
+        assert!(violations(&tree, &data, &monotonic, 20).unwrap().is_empty());
+        assert!(tree.depth() > 2);
+
+        // Constraining against the data leaves the column out.
+        let tree = constrained("X=decreasing,Y=decreasing").fit(&data).unwrap();
+        assert!(!tree.to_dot().contains("X >="));
+        assert!(!tree.to_dot().contains("Y >="));
+    }
+
+    #[test]
+    fn test_constrained_forest() {
+        let data = points(300, 4);
+        let data: Vec<&Point> = data.iter().collect();
+        let monotonic = Monotonic::parse("Yes", "Y=increasing").unwrap();
+        let forest = ExtraTreesParams {
+            trees: 20,
+            tree: constrained("Y=increasing"),
+            ..ExtraTreesParams::default()
+        }
+        .fit(&data)
+        .unwrap();
+        assert!(violations(&forest, &data, &monotonic, 20).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_grid() {
+        let data = points(50, 1);
+        let data: Vec<&Point> = data.iter().collect();
+        let grid = grid(&data, 0, 10).unwrap();
+        assert_eq!(10, grid.len());
+        assert_eq!(0.0, grid[0]);
+        assert_eq!(9.0, grid[9]);
+    }
+}
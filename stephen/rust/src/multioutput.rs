@@ -0,0 +1,484 @@
+//!
+//! Trees that predict several labels for each row at once, from the rows'
+//! `DataRow::labels`.
+//!
+//! A split is chosen by the mean over the outputs of the impurity it leaves,
+//! so one tree learns what the outputs have in common, and each leaf keeps
+//! the weight of each label for every output. This is usually better than a
+//! tree for each output when the outputs depend on each other.
+//!
+
+use dot::{self, DotNode};
+use error::{DecisionsError, Result};
+use model::MultiOutputModel;
+use question::{Predicate, Question};
+use row::{Col, DataRow};
+use std::collections::{BTreeMap, HashMap};
+use tree::{category_subsets, column_values, entropy, gini, partition, thresholds, Criterion, Node, TreeParams};
+
+///
+/// The settings that control how a multi-output tree is grown.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultiOutputParams {
+    /// The maximum depth of the tree. None if we want it as deep as we can.
+    pub max_depth: Option<u64>,
+    /// Nodes with fewer rows than this become leaves.
+    pub min_samples_split: usize,
+    /// Splits that would leave fewer rows than this on either side aren't considered.
+    pub min_samples_leaf: usize,
+    pub criterion: Criterion,
+}
+
+impl Default for MultiOutputParams {
+    fn default() -> Self {
+        MultiOutputParams {
+            max_depth: None,
+            min_samples_split: 2,
+            min_samples_leaf: 1,
+            criterion: Criterion::Gini,
+        }
+    }
+}
+
+/// A node in a multi-output tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MultiNode {
+    /// The total weight of the training rows with each label, for each output.
+    Leaf {
+        predictions: Vec<HashMap<String, f64>>,
+    },
+    Decision {
+        question: Question,
+        true_branch: Box<MultiNode>,
+        false_branch: Box<MultiNode>,
+    },
+}
+
+impl MultiNode {
+    pub fn depth(&self) -> usize {
+        match self {
+            MultiNode::Leaf { .. } => 1,
+            MultiNode::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } => 1 + true_branch.depth().max(false_branch.depth()),
+        }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        match self {
+            MultiNode::Leaf { .. } => 1,
+            MultiNode::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } => true_branch.leaf_count() + false_branch.leaf_count(),
+        }
+    }
+
+    ///
+    /// The tree for just one of the outputs, as a regular tree, so it can be
+    /// used with `classify` or exported.
+    ///
+    pub fn output(&self, output: usize) -> Result<Node> {
+        match self {
+            MultiNode::Leaf { predictions } => match predictions.get(output) {
+                Some(predictions) => Ok(Node::Leaf {
+                    predictions: predictions.clone(),
+                }),
+                None => Err(DecisionsError::Model(format!(
+                    "The tree has {} outputs, so there is no output {}",
+                    predictions.len(),
+                    output
+                ))),
+            },
+            MultiNode::Decision {
+                question,
+                true_branch,
+                false_branch,
+            } => Ok(Node::new_decision(
+                question.clone(),
+                true_branch.output(output)?,
+                false_branch.output(output)?,
+            )),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        dot::to_dot(self)
+    }
+}
+
+impl DotNode for MultiNode {
+    fn label(&self) -> String {
+        match self {
+            MultiNode::Leaf { predictions } => predictions
+                .iter()
+                .map(|predictions| {
+                    let predictions: BTreeMap<&String, &f64> = predictions.iter().collect();
+                    format!("{:?}", predictions).replace("\"", "")
+                })
+                .collect::<Vec<String>>()
+                .join("\\n"),
+            MultiNode::Decision { question, .. } => question.to_string(),
+        }
+    }
+
+    fn children(&self) -> Vec<(String, &MultiNode)> {
+        match self {
+            MultiNode::Leaf { .. } => Vec::new(),
+            MultiNode::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } => vec![("yes".to_string(), &**true_branch), ("no".to_string(), &**false_branch)],
+        }
+    }
+}
+
+/// Follow the tree down to a leaf and return the most likely label for each output.
+pub fn classify<T>(row: &T, node: &MultiNode) -> Result<Vec<String>>
+where
+    T: DataRow,
+{
+    node.predict_outputs(row)
+}
+
+impl MultiOutputModel for MultiNode {
+    /// The share of the weight of the training rows in the row's leaf with each label, for each output.
+    fn predict_proba_outputs<T>(&self, row: &T) -> Result<Vec<HashMap<String, f64>>>
+    where
+        T: DataRow,
+    {
+        match self {
+            MultiNode::Leaf { predictions } => Ok(predictions
+                .iter()
+                .map(|counts| {
+                    let total: f64 = counts.values().sum();
+                    counts.iter().map(|(label, count)| (label.clone(), count / total)).collect()
+                })
+                .collect()),
+            MultiNode::Decision {
+                question,
+                true_branch,
+                false_branch,
+            } => {
+                if question.matchit(row)? {
+                    true_branch.predict_proba_outputs(row)
+                } else {
+                    false_branch.predict_proba_outputs(row)
+                }
+            }
+        }
+    }
+}
+
+/// A row that has one of the outputs of another as its label, to choose the categories to split on for it.
+struct Output<'a, T: 'a> {
+    row: &'a T,
+    label: String,
+}
+
+impl<'a, T> DataRow for Output<'a, T>
+where
+    T: DataRow,
+{
+    fn id(&self) -> i64 {
+        self.row.id()
+    }
+
+    fn name(&self, col: usize) -> Result<String> {
+        self.row.name(col)
+    }
+
+    fn value(&self, col: usize) -> Result<Col> {
+        self.row.value(col)
+    }
+
+    fn label(&self) -> Result<String> {
+        Ok(self.label.clone())
+    }
+
+    fn len(&self) -> usize {
+        self.row.len()
+    }
+
+    fn weight(&self) -> f64 {
+        self.row.weight()
+    }
+}
+
+/// The total weight of the rows, and the weight of each label for each output.
+fn output_counts<'a, T, I>(rows: I, outputs: usize) -> Result<(f64, Vec<BTreeMap<String, f64>>)>
+where
+    T: DataRow + 'a,
+    I: Iterator<Item = &'a &'a T>,
+{
+    let mut total = 0.0;
+    let mut counts = vec![BTreeMap::new(); outputs];
+    for row in rows {
+        let labels = row.labels()?;
+        if labels.len() != outputs {
+            return Err(DecisionsError::Schema(format!(
+                "Row {} has {} labels, but the first row has {}",
+                row.id(),
+                labels.len(),
+                outputs
+            )));
+        }
+        for (counts, label) in counts.iter_mut().zip(labels) {
+            *counts.entry(label).or_insert(0.0) += row.weight();
+        }
+        total += row.weight();
+    }
+    Ok((total, counts))
+}
+
+/// The mean impurity of the outputs, and the total weight of the rows.
+fn impurity<'a, T, I>(rows: I, outputs: usize, criterion: Criterion) -> Result<(f64, f64)>
+where
+    T: DataRow + 'a,
+    I: Iterator<Item = &'a &'a T>,
+{
+    let (len, counts) = output_counts(rows, outputs)?;
+    let total: f64 = counts
+        .iter()
+        .map(|counts| match criterion {
+            Criterion::Gini => gini(len, counts),
+            Criterion::Entropy => entropy(len, counts),
+        })
+        .sum();
+    Ok((len, total / outputs as f64))
+}
+
+///
+/// The questions worth asking of a column. For a text column these are the
+/// groups of categories the single output trees would try for each output.
+///
+fn predicates<T>(rows: &[&T], col: usize, outputs: usize, params: &MultiOutputParams) -> Result<Vec<Predicate>>
+where
+    T: DataRow,
+{
+    let values = column_values(rows.iter(), col)?;
+    if !values.iter().any(|val| matches!(val, Col::Text(_))) {
+        return Ok(thresholds(&values));
+    }
+
+    let tree_params = TreeParams {
+        criterion: params.criterion,
+        ..TreeParams::default()
+    };
+    let labels = rows.iter().map(|row| row.labels()).collect::<Result<Vec<Vec<String>>>>()?;
+    let mut predicates = BTreeMap::new();
+    for output in 0..outputs {
+        let output_rows: Vec<Output<T>> = rows
+            .iter()
+            .zip(labels.iter())
+            .map(|(row, labels)| Output {
+                row: *row,
+                label: labels[output].clone(),
+            })
+            .collect();
+        let output_rows: Vec<&Output<T>> = output_rows.iter().collect();
+        for predicate in category_subsets(&output_rows, col, &tree_params)? {
+            // Predicates aren't ordered, but their text is unique.
+            predicates.insert(predicate.to_string(), predicate);
+        }
+    }
+    Ok(predicates.into_values().collect())
+}
+
+/// Find the split that leaves the lowest mean impurity over the outputs.
+fn find_best_split<T>(rows: &[&T], outputs: usize, params: &MultiOutputParams) -> Result<(f64, Option<Question>)>
+where
+    T: DataRow,
+{
+    let mut best_gain = 0.0;
+    let mut best_question = None;
+    let (_, current) = impurity(rows.iter(), outputs, params.criterion)?;
+
+    for col in 0..rows[0].len() {
+        for predicate in predicates(rows, col, outputs, params)? {
+            let question = Question::with_predicate(rows[0].name(col)?, col, predicate);
+            let (true_rows, false_rows) = partition(rows, &question)?;
+            if true_rows.len() < params.min_samples_leaf.max(1) || false_rows.len() < params.min_samples_leaf.max(1) {
+                continue;
+            }
+
+            let (true_len, true_impurity) = impurity(true_rows.iter(), outputs, params.criterion)?;
+            let (false_len, false_impurity) = impurity(false_rows.iter(), outputs, params.criterion)?;
+            if true_len + false_len <= 0.0 {
+                continue;
+            }
+            let p = true_len / (true_len + false_len);
+            let gain = current - p * true_impurity - (1.0 - p) * false_impurity;
+            if gain > best_gain {
+                best_gain = gain;
+                best_question = Some(question);
+            }
+        }
+    }
+
+    Ok((best_gain, best_question))
+}
+
+fn grow<T>(rows: &[&T], outputs: usize, depth: Option<u64>, params: &MultiOutputParams) -> Result<MultiNode>
+where
+    T: DataRow,
+{
+    let leaf = || -> Result<MultiNode> {
+        let (_, counts) = output_counts(rows.iter(), outputs)?;
+        Ok(MultiNode::Leaf {
+            predictions: counts.into_iter().map(|counts| counts.into_iter().collect()).collect(),
+        })
+    };
+
+    let depth = depth.map(|d| d.saturating_sub(1));
+    if depth == Some(0) || rows.len() < params.min_samples_split {
+        return leaf();
+    }
+    match find_best_split(rows, outputs, params)? {
+        (gain, Some(question)) if gain > 0.0 => {
+            let (true_rows, false_rows) = partition(rows, &question)?;
+            Ok(MultiNode::Decision {
+                question,
+                true_branch: Box::new(grow(&true_rows, outputs, depth, params)?),
+                false_branch: Box::new(grow(&false_rows, outputs, depth, params)?),
+            })
+        }
+        _ => leaf(),
+    }
+}
+
+impl MultiOutputParams {
+    /// Grow a tree that predicts each of the rows' labels. Every row needs the same number of them.
+    pub fn fit<T>(&self, rows: &[&T]) -> Result<MultiNode>
+    where
+        T: DataRow,
+    {
+        let outputs = match rows.first() {
+            Some(row) => row.labels()?.len(),
+            None => return Err(DecisionsError::Config("A tree needs at least one row to train on".to_string())),
+        };
+        grow(rows, outputs, self.max_depth, self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metrics::{accuracy, output_accuracy, output_brier};
+    use row::no_column;
+    use row::test::{points, Point};
+    use tree::{build_tree, classify as classify_one};
+
+    /// A point with a second label for which half it is in.
+    struct Tagged<'a> {
+        point: &'a Point,
+        colour: &'static str,
+    }
+
+    impl<'a> DataRow for Tagged<'a> {
+        fn id(&self) -> i64 {
+            self.point.id
+        }
+        fn name(&self, col: usize) -> Result<String> {
+            match col {
+                0 | 1 => self.point.name(col),
+                2 => Ok("Colour".to_string()),
+                _ => Err(no_column(self, col)),
+            }
+        }
+        fn value(&self, col: usize) -> Result<Col> {
+            match col {
+                0 | 1 => self.point.value(col),
+                2 => Ok(Col::Text(self.colour.to_string())),
+                _ => Err(no_column(self, col)),
+            }
+        }
+        fn label(&self) -> Result<String> {
+            Ok(self.point.label.clone())
+        }
+        fn labels(&self) -> Result<Vec<String>> {
+            let side = if self.point.x >= 5 { "Right" } else { "Left" };
+            Ok(vec![self.point.label.clone(), side.to_string()])
+        }
+        fn len(&self) -> usize {
+            3
+        }
+    }
+
+    fn tagged(points: &[Point]) -> Vec<Tagged<'_>> {
+        let colours = ["Red", "Green", "Blue"];
+        points
+            .iter()
+            .map(|point| Tagged {
+                point,
+                colour: colours[point.id as usize % 3],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_outputs() {
+        let data = points(200, 1);
+        let rows = tagged(&data);
+        let rows: Vec<&Tagged> = rows.iter().collect();
+
+        let tree = MultiOutputParams {
+            max_depth: Some(5),
+            ..MultiOutputParams::default()
+        }
+        .fit(&rows)
+        .unwrap();
+
+        // The side is easy to get right, the label is noisy.
+        let accuracy_per_output = output_accuracy(&tree, &rows).unwrap();
+        assert_eq!(2, accuracy_per_output.len());
+        assert_eq!(1.0, accuracy_per_output[1]);
+        assert!(accuracy_per_output[0] > 0.8);
+        let brier = output_brier(&tree, &rows).unwrap();
+        assert_eq!(0.0, brier[1]);
+        assert!(brier[0] > 0.0);
+
+        // Each output can be pulled out as a regular tree.
+        let first = tree.output(0).unwrap();
+        assert_eq!(accuracy_per_output[0], accuracy(&first, &rows).unwrap());
+        for row in &rows {
+            let predicted = classify(*row, &tree).unwrap();
+            assert_eq!(2, predicted.len());
+            assert_eq!(classify_one(*row, &first).unwrap(), predicted[0]);
+        }
+        assert!(tree.output(2).is_err());
+        assert!(tree.to_dot().contains("Right"));
+    }
+
+    #[test]
+    fn test_mean_impurity() {
+        let data = points(4, 1);
+        let rows = tagged(&data);
+        let rows: Vec<&Tagged> = rows.iter().collect();
+        let (len, counts) = output_counts(rows.iter(), 2).unwrap();
+        assert_eq!(4.0, len);
+        let expected = (gini(len, &counts[0]) + gini(len, &counts[1])) / 2.0;
+        assert_eq!((4.0, expected), impurity(rows.iter(), 2, Criterion::Gini).unwrap());
+
+        // Every row needs the same number of labels.
+        assert!(output_counts(rows.iter(), 3).is_err());
+    }
+
+    #[test]
+    fn test_single_output() {
+        // With one label it grows the same tree as the single output trees.
+        let data = points(100, 2);
+        let rows: Vec<&Point> = data.iter().collect();
+        let multi = MultiOutputParams::default().fit(&rows).unwrap();
+        let single = build_tree(&rows, None).unwrap();
+        assert_eq!(
+            serde_json::to_value(&single).unwrap(),
+            serde_json::to_value(multi.output(0).unwrap()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,744 @@
+//!
+//! Trees whose decisions have a branch for each category of a text column,
+//! or for each bin of a numeric one, in the style of ID3, C4.5 and CHAID.
+//!
+//! ID3 splits on the column with the most information gain, and C4.5 on the
+//! one with the best gain ratio out of those with at least the average gain,
+//! which stops it favouring columns with lots of categories. CHAID first
+//! merges the categories of each column whose labels aren't significantly
+//! different by a chi-squared test, then splits on the column whose test is
+//! the most significant once it is adjusted for all the ways the categories
+//! could have been merged.
+//!
+//! A row goes down the first branch whose question it matches. Missing values,
+//! and categories that weren't in the training rows, match none of them and get
+//! the prediction of the decision itself.
+//!
+
+use dot::{self, DotNode};
+use error::Result;
+use model::{Estimator, Model};
+use question::{Predicate, Question};
+use row::{Col, DataRow};
+use std::collections::{BTreeMap, HashMap};
+use tree::{class_counts_iter, entropy};
+
+/// How to choose the column to split on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Criterion {
+    /// Information gain, as in ID3.
+    Gain,
+    /// Gain ratio, as in C4.5.
+    GainRatio,
+    /// The Bonferroni adjusted chi-squared test of merged categories, as in CHAID.
+    ChiSquared,
+}
+
+///
+/// The settings that control how a multiway tree is grown.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultiwayParams {
+    /// The maximum depth of the tree. None if we want it as deep as we can.
+    pub max_depth: Option<u64>,
+    /// Nodes with fewer rows than this become leaves.
+    pub min_samples_split: usize,
+    /// Splits that would leave fewer rows than this in any branch aren't considered.
+    pub min_samples_leaf: usize,
+    pub criterion: Criterion,
+    /// Numeric columns are cut into at most this many bins with about the same number of rows.
+    pub bins: usize,
+    /// CHAID merges two groups of categories when the test of their difference has a p-value above this.
+    pub merge_alpha: f64,
+    /// CHAID only splits when the adjusted p-value is below this.
+    pub split_alpha: f64,
+}
+
+impl Default for MultiwayParams {
+    fn default() -> Self {
+        MultiwayParams {
+            max_depth: None,
+            min_samples_split: 2,
+            min_samples_leaf: 1,
+            criterion: Criterion::GainRatio,
+            bins: 5,
+            merge_alpha: 0.05,
+            split_alpha: 0.05,
+        }
+    }
+}
+
+/// A node in a multiway tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MultiwayNode {
+    /// The total weight of the training rows with each label that ended up in the leaf.
+    Leaf { predictions: HashMap<String, f64> },
+    /// A branch for each question, along with the weight of the training rows at the
+    /// decision for rows that don't match any of them.
+    Decision {
+        branches: Vec<(Question, MultiwayNode)>,
+        predictions: HashMap<String, f64>,
+    },
+}
+
+impl MultiwayNode {
+    /// The number of levels in the tree, so a tree that is just a leaf has a depth of 1.
+    pub fn depth(&self) -> usize {
+        match self {
+            MultiwayNode::Leaf { .. } => 1,
+            MultiwayNode::Decision { branches, .. } => {
+                1 + branches.iter().map(|(_, node)| node.depth()).max().unwrap_or(0)
+            }
+        }
+    }
+
+    /// The number of leaves in the tree.
+    pub fn leaf_count(&self) -> usize {
+        match self {
+            MultiwayNode::Leaf { .. } => 1,
+            MultiwayNode::Decision { branches, .. } => branches.iter().map(|(_, node)| node.leaf_count()).sum(),
+        }
+    }
+
+    /// Create a dot format for our tree.
+    pub fn to_dot(&self) -> String {
+        dot::to_dot(self)
+    }
+
+    /// Follow the tree down as far as the row goes and return the counts of each label there.
+    fn counts<T>(&self, row: &T) -> Result<&HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        match self {
+            MultiwayNode::Leaf { predictions } => Ok(predictions),
+            MultiwayNode::Decision { branches, predictions } => {
+                for (question, node) in branches {
+                    if question.matchit(row)? {
+                        return node.counts(row);
+                    }
+                }
+                Ok(predictions)
+            }
+        }
+    }
+}
+
+impl DotNode for MultiwayNode {
+    fn label(&self) -> String {
+        match self {
+            MultiwayNode::Leaf { predictions } => format!("{:?}", predictions).replace("\"", ""),
+            MultiwayNode::Decision { branches, .. } => branches
+                .first()
+                .map(|(question, _)| question.field_name().to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn children(&self) -> Vec<(String, &MultiwayNode)> {
+        match self {
+            MultiwayNode::Leaf { .. } => Vec::new(),
+            MultiwayNode::Decision { branches, .. } => branches
+                .iter()
+                .map(|(question, node)| (question.predicate().to_string(), node))
+                .collect(),
+        }
+    }
+}
+
+impl Model for MultiwayNode {
+    /// The share of the weight of the training rows where the row ends up that has each label.
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        let counts = self.counts(row)?;
+        let total: f64 = counts.values().sum();
+        Ok(counts
+            .iter()
+            .map(|(label, count)| (label.clone(), count / total))
+            .collect())
+    }
+}
+
+impl Estimator for MultiwayParams {
+    type Model = MultiwayNode;
+
+    fn fit<T>(&self, rows: &[&T]) -> Result<MultiwayNode>
+    where
+        T: DataRow,
+    {
+        grow(rows, self, self.max_depth)
+    }
+}
+
+/// Whether the groups of a column have an order, which limits CHAID to merging neighbours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Nominal,
+    Ordinal,
+}
+
+///
+/// Rows that go down the same branch. Text groups hold their categories,
+/// numeric groups the lowest and highest value in the bin.
+///
+struct Group<'a, T: 'a> {
+    values: Vec<Col>,
+    rows: Vec<&'a T>,
+    /// The number of categories or bins the group started as, for the Bonferroni adjustment.
+    merged: usize,
+}
+
+impl<'a, T> Group<'a, T>
+where
+    T: DataRow,
+{
+    fn counts(&self) -> Result<(f64, BTreeMap<String, f64>)> {
+        class_counts_iter(self.rows.iter())
+    }
+}
+
+/// A possible split, with how good it is by the criterion; higher is better.
+struct Split<'a, T: 'a> {
+    col: usize,
+    kind: Kind,
+    groups: Vec<Group<'a, T>>,
+    gain: f64,
+    score: f64,
+}
+
+fn grow<T>(rows: &[&T], params: &MultiwayParams, max_depth: Option<u64>) -> Result<MultiwayNode>
+where
+    T: DataRow,
+{
+    let (_, counts) = class_counts_iter(rows.iter())?;
+    let predictions: HashMap<String, f64> = counts.into_iter().collect();
+
+    let depth = max_depth.map(|d| d.saturating_sub(1));
+    if depth == Some(0) || rows.is_empty() || rows.len() < params.min_samples_split {
+        return Ok(MultiwayNode::Leaf { predictions });
+    }
+
+    match best_split(rows, params)? {
+        Some(split) => {
+            let name = rows[0].name(split.col)?;
+            let predicates = predicates(split.kind, &split.groups);
+            let mut branches = Vec::new();
+            for (predicate, group) in predicates.into_iter().zip(split.groups.iter()) {
+                let question = Question::with_predicate(name.clone(), split.col, predicate);
+                branches.push((question, grow(&group.rows, params, depth)?));
+            }
+            Ok(MultiwayNode::Decision { branches, predictions })
+        }
+        None => Ok(MultiwayNode::Leaf { predictions }),
+    }
+}
+
+/// The best column to split on, if any of them are worth it.
+fn best_split<'a, T>(rows: &[&'a T], params: &MultiwayParams) -> Result<Option<Split<'a, T>>>
+where
+    T: DataRow,
+{
+    let total: f64 = rows.iter().map(|row| row.weight()).sum();
+    let mut splits = Vec::new();
+
+    for col in 0..rows[0].len() {
+        let (kind, mut groups) = column_groups(rows, col, params.bins)?;
+        if params.criterion == Criterion::ChiSquared {
+            groups = merge(kind, groups, params.merge_alpha)?;
+        }
+        if groups.len() < 2 || groups.iter().any(|group| group.rows.len() < params.min_samples_leaf) {
+            continue;
+        }
+
+        let tables = groups.iter().map(|group| group.counts()).collect::<Result<Vec<_>>>()?;
+        let known: f64 = tables.iter().map(|(len, _)| len).sum();
+        if known <= 0.0 {
+            continue;
+        }
+
+        // Rows missing the column go down none of the branches, so the gain only counts for the rest.
+        let (_, all) = class_counts_iter(groups.iter().flat_map(|group| group.rows.iter()))?;
+        let after: f64 = tables.iter().map(|(len, counts)| len / known * entropy(*len, counts)).sum();
+        let gain = known / total * (entropy(known, &all) - after);
+
+        let score = match params.criterion {
+            Criterion::Gain => gain,
+            Criterion::GainRatio => {
+                let split_info: f64 = tables
+                    .iter()
+                    .map(|(len, _)| len / known)
+                    .filter(|p| *p > 0.0)
+                    .map(|p| -p * p.log2())
+                    .sum();
+                if split_info > 0.0 {
+                    gain / split_info
+                } else {
+                    0.0
+                }
+            }
+            Criterion::ChiSquared => {
+                let original: usize = groups.iter().map(|group| group.merged).sum();
+                let p = chi_squared(&tables) * bonferroni(kind, original, groups.len());
+                if p.min(1.0) >= params.split_alpha {
+                    continue;
+                }
+                -p
+            }
+        };
+
+        splits.push(Split { col, kind, groups, gain, score });
+    }
+
+    // C4.5 only considers the columns with at least the average gain.
+    let useful: Vec<&Split<T>> = splits.iter().filter(|split| split.gain > 0.0).collect();
+    let average = if params.criterion == Criterion::GainRatio && !useful.is_empty() {
+        useful.iter().map(|split| split.gain).sum::<f64>() / useful.len() as f64
+    } else {
+        0.0
+    };
+
+    let mut best: Option<Split<T>> = None;
+    for split in splits {
+        if split.gain <= 0.0 || split.gain < average - 1e-12 {
+            continue;
+        }
+        if best.as_ref().is_none_or(|best| split.score > best.score) {
+            best = Some(split);
+        }
+    }
+    Ok(best)
+}
+
+///
+/// Group the rows by the column, leaving out those that are missing it.
+/// Text columns have a group for each category, numeric ones a group for
+/// each bin, cut where the rows sorted by the column are evenly spaced.
+///
+fn column_groups<'a, T>(rows: &[&'a T], col: usize, bins: usize) -> Result<(Kind, Vec<Group<'a, T>>)>
+where
+    T: DataRow,
+{
+    let mut values = Vec::new();
+    for row in rows {
+        match row.value(col)? {
+            Col::Null => (),
+            value => values.push((value, *row)),
+        }
+    }
+
+    if values.iter().any(|(value, _)| matches!(value, Col::Text(_))) {
+        let mut categories: BTreeMap<Col, Vec<&T>> = BTreeMap::new();
+        for (value, row) in values {
+            categories.entry(value).or_default().push(row);
+        }
+        let groups = categories
+            .into_iter()
+            .map(|(value, rows)| Group {
+                values: vec![value],
+                rows,
+                merged: 1,
+            })
+            .collect();
+        return Ok((Kind::Nominal, groups));
+    }
+
+    if values.is_empty() {
+        return Ok((Kind::Ordinal, Vec::new()));
+    }
+    values.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut cuts: Vec<&Col> = (1..bins.max(1))
+        .map(|bin| &values[bin * values.len() / bins].0)
+        .collect();
+    cuts.dedup();
+
+    let mut groups: Vec<Group<T>> = Vec::new();
+    let mut next = 0;
+    for (value, row) in &values {
+        let start = groups.is_empty() || (next < cuts.len() && value >= cuts[next]);
+        if start {
+            while next < cuts.len() && value >= cuts[next] {
+                next += 1;
+            }
+            groups.push(Group {
+                values: vec![value.clone(), value.clone()],
+                rows: Vec::new(),
+                merged: 1,
+            });
+        }
+        if let Some(group) = groups.last_mut() {
+            group.values[1] = value.clone();
+            group.rows.push(*row);
+        }
+    }
+    Ok((Kind::Ordinal, groups))
+}
+
+///
+/// Merge the pair of groups that are least significantly different, for as long
+/// as the p-value of the test between them is above `alpha`. Ordinal groups can
+/// only be merged with their neighbours.
+///
+fn merge<T>(kind: Kind, mut groups: Vec<Group<T>>, alpha: f64) -> Result<Vec<Group<T>>>
+where
+    T: DataRow,
+{
+    while groups.len() > 1 {
+        let tables = groups.iter().map(|group| group.counts()).collect::<Result<Vec<_>>>()?;
+        let mut most: Option<(f64, usize, usize)> = None;
+        for a in 0..groups.len() {
+            for b in a + 1..groups.len() {
+                if kind == Kind::Ordinal && b != a + 1 {
+                    continue;
+                }
+                let p = chi_squared(&[tables[a].clone(), tables[b].clone()]);
+                if most.is_none_or(|(most, _, _)| p > most) {
+                    most = Some((p, a, b));
+                }
+            }
+        }
+
+        match most {
+            Some((p, a, b)) if p > alpha => {
+                let merged = groups.remove(b);
+                let group = &mut groups[a];
+                group.rows.extend(merged.rows);
+                group.merged += merged.merged;
+                match kind {
+                    Kind::Nominal => {
+                        group.values.extend(merged.values);
+                        group.values.sort();
+                    }
+                    // The merged bin runs from the lowest of the first to the highest of the second.
+                    Kind::Ordinal => group.values[1] = merged.values[1].clone(),
+                }
+            }
+            _ => break,
+        }
+    }
+    Ok(groups)
+}
+
+///
+/// The question for each group. Numeric bins rely on the rows trying them in order,
+/// and are cut half way between the highest value in one and the lowest in the next.
+///
+fn predicates<T>(kind: Kind, groups: &[Group<T>]) -> Vec<Predicate> {
+    match kind {
+        Kind::Nominal => groups
+            .iter()
+            .map(|group| match group.values.as_slice() {
+                [value] => Predicate::Equal(value.clone()),
+                values => Predicate::In(values.to_vec()),
+            })
+            .collect(),
+        Kind::Ordinal => {
+            let cuts: Vec<Col> = groups
+                .windows(2)
+                .map(|pair| pair[0].values[1].midpoint(&pair[1].values[0]).unwrap_or_else(|| pair[1].values[0].clone()))
+                .collect();
+            let mut predicates: Vec<Predicate> = cuts.iter().cloned().map(Predicate::Less).collect();
+            predicates.push(Predicate::AtLeast(cuts.last().cloned().unwrap_or_else(|| groups[0].values[0].clone())));
+            predicates
+        }
+    }
+}
+
+///
+/// The p-value of Pearson's chi-squared test that the labels are independent
+/// of the groups, given the weighted count of each label in each group.
+///
+fn chi_squared(tables: &[(f64, BTreeMap<String, f64>)]) -> f64 {
+    let total: f64 = tables.iter().map(|(len, _)| len).sum();
+    let mut labels: BTreeMap<&String, f64> = BTreeMap::new();
+    for (_, counts) in tables {
+        for (label, count) in counts {
+            *labels.entry(label).or_insert(0.0) += count;
+        }
+    }
+    labels.retain(|_, count| *count > 0.0);
+    let groups = tables.iter().filter(|(len, _)| *len > 0.0).count();
+    if total <= 0.0 || groups < 2 || labels.len() < 2 {
+        return 1.0;
+    }
+
+    let mut statistic = 0.0;
+    for (len, counts) in tables.iter().filter(|(len, _)| *len > 0.0) {
+        for (label, label_total) in &labels {
+            let expected = len * label_total / total;
+            let observed = counts.get(*label).unwrap_or(&0.0);
+            statistic += (observed - expected).powi(2) / expected;
+        }
+    }
+
+    let freedom = ((groups - 1) * (labels.len() - 1)) as f64;
+    gamma_q(freedom / 2.0, statistic / 2.0)
+}
+
+///
+/// How many ways there are to merge `original` categories into `groups`, which
+/// the p-value is multiplied by to allow for having tried them all (Kass, 1980).
+///
+fn bonferroni(kind: Kind, original: usize, groups: usize) -> f64 {
+    match kind {
+        // Choosing where to put the boundaries between neighbours.
+        Kind::Ordinal => (1..groups).fold(1.0, |ways, i| ways * (original - groups + i) as f64 / i as f64),
+        // Stirling numbers of the second kind, the ways to divide a set into `groups` non empty subsets.
+        Kind::Nominal => {
+            let mut stirling = vec![0.0; groups + 1];
+            stirling[0] = 1.0;
+            for _ in 0..original {
+                for k in (1..=groups).rev() {
+                    stirling[k] = k as f64 * stirling[k] + stirling[k - 1];
+                }
+                stirling[0] = 0.0;
+            }
+            stirling[groups]
+        }
+    }
+}
+
+/// The log of the gamma function, from the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut y = x;
+    let mut series = 1.000000000190015;
+    for coefficient in &COEFFICIENTS {
+        y += 1.0;
+        series += coefficient / y;
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+///
+/// The upper regularised incomplete gamma function Q(a, x), which gives the
+/// chance of a chi-squared statistic of at least `2x` with `2a` degrees of freedom.
+/// Uses the series for small `x` and the continued fraction otherwise.
+///
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-14;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut n = a;
+        for _ in 0..500 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        (1.0 - sum * front).max(0.0)
+    } else {
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        (front * h).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use error::Result;
+    use metrics::accuracy;
+    use row::no_column;
+    use row::test::{points, Point};
+
+    #[derive(Debug)]
+    struct Passenger {
+        id: i64,
+        port: Option<&'static str>,
+        ticket: String,
+        label: &'static str,
+    }
+
+    impl DataRow for Passenger {
+        fn id(&self) -> i64 {
+            self.id
+        }
+        fn name(&self, col: usize) -> Result<String> {
+            match col {
+                0 => Ok("Ticket".to_string()),
+                1 => Ok("Port".to_string()),
+                _ => Err(no_column(self, col)),
+            }
+        }
+        fn value(&self, col: usize) -> Result<Col> {
+            match col {
+                0 => Ok(Col::Text(self.ticket.clone())),
+                1 => Ok(self.port.map_or(Col::Null, |port| Col::Text(port.to_string()))),
+                _ => Err(no_column(self, col)),
+            }
+        }
+        fn label(&self) -> Result<String> {
+            Ok(self.label.to_string())
+        }
+        fn len(&self) -> usize {
+            2
+        }
+    }
+
+    /// Passengers from A and B live and from C and D die. Every ticket is different.
+    fn passengers() -> Vec<Passenger> {
+        (0..80)
+            .map(|id| {
+                let port = ["A", "B", "C", "D"][id as usize % 4];
+                let lived = port == "A" || port == "B";
+                Passenger {
+                    id,
+                    port: Some(port),
+                    ticket: format!("T{}", id),
+                    label: if lived { "Lived" } else { "Died" },
+                }
+            })
+            .collect()
+    }
+
+    fn root(tree: &MultiwayNode) -> Vec<&Question> {
+        match tree {
+            MultiwayNode::Decision { branches, .. } => branches.iter().map(|(question, _)| question).collect(),
+            MultiwayNode::Leaf { .. } => panic!("Expected a decision"),
+        }
+    }
+
+    #[test]
+    fn test_gain_ratio() {
+        let data = passengers();
+        let data: Vec<&Passenger> = data.iter().collect();
+        let stump = |criterion| MultiwayParams {
+            max_depth: Some(2),
+            criterion,
+            ..MultiwayParams::default()
+        };
+
+        // A different ticket for everyone separates them perfectly, so ID3 splits on it.
+        let id3 = stump(Criterion::Gain).fit(&data).unwrap();
+        assert_eq!("Ticket", root(&id3)[0].field_name());
+        assert_eq!(80, root(&id3).len());
+        assert_eq!(1.0, accuracy(&id3, &data).unwrap());
+
+        // C4.5 sees that the port does as well with far fewer branches.
+        let c45 = stump(Criterion::GainRatio).fit(&data).unwrap();
+        let questions = root(&c45);
+        assert_eq!(4, questions.len());
+        assert_eq!("Port", questions[0].field_name());
+        assert_eq!(&Predicate::Equal(Col::Text("A".to_string())), questions[0].predicate());
+        assert_eq!(1.0, accuracy(&c45, &data).unwrap());
+    }
+
+    #[test]
+    fn test_chaid() {
+        let data = passengers();
+        let data: Vec<&Passenger> = data.iter().collect();
+        let params = MultiwayParams {
+            criterion: Criterion::ChiSquared,
+            ..MultiwayParams::default()
+        };
+        let tree = params.fit(&data).unwrap();
+
+        // The ports that are alike are merged, and the tickets are too rare to be significant.
+        let text = |values: &[&str]| Predicate::In(values.iter().map(|v| Col::Text(v.to_string())).collect());
+        let predicates: Vec<&Predicate> = root(&tree).iter().map(|question| question.predicate()).collect();
+        assert_eq!(vec![&text(&["A", "B"]), &text(&["C", "D"])], predicates);
+        assert_eq!(2, tree.depth());
+
+        // A port we haven't seen, or none, gets the prediction for everyone.
+        let unknown = Passenger {
+            id: 100,
+            port: None,
+            ticket: "T1".to_string(),
+            label: "",
+        };
+        assert_eq!(0.5, tree.predict_proba(&unknown).unwrap()["Lived"]);
+
+        // A column with nothing in it can't be split on.
+        let nowhere: Vec<Passenger> = passengers().into_iter().map(|p| Passenger { port: None, ..p }).collect();
+        let nowhere: Vec<&Passenger> = nowhere.iter().collect();
+        assert!(matches!(params.fit(&nowhere).unwrap(), MultiwayNode::Leaf { .. }));
+
+        let dot = tree.to_dot();
+        assert!(dot.contains("label=\"Port\""));
+        assert!(dot.contains("label=\"in {A, B}\""));
+    }
+
+    #[test]
+    fn test_numeric_bins() {
+        let data = points(200, 3);
+        let data: Vec<&Point> = data.iter().collect();
+        let params = MultiwayParams {
+            max_depth: Some(2),
+            bins: 4,
+            ..MultiwayParams::default()
+        };
+        let tree = params.fit(&data).unwrap();
+
+        // Each bin but the last is below the start of the next, and the last is the rest.
+        let questions = root(&tree);
+        assert_eq!(4, questions.len());
+        for question in &questions[..3] {
+            assert!(matches!(question.predicate(), Predicate::Less(_)));
+        }
+        assert!(matches!(questions[3].predicate(), Predicate::AtLeast(_)));
+        assert!(accuracy(&tree, &data).unwrap() > 0.7);
+    }
+
+    #[test]
+    fn test_chi_squared() {
+        // The 5% critical values for one and two degrees of freedom.
+        assert!((gamma_q(0.5, 3.841 / 2.0) - 0.05).abs() < 1e-4);
+        assert!((gamma_q(1.0, 5.991 / 2.0) - 0.05).abs() < 1e-4);
+        assert!((gamma_q(5.0, 2.0) - 0.947347).abs() < 1e-5);
+
+        let table = |yes: f64, no: f64| {
+            let mut counts = BTreeMap::new();
+            counts.insert("Yes".to_string(), yes);
+            counts.insert("No".to_string(), no);
+            (yes + no, counts)
+        };
+        assert_eq!(1.0, chi_squared(&[table(10.0, 10.0), table(5.0, 5.0)]));
+        assert!(chi_squared(&[table(30.0, 0.0), table(0.0, 30.0)]) < 1e-10);
+
+        // Four categories can be put in two groups seven ways, or three in order.
+        assert_eq!(7.0, bonferroni(Kind::Nominal, 4, 2));
+        assert_eq!(3.0, bonferroni(Kind::Ordinal, 4, 2));
+        assert_eq!(1.0, bonferroni(Kind::Nominal, 4, 4));
+        assert_eq!(1.0, bonferroni(Kind::Ordinal, 4, 4));
+    }
+}
@@ -0,0 +1,650 @@
+use prost::Message;
+use question::{Predicate, Question};
+use row::Col;
+use schema::{FieldKind, Schema};
+use tree::Node;
+
+///
+/// The bits of the ONNX protobuf schema that we need to describe a tree ensemble.
+/// Tags match `onnx.proto` so any ONNX runtime can read the result.
+///
+pub mod proto {
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ModelProto {
+        #[prost(int64, tag = "1")]
+        pub ir_version: i64,
+        #[prost(string, tag = "2")]
+        pub producer_name: String,
+        #[prost(string, tag = "3")]
+        pub producer_version: String,
+        #[prost(message, optional, tag = "7")]
+        pub graph: Option<GraphProto>,
+        #[prost(message, repeated, tag = "8")]
+        pub opset_import: Vec<OperatorSetIdProto>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct OperatorSetIdProto {
+        #[prost(string, tag = "1")]
+        pub domain: String,
+        #[prost(int64, tag = "2")]
+        pub version: i64,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct GraphProto {
+        #[prost(message, repeated, tag = "1")]
+        pub node: Vec<NodeProto>,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(message, repeated, tag = "11")]
+        pub input: Vec<ValueInfoProto>,
+        #[prost(message, repeated, tag = "12")]
+        pub output: Vec<ValueInfoProto>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct NodeProto {
+        #[prost(string, repeated, tag = "1")]
+        pub input: Vec<String>,
+        #[prost(string, repeated, tag = "2")]
+        pub output: Vec<String>,
+        #[prost(string, tag = "3")]
+        pub name: String,
+        #[prost(string, tag = "4")]
+        pub op_type: String,
+        #[prost(message, repeated, tag = "5")]
+        pub attribute: Vec<AttributeProto>,
+        #[prost(string, tag = "7")]
+        pub domain: String,
+    }
+
+    /// Values for `AttributeProto::r#type`.
+    pub mod attribute_type {
+        pub const FLOAT: i32 = 1;
+        pub const INT: i32 = 2;
+        pub const STRING: i32 = 3;
+        pub const FLOATS: i32 = 6;
+        pub const INTS: i32 = 7;
+        pub const STRINGS: i32 = 8;
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct AttributeProto {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(float, tag = "2")]
+        pub f: f32,
+        #[prost(int64, tag = "3")]
+        pub i: i64,
+        #[prost(bytes = "vec", tag = "4")]
+        pub s: Vec<u8>,
+        #[prost(float, repeated, tag = "7")]
+        pub floats: Vec<f32>,
+        #[prost(int64, repeated, tag = "8")]
+        pub ints: Vec<i64>,
+        #[prost(bytes = "vec", repeated, tag = "9")]
+        pub strings: Vec<Vec<u8>>,
+        #[prost(int32, tag = "20")]
+        pub r#type: i32,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ValueInfoProto {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(message, optional, tag = "2")]
+        pub r#type: Option<TypeProto>,
+    }
+
+    /// Values for `TensorType::elem_type`.
+    pub mod data_type {
+        pub const FLOAT: i32 = 1;
+        pub const STRING: i32 = 8;
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct TypeProto {
+        #[prost(message, optional, tag = "1")]
+        pub tensor_type: Option<TensorType>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct TensorType {
+        #[prost(int32, tag = "1")]
+        pub elem_type: i32,
+        #[prost(message, optional, tag = "2")]
+        pub shape: Option<TensorShapeProto>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct TensorShapeProto {
+        #[prost(message, repeated, tag = "1")]
+        pub dim: Vec<Dimension>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Dimension {
+        #[prost(int64, optional, tag = "1")]
+        pub dim_value: Option<i64>,
+        #[prost(string, optional, tag = "2")]
+        pub dim_param: Option<String>,
+    }
+}
+
+use self::proto::*;
+
+const ML_DOMAIN: &str = "ai.onnx.ml";
+
+fn ints(name: &str, values: Vec<i64>) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        ints: values,
+        r#type: attribute_type::INTS,
+        ..Default::default()
+    }
+}
+
+fn float(name: &str, value: f32) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        f: value,
+        r#type: attribute_type::FLOAT,
+        ..Default::default()
+    }
+}
+
+fn floats(name: &str, values: Vec<f32>) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        floats: values,
+        r#type: attribute_type::FLOATS,
+        ..Default::default()
+    }
+}
+
+fn strings(name: &str, values: Vec<String>) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        strings: values.into_iter().map(|s| s.into_bytes()).collect(),
+        r#type: attribute_type::STRINGS,
+        ..Default::default()
+    }
+}
+
+fn string(name: &str, value: &str) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        s: value.as_bytes().to_vec(),
+        r#type: attribute_type::STRING,
+        ..Default::default()
+    }
+}
+
+fn int(name: &str, value: i64) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        i: value,
+        r#type: attribute_type::INT,
+        ..Default::default()
+    }
+}
+
+fn dim_param(name: &str) -> Dimension {
+    Dimension {
+        dim_value: None,
+        dim_param: Some(name.to_string()),
+    }
+}
+
+fn dim_value(value: i64) -> Dimension {
+    Dimension {
+        dim_value: Some(value),
+        dim_param: None,
+    }
+}
+
+fn tensor(name: &str, elem_type: i32, dim: Vec<Dimension>) -> ValueInfoProto {
+    ValueInfoProto {
+        name: name.to_string(),
+        r#type: Some(TypeProto {
+            tensor_type: Some(TensorType {
+                elem_type,
+                shape: Some(TensorShapeProto { dim }),
+            }),
+        }),
+    }
+}
+
+fn node(op_type: &str, domain: &str, input: Vec<String>, output: Vec<String>, attribute: Vec<AttributeProto>) -> NodeProto {
+    NodeProto {
+        name: output[0].clone(),
+        op_type: op_type.to_string(),
+        domain: domain.to_string(),
+        input,
+        output,
+        attribute,
+    }
+}
+
+/// The value a text column is compared with, the index of the category
+/// in the schema. Text that isn't a category sits half way between its neighbours.
+fn category_value(categories: Option<&[String]>, text: &str) -> f32 {
+    let categories = categories.unwrap_or_default();
+    match categories.binary_search_by(|c| c.as_str().cmp(text)) {
+        Ok(idx) => idx as f32,
+        Err(idx) => idx as f32 - 0.5,
+    }
+}
+
+///
+/// Rewrite a decision whose predicate isn't a simple comparison into ones that are,
+/// or None if it already is. A negation swaps the branches, a range checks each
+/// end in turn and a set checks each value in turn. The branches that several
+/// checks lead to are copied, as every node of an ONNX tree has one parent.
+///
+fn lower(node: &Node) -> Option<Node> {
+    let (question, true_branch, false_branch) = match node {
+        Node::Decision {
+            question,
+            true_branch,
+            false_branch,
+        } => (question, &**true_branch, &**false_branch),
+        Node::Leaf { .. } => return None,
+    };
+    let ask = |predicate: Predicate| Question::with_predicate(question.field_name().to_string(), question.col(), predicate);
+
+    match question.predicate() {
+        Predicate::Not(inner) => Some(Node::new_decision(
+            ask((**inner).clone()),
+            false_branch.clone(),
+            true_branch.clone(),
+        )),
+        Predicate::Between { low, high } => Some(Node::new_decision(
+            ask(Predicate::AtLeast(low.clone())),
+            Node::new_decision(ask(Predicate::AtMost(high.clone())), true_branch.clone(), false_branch.clone()),
+            false_branch.clone(),
+        )),
+        Predicate::In(values) => Some(match values.split_first() {
+            None => false_branch.clone(),
+            Some((first, [])) => Node::new_decision(ask(Predicate::Equal(first.clone())), true_branch.clone(), false_branch.clone()),
+            Some((first, rest)) => Node::new_decision(
+                ask(Predicate::Equal(first.clone())),
+                true_branch.clone(),
+                Node::new_decision(ask(Predicate::In(rest.to_vec())), true_branch.clone(), false_branch.clone()),
+            ),
+        }),
+        _ => None,
+    }
+}
+
+/// The flattened attribute arrays of a `TreeEnsembleClassifier`.
+#[derive(Default)]
+struct Ensemble {
+    nodes_treeids: Vec<i64>,
+    nodes_nodeids: Vec<i64>,
+    nodes_featureids: Vec<i64>,
+    nodes_modes: Vec<String>,
+    nodes_values: Vec<f32>,
+    nodes_truenodeids: Vec<i64>,
+    nodes_falsenodeids: Vec<i64>,
+    nodes_missing_value_tracks_true: Vec<i64>,
+    class_treeids: Vec<i64>,
+    class_nodeids: Vec<i64>,
+    class_ids: Vec<i64>,
+    class_weights: Vec<f32>,
+}
+
+impl Ensemble {
+    ///
+    /// Add a node, and all its children, returning the id given to the node.
+    ///
+    /// Text questions compare against the index of the category in the schema,
+    /// which is what the `LabelEncoder` in front of the ensemble turns the text into.
+    /// The categories are sorted, so ordering the indexes orders the text.
+    /// Ranges, sets and negations have no mode of their own, so they are first
+    /// rewritten as simple comparisons by `lower`.
+    ///
+    fn add(&mut self, schema: &Schema, tree_id: i64, node: &Node, weight: f32, next_id: &mut i64) -> i64 {
+        if let Some(lowered) = lower(node) {
+            return self.add(schema, tree_id, &lowered, weight, next_id);
+        }
+
+        let id = *next_id;
+        *next_id += 1;
+        let idx = self.nodes_nodeids.len();
+
+        self.nodes_treeids.push(tree_id);
+        self.nodes_nodeids.push(id);
+        self.nodes_missing_value_tracks_true.push(0);
+
+        match node {
+            Node::Leaf { predictions } => {
+                self.nodes_featureids.push(0);
+                self.nodes_modes.push("LEAF".to_string());
+                self.nodes_values.push(0.0);
+                self.nodes_truenodeids.push(0);
+                self.nodes_falsenodeids.push(0);
+
+                for (class, p) in schema.distribution(predictions).into_iter().enumerate() {
+                    self.class_treeids.push(tree_id);
+                    self.class_nodeids.push(id);
+                    self.class_ids.push(class as i64);
+                    self.class_weights.push(p as f32 * weight);
+                }
+            }
+            Node::Decision {
+                question,
+                true_branch,
+                false_branch,
+            } => {
+                let value = |value: &Col| match value {
+                    Col::Text(ref text) => category_value(schema.categories(question.col()), text),
+                    Col::Int(num) => *num as f32,
+                    Col::Float(num) => *num as f32,
+                    Col::Null => f32::NAN,
+                };
+                // Missing values are NaN, which fails every comparison, so only
+                // a null check sends them down the true branch.
+                let (mode, value, missing) = match question.predicate() {
+                    Predicate::Equal(v) => ("BRANCH_EQ", value(v), 0),
+                    Predicate::Less(v) => ("BRANCH_LT", value(v), 0),
+                    Predicate::AtMost(v) => ("BRANCH_LEQ", value(v), 0),
+                    Predicate::AtLeast(v) => ("BRANCH_GTE", value(v), 0),
+                    Predicate::Greater(v) => ("BRANCH_GT", value(v), 0),
+                    Predicate::IsNull => ("BRANCH_EQ", f32::NAN, 1),
+                    Predicate::Between { .. } | Predicate::In(_) | Predicate::Not(_) => {
+                        unreachable!("lower rewrites {} as simple questions", question)
+                    }
+                };
+                self.nodes_missing_value_tracks_true[idx] = missing;
+                self.nodes_featureids.push(question.col() as i64);
+                self.nodes_modes.push(mode.to_string());
+                self.nodes_values.push(value);
+                self.nodes_truenodeids.push(0);
+                self.nodes_falsenodeids.push(0);
+
+                let true_id = self.add(schema, tree_id, true_branch, weight, next_id);
+                let false_id = self.add(schema, tree_id, false_branch, weight, next_id);
+                self.nodes_truenodeids[idx] = true_id;
+                self.nodes_falsenodeids[idx] = false_id;
+            }
+        }
+
+        id
+    }
+
+    fn attributes(self, schema: &Schema) -> Vec<AttributeProto> {
+        vec![
+            strings("classlabels_strings", schema.classes.clone()),
+            ints("class_ids", self.class_ids),
+            ints("class_nodeids", self.class_nodeids),
+            ints("class_treeids", self.class_treeids),
+            floats("class_weights", self.class_weights),
+            ints("nodes_falsenodeids", self.nodes_falsenodeids),
+            ints("nodes_featureids", self.nodes_featureids),
+            ints("nodes_missing_value_tracks_true", self.nodes_missing_value_tracks_true),
+            strings("nodes_modes", self.nodes_modes),
+            ints("nodes_nodeids", self.nodes_nodeids),
+            ints("nodes_treeids", self.nodes_treeids),
+            ints("nodes_truenodeids", self.nodes_truenodeids),
+            floats("nodes_values", self.nodes_values),
+            string("post_transform", "NONE"),
+        ]
+    }
+}
+
+///
+/// Export an ensemble of trees as an ONNX model.
+///
+/// The model takes one `[N, 1]` input per field, named after the field. Text
+/// fields are string tensors that are mapped to their category index with a
+/// `LabelEncoder`, with unseen or empty strings becoming NaN. Numeric fields are
+/// float tensors with NaN for missing values. The columns are concatenated and
+/// fed into a `TreeEnsembleClassifier` whose class weights are the leaf
+/// probabilities divided by the number of trees, so the `probabilities` output
+/// is the average over the ensemble.
+///
+pub fn ensemble_to_onnx(trees: &[Node], schema: &Schema) -> Vec<u8> {
+    let mut inputs = Vec::new();
+    let mut nodes = Vec::new();
+    let mut columns = Vec::new();
+
+    for field in &schema.fields {
+        match field.kind {
+            FieldKind::Categorical(ref categories) => {
+                inputs.push(tensor(&field.name, data_type::STRING, vec![dim_param("N"), dim_value(1)]));
+
+                let column = format!("{}_float", field.name);
+                nodes.push(node(
+                    "LabelEncoder",
+                    ML_DOMAIN,
+                    vec![field.name.clone()],
+                    vec![column.clone()],
+                    vec![
+                        strings("keys_strings", categories.clone()),
+                        floats("values_floats", (0..categories.len()).map(|c| c as f32).collect()),
+                        float("default_float", f32::NAN),
+                    ],
+                ));
+                columns.push(column);
+            }
+            FieldKind::Continuous => {
+                inputs.push(tensor(&field.name, data_type::FLOAT, vec![dim_param("N"), dim_value(1)]));
+                columns.push(field.name.clone());
+            }
+        }
+    }
+
+    nodes.push(node("Concat", "", columns, vec!["features".to_string()], vec![int("axis", 1)]));
+
+    let mut ensemble = Ensemble::default();
+    let weight = 1.0 / trees.len() as f32;
+    for (tree_id, tree) in trees.iter().enumerate() {
+        let mut next_id = 0;
+        ensemble.add(schema, tree_id as i64, tree, weight, &mut next_id);
+    }
+
+    nodes.push(node(
+        "TreeEnsembleClassifier",
+        ML_DOMAIN,
+        vec!["features".to_string()],
+        vec![schema.target.clone(), "probabilities".to_string()],
+        ensemble.attributes(schema),
+    ));
+
+    let model = ModelProto {
+        ir_version: 6,
+        producer_name: "decisions".to_string(),
+        producer_version: env!("CARGO_PKG_VERSION").to_string(),
+        opset_import: vec![
+            OperatorSetIdProto {
+                domain: "".to_string(),
+                version: 11,
+            },
+            OperatorSetIdProto {
+                domain: ML_DOMAIN.to_string(),
+                version: 2,
+            },
+        ],
+        graph: Some(GraphProto {
+            name: "decisions".to_string(),
+            node: nodes,
+            input: inputs,
+            output: vec![
+                tensor(&schema.target, data_type::STRING, vec![dim_param("N")]),
+                tensor(
+                    "probabilities",
+                    data_type::FLOAT,
+                    vec![dim_param("N"), dim_value(schema.classes.len() as i64)],
+                ),
+            ],
+        }),
+    };
+
+    model.encode_to_vec()
+}
+
+///
+/// Export a single tree as an ONNX model.
+///
+pub fn tree_to_onnx(tree: &Node, schema: &Schema) -> Vec<u8> {
+    ensemble_to_onnx(std::slice::from_ref(tree), schema)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{training_data, Fruit};
+    use std::collections::HashMap;
+    use tree::build_tree;
+
+    fn leaf(label: &str) -> Node {
+        let mut predictions = HashMap::new();
+        predictions.insert(label.to_string(), 1.0);
+        Node::Leaf { predictions }
+    }
+
+    fn attribute<'a>(node: &'a NodeProto, name: &str) -> &'a AttributeProto {
+        node.attribute.iter().find(|a| a.name == name).unwrap()
+    }
+
+    fn text(attribute: &AttributeProto) -> Vec<String> {
+        attribute
+            .strings
+            .iter()
+            .map(|s| String::from_utf8(s.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_tree_to_onnx() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let tree = build_tree(&data, None).unwrap();
+        let schema = Schema::from_rows(&data, "Label").unwrap();
+
+        let bytes = tree_to_onnx(&tree, &schema);
+        let model = ModelProto::decode(&bytes[..]).unwrap();
+        let graph = model.graph.unwrap();
+
+        let inputs: Vec<&str> = graph.input.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(vec!["Colour", "Things", "Fruit"], inputs);
+
+        let ops: Vec<&str> = graph.node.iter().map(|n| n.op_type.as_str()).collect();
+        assert_eq!(
+            vec!["LabelEncoder", "LabelEncoder", "Concat", "TreeEnsembleClassifier"],
+            ops
+        );
+
+        let classifier = graph.node.last().unwrap();
+        assert_eq!(ML_DOMAIN, classifier.domain);
+        assert_eq!(vec!["Apple", "Grape", "Lemon"], text(attribute(classifier, "classlabels_strings")));
+
+        let modes = text(attribute(classifier, "nodes_modes"));
+        let features = &attribute(classifier, "nodes_featureids").ints;
+        let truenodes = &attribute(classifier, "nodes_truenodeids").ints;
+        let leaves = modes.iter().filter(|m| *m == "LEAF").count();
+        assert_eq!(modes.len(), 2 * leaves - 1);
+
+        for (idx, mode) in modes.iter().enumerate() {
+            match mode.as_str() {
+                "LEAF" => assert_eq!(0, truenodes[idx]),
+                "BRANCH_GTE" => assert_eq!(1, features[idx]),
+                "BRANCH_EQ" => assert!(features[idx] == 0 || features[idx] == 2),
+                _ => panic!("Unexpected mode {}", mode),
+            }
+        }
+
+        assert!(attribute(classifier, "nodes_missing_value_tracks_true").ints.iter().all(|&m| m == 0));
+
+        // One weight per class per leaf, and each leaf's weights sum to one.
+        let weights = &attribute(classifier, "class_weights").floats;
+        assert_eq!(leaves * 3, weights.len());
+        let total: f32 = weights.iter().sum();
+        assert!((total - leaves as f32).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_compound_predicates() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let schema = Schema::from_rows(&data, "Label").unwrap();
+
+        let colour = Predicate::In(vec![Col::Text("Green".to_string()), Col::Text("Yellow".to_string())]);
+        let things = Predicate::Between {
+            low: Col::Int(2),
+            high: Col::Int(4),
+        };
+        let tree = Node::new_decision(
+            Question::with_predicate("Colour".to_string(), 0, colour),
+            Node::new_decision(
+                Question::with_predicate("Things".to_string(), 1, things.negate()),
+                leaf("Lemon"),
+                leaf("Apple"),
+            ),
+            leaf("Grape"),
+        );
+
+        let model = ModelProto::decode(&tree_to_onnx(&tree, &schema)[..]).unwrap();
+        let classifier = model.graph.unwrap().node.pop().unwrap();
+        let modes = text(attribute(&classifier, "nodes_modes"));
+        let values = &attribute(&classifier, "nodes_values").floats;
+
+        // The set is a check for each colour, both leading to a copy of the range,
+        // which checks each end with the branches swapped by the negation.
+        let count = |mode: &str| modes.iter().filter(|m| *m == mode).count();
+        assert_eq!(7, count("LEAF"));
+        assert_eq!(2, count("BRANCH_EQ"));
+        assert_eq!(2, count("BRANCH_GTE"));
+        assert_eq!(2, count("BRANCH_LEQ"));
+        let equals: Vec<f32> = (0..modes.len()).filter(|&i| modes[i] == "BRANCH_EQ").map(|i| values[i]).collect();
+        assert_eq!(vec![0.0, 2.0], equals);
+        let gte = modes.iter().position(|m| m == "BRANCH_GTE").unwrap();
+        let leq = modes.iter().position(|m| m == "BRANCH_LEQ").unwrap();
+
+        let leaf_label = |idx: usize| {
+            let id = attribute(&classifier, "nodes_nodeids").ints[idx];
+            let nodes = &attribute(&classifier, "class_nodeids").ints;
+            let weights = &attribute(&classifier, "class_weights").floats;
+            let classes = &attribute(&classifier, "class_ids").ints;
+            let class = (0..nodes.len()).find(|&c| nodes[c] == id && weights[c] > 0.0).unwrap();
+            schema.classes[classes[class] as usize].clone()
+        };
+        let truenodes = &attribute(&classifier, "nodes_truenodeids").ints;
+        let falsenodes = &attribute(&classifier, "nodes_falsenodeids").ints;
+        let ids = &attribute(&classifier, "nodes_nodeids").ints;
+        let idx = |id: i64| ids.iter().position(|&i| i == id).unwrap();
+
+        // Within the range it's not a lemon, outside it is.
+        assert_eq!(leq, idx(truenodes[gte]));
+        assert_eq!("Apple", leaf_label(idx(truenodes[leq])));
+        assert_eq!("Lemon", leaf_label(idx(falsenodes[leq])));
+        assert_eq!("Lemon", leaf_label(idx(falsenodes[gte])));
+    }
+
+    #[test]
+    fn test_ensemble_to_onnx() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let trees = vec![build_tree(&data, None).unwrap(), build_tree(&data, Some(1)).unwrap()];
+        let schema = Schema::from_rows(&data, "Label").unwrap();
+
+        let model = ModelProto::decode(&ensemble_to_onnx(&trees, &schema)[..]).unwrap();
+        let classifier = model.graph.unwrap().node.pop().unwrap();
+
+        let treeids = &attribute(&classifier, "nodes_treeids").ints;
+        assert!(treeids.contains(&0));
+        assert!(treeids.contains(&1));
+
+        // Averaging over two trees halves the weights.
+        let leaves = text(attribute(&classifier, "nodes_modes"))
+            .iter()
+            .filter(|m| *m == "LEAF")
+            .count();
+        let total: f32 = attribute(&classifier, "class_weights").floats.iter().sum();
+        assert!((total - leaves as f32 / 2.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,695 @@
+//!
+//! Hyperparameter searches that spend less time on candidates that aren't
+//! doing well, and stop when they run out of budget.
+//!
+//! The resource we give a candidate is the number of cross validation folds
+//! it is scored on. Successive halving scores every candidate on a few
+//! folds, keeps the best `1 / eta` of them and scores those on `eta` times
+//! as many folds, until the survivors have been scored on every fold.
+//! Hyperband runs successive halving several times, trading off the number
+//! of candidates against how few folds they start on, so we don't have to
+//! guess how early a candidate can be judged.
+//!
+//! `tpe` is a Tree-structured Parzen Estimator. After some random trials it
+//! splits the trials so far into the best quarter and the rest, and fits a
+//! density to each hyperparameter for both groups: a smoothed histogram for
+//! a list of values, and a Gaussian around each value mixed with the uniform
+//! prior for a range. Candidates are drawn from the density of the good
+//! trials, and the one most likely to be good rather than bad is tried
+//! next. Each hyperparameter is modelled on its own.
+//!
+//! Every score is appended to a `TrialLog` as a line of json. Running the
+//! same search again, with the same seed and log, reads the scores back
+//! rather than training again, so a search that was stopped carries on
+//! from where it got to.
+//!
+
+use cross_validation::{check_folds, fold_score};
+use error::{open, DecisionsError, Result};
+use random::Rng;
+use rayon::prelude::*;
+use row::DataRow;
+use serde_json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use model::Model;
+use tuning::{Dimension, Params, Search, Space, Trial, Value};
+
+/// The number of random trials `tpe` makes before it starts modelling them.
+const STARTUP_TRIALS: usize = 10;
+/// The number of candidates `tpe` draws for each trial.
+const CANDIDATES: usize = 24;
+/// The proportion of the trials that `tpe` counts as good.
+const GAMMA: f64 = 0.25;
+
+///
+/// When to stop searching, whichever comes first.
+/// The time is only checked between rounds of successive halving and
+/// between trials of `tpe`, so a search can run over by one round.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    /// The most models to train, each being scored on a single fold.
+    /// Scores read back from the log don't count.
+    pub evaluations: Option<usize>,
+    pub time: Option<Duration>,
+}
+
+/// How much of the budget we have used.
+struct Spent {
+    budget: Budget,
+    start: Instant,
+    evaluations: usize,
+}
+
+impl Spent {
+    fn new(budget: Budget) -> Self {
+        Spent {
+            budget,
+            start: Instant::now(),
+            evaluations: 0,
+        }
+    }
+
+    fn out_of_time(&self) -> bool {
+        self.budget.time.is_some_and(|time| self.start.elapsed() >= time)
+    }
+
+    fn evaluations_left(&self) -> usize {
+        self.budget
+            .evaluations
+            .map_or(usize::MAX, |evaluations| evaluations.saturating_sub(self.evaluations))
+    }
+}
+
+/// A line of the trial log.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    folds: usize,
+    params: Params,
+    scores: Vec<f64>,
+}
+
+///
+/// The scores of each candidate on the folds it has been scored on.
+/// If it has a file, each new score is appended to it as we go.
+///
+#[derive(Debug, Default)]
+pub struct TrialLog {
+    file: Option<File>,
+    scores: HashMap<String, Vec<f64>>,
+}
+
+fn key(params: &Params) -> Result<String> {
+    Ok(serde_json::to_string(params)?)
+}
+
+impl TrialLog {
+    /// A log that is just kept in memory.
+    pub fn new() -> Self {
+        TrialLog::default()
+    }
+
+    ///
+    /// Read back the scores in the log file, if it exists, and append to it from now on.
+    ///
+    /// #Arguments
+    ///
+    /// * `path` - The log file.
+    /// * `folds` - The number of folds of this search, which has to match the log.
+    ///
+    pub fn open(path: &str, folds: usize) -> Result<Self> {
+        let mut scores = HashMap::new();
+        if Path::new(path).exists() {
+            for (idx, line) in BufReader::new(open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: Record = serde_json::from_str(&line)
+                    .map_err(|err| DecisionsError::Config(format!("{} line {}: {}", path, idx + 1, err)))?;
+                if record.folds != folds {
+                    return Err(DecisionsError::Config(format!(
+                        "{} was written by a search with {} folds, not {}",
+                        path, record.folds, folds
+                    )));
+                }
+                let known = scores.entry(key(&record.params)?).or_insert_with(Vec::new);
+                if record.scores.len() >= known.len() {
+                    *known = record.scores;
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| DecisionsError::File {
+                path: path.to_string(),
+                err,
+            })?;
+        Ok(TrialLog {
+            file: Some(file),
+            scores,
+        })
+    }
+
+    /// The scores we already have for the candidate, in fold order.
+    pub fn known(&self, params: &Params) -> Result<Vec<f64>> {
+        Ok(self.scores.get(&key(params)?).cloned().unwrap_or_default())
+    }
+
+    fn record(&mut self, folds: usize, params: &Params, scores: &[f64]) -> Result<()> {
+        if let Some(ref mut file) = self.file {
+            let record = Record {
+                folds,
+                params: params.clone(),
+                scores: scores.to_vec(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+            file.flush()?;
+        }
+        self.scores.insert(key(params)?, scores.to_vec());
+        Ok(())
+    }
+}
+
+/// Best first.
+fn by_score(a: &Trial, b: &Trial) -> Ordering {
+    b.mean.partial_cmp(&a.mean).unwrap_or(Ordering::Equal)
+}
+
+///
+/// Score the candidates on the first `n` folds, in parallel, using the scores
+/// already in the log. Only as many candidates as the budget allows are scored,
+/// in the order they are given.
+///
+fn evaluate<T, M, F>(
+    rows: &[&T],
+    folds: usize,
+    n: usize,
+    candidates: &[Params],
+    log: &mut TrialLog,
+    spent: &mut Spent,
+    train_fn: &F,
+) -> Result<Vec<Trial>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    if spent.out_of_time() {
+        return Ok(Vec::new());
+    }
+
+    let mut affordable = Vec::new();
+    let mut left = spent.evaluations_left();
+    for params in candidates {
+        let mut known = log.known(params)?;
+        known.truncate(n);
+        let cost = n - known.len();
+        if cost > left {
+            break;
+        }
+        left -= cost;
+        affordable.push((params, known));
+    }
+
+    let scores = affordable
+        .par_iter()
+        .map(|(params, known)| {
+            let mut scores = known.clone();
+            for fold in known.len()..n {
+                scores.push(fold_score(rows, folds, fold, |train| train_fn(params, train))?);
+            }
+            Ok(scores)
+        })
+        .collect::<Result<Vec<Vec<f64>>>>()?;
+
+    let mut trials = Vec::new();
+    for ((params, known), scores) in affordable.into_iter().zip(scores) {
+        if scores.len() > known.len() {
+            spent.evaluations += scores.len() - known.len();
+            log.record(folds, params, &scores)?;
+        }
+        trials.push(Trial::new(params.clone(), scores));
+    }
+    Ok(trials)
+}
+
+/// Successive halving, returning the last trial of each candidate.
+#[allow(clippy::too_many_arguments)]
+fn halving<T, M, F>(
+    rows: &[&T],
+    folds: usize,
+    candidates: Vec<Params>,
+    eta: usize,
+    min_folds: usize,
+    log: &mut TrialLog,
+    spent: &mut Spent,
+    train_fn: &F,
+) -> Result<Vec<Trial>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    if eta < 2 {
+        return Err(DecisionsError::Config(format!("eta is {}, it needs to be at least 2", eta)));
+    }
+    check_folds(rows.len(), folds)?;
+    let mut n = min_folds.clamp(1, folds);
+
+    // Random candidates can come up more than once, but we only need to score them once.
+    let mut survivors: Vec<Params> = Vec::new();
+    for params in candidates {
+        if !survivors.contains(&params) {
+            survivors.push(params);
+        }
+    }
+    let mut dropped = Vec::new();
+    let mut current: Vec<Trial> = Vec::new();
+
+    while !survivors.is_empty() {
+        let evaluated = evaluate(rows, folds, n, &survivors, log, spent, train_fn)?;
+        let complete = evaluated.len() == survivors.len();
+
+        // Survivors we couldn't afford to score again keep their last trial.
+        dropped.extend(
+            current
+                .into_iter()
+                .filter(|trial| !evaluated.iter().any(|e| e.params == trial.params)),
+        );
+        current = evaluated;
+
+        if !complete || n >= folds {
+            break;
+        }
+
+        current.sort_by(by_score);
+        let keep = current.len().div_ceil(eta);
+        dropped.extend(current.split_off(keep));
+        survivors = current.iter().map(|trial| trial.params.clone()).collect();
+        n = (n * eta).min(folds);
+    }
+
+    dropped.extend(current);
+    Ok(dropped)
+}
+
+/// Rank the trials and train the best on all the rows.
+fn finish<T, M, F>(rows: &[&T], trials: Vec<Trial>, train_fn: F) -> Result<Search<M>>
+where
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M>,
+{
+    // A candidate can be tried more than once, keep the trial with the most folds.
+    let mut leaderboard: Vec<Trial> = Vec::new();
+    for trial in trials {
+        match leaderboard.iter_mut().find(|t| t.params == trial.params) {
+            Some(existing) if existing.scores.len() >= trial.scores.len() => {}
+            Some(existing) => *existing = trial,
+            None => leaderboard.push(trial),
+        }
+    }
+
+    // Candidates that got further are ranked above those that were dropped early.
+    leaderboard.sort_by(|a, b| b.scores.len().cmp(&a.scores.len()).then_with(|| by_score(a, b)));
+
+    let best = match leaderboard.first() {
+        Some(trial) => train_fn(&trial.params, rows.to_vec())?,
+        None => {
+            return Err(DecisionsError::Config(
+                "The budget ran out before any candidates were scored".to_string(),
+            ))
+        }
+    };
+    Ok(Search { leaderboard, best })
+}
+
+///
+/// Successive halving over the given candidates.
+///
+/// #Arguments
+///
+/// * `rows` - The rows to train and validate on.
+/// * `folds` - The number of cross validation folds.
+/// * `candidates` - The hyperparameters to try.
+/// * `eta` - Keep `1 / eta` of the candidates each round, scoring them on `eta` times as many folds.
+/// * `min_folds` - The number of folds to score every candidate on in the first round.
+/// * `budget` - When to stop.
+/// * `log` - Where the scores are recorded.
+/// * `train_fn` - Trains a tree on some rows with the given hyperparameters.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn successive_halving<T, M, F>(
+    rows: &[&T],
+    folds: usize,
+    candidates: Vec<Params>,
+    eta: usize,
+    min_folds: usize,
+    budget: Budget,
+    log: &mut TrialLog,
+    train_fn: F,
+) -> Result<Search<M>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    let mut spent = Spent::new(budget);
+    let trials = halving(rows, folds, candidates, eta, min_folds, log, &mut spent, &train_fn)?;
+    finish(rows, trials, train_fn)
+}
+
+///
+/// Hyperband, running successive halving on random candidates from the space,
+/// from many candidates starting on one fold to a few scored on every fold.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn hyperband<T, M, F>(
+    rows: &[&T],
+    folds: usize,
+    space: &Space,
+    eta: usize,
+    seed: u64,
+    budget: Budget,
+    log: &mut TrialLog,
+    train_fn: F,
+) -> Result<Search<M>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    if eta < 2 {
+        return Err(DecisionsError::Config(format!("eta is {}, it needs to be at least 2", eta)));
+    }
+    let mut spent = Spent::new(budget);
+    let mut rng = Rng::new(seed);
+
+    // The number of times we can divide the folds by eta.
+    let mut brackets = 0;
+    while eta.pow(brackets + 1) <= folds {
+        brackets += 1;
+    }
+
+    let mut trials = Vec::new();
+    for s in (0..=brackets).rev() {
+        let candidates = ((brackets + 1) as f64 / (s + 1) as f64 * eta.pow(s) as f64).ceil() as usize;
+        let min_folds = (folds / eta.pow(s)).max(1);
+
+        let mut bracket_rng = rng.split();
+        let candidates = (0..candidates).map(|_| space.sample_with(&mut bracket_rng)).collect();
+        trials.extend(halving(rows, folds, candidates, eta, min_folds, log, &mut spent, &train_fn)?);
+    }
+
+    finish(rows, trials, train_fn)
+}
+
+fn gaussian(x: f64, mean: f64, std: f64) -> f64 {
+    (-0.5 * ((x - mean) / std).powi(2)).exp() / (std * (2.0 * PI).sqrt())
+}
+
+/// Where a value sits in a range, ints being in the middle of their unit.
+fn position(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(v) => Some(*v as f64 + 0.5),
+        Value::Float(v) => Some(*v),
+        Value::Text(_) => None,
+    }
+}
+
+/// The width of the Gaussian around each value, narrowing as we get more values.
+fn bandwidth(low: f64, high: f64, values: usize) -> f64 {
+    (high - low) / 5.0 * ((values + 1) as f64).powf(-0.2)
+}
+
+/// The density of the values at the given value.
+fn density(dimension: &Dimension, values: &[&Value], value: &Value) -> f64 {
+    match dimension {
+        Dimension::Choice(choices) => {
+            let count = values.iter().filter(|v| **v == value).count();
+            (count + 1) as f64 / (values.len() + choices.len()) as f64
+        }
+        Dimension::Int { low, high } => range_density(*low as f64, *high as f64, values, value),
+        Dimension::Float { low, high } => range_density(*low, *high, values, value),
+    }
+}
+
+fn range_density(low: f64, high: f64, values: &[&Value], value: &Value) -> f64 {
+    let x = position(value).unwrap_or(low);
+    let points: Vec<f64> = values.iter().filter_map(|v| position(v)).collect();
+    let std = bandwidth(low, high, points.len());
+    let kernels: f64 = points.iter().map(|p| gaussian(x, *p, std)).sum();
+    (1.0 / (high - low) + kernels) / (points.len() + 1) as f64
+}
+
+/// Draw a value from the density of the values.
+fn draw(dimension: &Dimension, values: &[&Value], rng: &mut Rng) -> Value {
+    match dimension {
+        Dimension::Choice(choices) => {
+            let idx = rng.below(values.len() + choices.len());
+            if idx < values.len() {
+                values[idx].clone()
+            } else {
+                choices[idx - values.len()].clone()
+            }
+        }
+        Dimension::Int { low, high } => {
+            let x = range_draw(*low as f64, *high as f64, values, rng);
+            Value::Int((x.floor() as i64).clamp(*low, high - 1))
+        }
+        Dimension::Float { low, high } => Value::Float(range_draw(*low, *high, values, rng).clamp(*low, *high)),
+    }
+}
+
+fn range_draw(low: f64, high: f64, values: &[&Value], rng: &mut Rng) -> f64 {
+    let points: Vec<f64> = values.iter().filter_map(|v| position(v)).collect();
+    let idx = rng.below(points.len() + 1);
+    if idx == points.len() {
+        rng.range(low, high)
+    } else {
+        rng.normal(points[idx], bandwidth(low, high, points.len()))
+    }
+}
+
+/// The candidate that looks most like the good trials rather than the bad ones.
+fn propose(space: &Space, history: &[Trial], rng: &mut Rng) -> Params {
+    let mut sorted: Vec<&Trial> = history.iter().collect();
+    sorted.sort_by(|a, b| by_score(a, b));
+    let good = ((sorted.len() as f64 * GAMMA).ceil() as usize).max(1);
+    let (good, bad) = sorted.split_at(good);
+
+    let mut best = None;
+    let mut best_score = f64::NEG_INFINITY;
+    for _ in 0..CANDIDATES {
+        let mut params = Params::new();
+        let mut score = 0.0;
+        for (name, dimension) in space.dimensions() {
+            let good: Vec<&Value> = good.iter().filter_map(|t| t.params.get(name)).collect();
+            let bad: Vec<&Value> = bad.iter().filter_map(|t| t.params.get(name)).collect();
+            let value = draw(dimension, &good, rng);
+            score += density(dimension, &good, &value).ln() - density(dimension, &bad, &value).ln();
+            params.insert(name.clone(), value);
+        }
+        if score > best_score {
+            best_score = score;
+            best = Some(params);
+        }
+    }
+    best.unwrap_or_else(|| space.sample_with(rng))
+}
+
+///
+/// Try a number of candidates proposed by a Tree-structured Parzen Estimator,
+/// each scored on every fold.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn tpe<T, M, F>(
+    rows: &[&T],
+    folds: usize,
+    space: &Space,
+    trials: usize,
+    seed: u64,
+    budget: Budget,
+    log: &mut TrialLog,
+    train_fn: F,
+) -> Result<Search<M>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    let mut spent = Spent::new(budget);
+    let mut rng = Rng::new(seed);
+    let mut history = Vec::new();
+
+    for _ in 0..trials {
+        let params = if history.len() < STARTUP_TRIALS {
+            space.sample_with(&mut rng)
+        } else {
+            propose(space, &history, &mut rng)
+        };
+
+        match evaluate(rows, folds, folds, &[params], log, &mut spent, &train_fn)?.pop() {
+            Some(trial) => history.push(trial),
+            None => break,
+        }
+    }
+
+    finish(rows, history, train_fn)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{training_data, Fruit};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use tuning::train_tree;
+
+    fn log_path(name: &str) -> String {
+        let path = ::std::env::temp_dir().join(format!("decisions-{}-{}.jsonl", name, ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn candidates() -> Vec<Params> {
+        Space::parse(&["depth=1..=3", "criterion=gini,entropy"]).unwrap().grid().unwrap()
+    }
+
+    #[test]
+    fn test_successive_halving() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let trained = AtomicUsize::new(0);
+        let train_fn = |params: &Params, rows: Vec<&Fruit>| {
+            trained.fetch_add(1, AtomicOrdering::SeqCst);
+            train_tree(params, rows)
+        };
+
+        let mut log = TrialLog::new();
+        let search = successive_halving(&data, 5, candidates(), 2, 1, Budget::default(), &mut log, train_fn).unwrap();
+
+        // Six candidates on 1 fold, three on another, two on two more, one on the last
+        // then the refit. Scores from earlier rounds are reused.
+        assert_eq!(6 + 3 + 2 * 2 + 1 + 1, trained.load(AtomicOrdering::SeqCst));
+        assert_eq!(6, search.leaderboard.len());
+        assert_eq!(5, search.leaderboard[0].scores.len());
+        assert!(search.leaderboard[1].scores.len() < 5);
+    }
+
+    #[test]
+    fn test_budget() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let trained = AtomicUsize::new(0);
+        let train_fn = |params: &Params, rows: Vec<&Fruit>| {
+            if rows.len() < 5 {
+                trained.fetch_add(1, AtomicOrdering::SeqCst);
+            }
+            train_tree(params, rows)
+        };
+
+        let budget = Budget {
+            evaluations: Some(4),
+            time: None,
+        };
+        let mut log = TrialLog::new();
+        let search = successive_halving(&data, 5, candidates(), 2, 1, budget, &mut log, train_fn).unwrap();
+        assert_eq!(4, trained.load(AtomicOrdering::SeqCst));
+        assert_eq!(4, search.leaderboard.len());
+
+        let budget = Budget {
+            evaluations: Some(0),
+            time: None,
+        };
+        assert!(successive_halving(&data, 5, candidates(), 2, 1, budget, &mut TrialLog::new(), train_tree).is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let path = log_path("resume");
+        let space = Space::parse(&["depth=1..=3", "min_samples_leaf=1..=2"]).unwrap();
+
+        let mut log = TrialLog::open(&path, 5).unwrap();
+        let first = hyperband(&data, 5, &space, 2, 7, Budget::default(), &mut log, train_tree).unwrap();
+
+        // Everything is read back from the log, so only the refit trains a tree.
+        let trained = AtomicUsize::new(0);
+        let train_fn = |params: &Params, rows: Vec<&Fruit>| {
+            trained.fetch_add(1, AtomicOrdering::SeqCst);
+            train_tree(params, rows)
+        };
+        let mut log = TrialLog::open(&path, 5).unwrap();
+        let second = hyperband(&data, 5, &space, 2, 7, Budget::default(), &mut log, train_fn).unwrap();
+
+        assert_eq!(1, trained.load(AtomicOrdering::SeqCst));
+        assert_eq!(first.leaderboard, second.leaderboard);
+        assert!(TrialLog::open(&path, 3).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tpe() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let space = Space::parse(&["depth=1..=4", "criterion=gini,entropy"]).unwrap();
+
+        let trained = AtomicUsize::new(0);
+        let train_fn = |params: &Params, rows: Vec<&Fruit>| {
+            if rows.len() < 5 {
+                trained.fetch_add(1, AtomicOrdering::SeqCst);
+            }
+            train_tree(params, rows)
+        };
+        let search = tpe(&data, 5, &space, 15, 3, Budget::default(), &mut TrialLog::new(), train_fn).unwrap();
+
+        // Candidates that come up again are read back from the log.
+        assert!(trained.load(AtomicOrdering::SeqCst) <= 15 * 5);
+        assert!(search.leaderboard.len() <= 8);
+        assert!(search.leaderboard.iter().all(|trial| trial.scores.len() == 5));
+        assert!(search.leaderboard.windows(2).all(|pair| pair[0].mean >= pair[1].mean));
+
+        // The random trials it starts with only depend on the seed.
+        let params = |seed| {
+            let search = tpe(&data, 5, &space, STARTUP_TRIALS, seed, Budget::default(), &mut TrialLog::new(), train_tree);
+            let mut params: Vec<Params> = search.unwrap().leaderboard.into_iter().map(|t| t.params).collect();
+            params.sort_by_key(|p| key(p).unwrap());
+            params
+        };
+        assert_eq!(params(3), params(3));
+    }
+
+    #[test]
+    fn test_propose() {
+        // Deeper is better, so most of the proposals should be deep.
+        let space = Space::parse(&["depth=1..=10"]).unwrap();
+        let history: Vec<Trial> = (1..=10)
+            .chain(1..=10)
+            .map(|depth| {
+                let mut params = Params::new();
+                params.insert("depth".to_string(), Value::Int(depth));
+                Trial::new(params, vec![depth as f64 / 10.0])
+            })
+            .collect();
+
+        let mut rng = Rng::new(1);
+        let deep = (0..50)
+            .filter(|_| match propose(&space, &history, &mut rng)["depth"] {
+                Value::Int(depth) => depth >= 7,
+                _ => false,
+            })
+            .count();
+        assert!(deep > 40, "Only {} of the proposals were deep", deep);
+    }
+}
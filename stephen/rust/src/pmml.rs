@@ -0,0 +1,436 @@
+use question::Predicate;
+use row::Col;
+use schema::{FieldKind, Schema};
+use tree::Node;
+
+///
+/// Escape a string so it can go inside an xml attribute.
+///
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The `DataDictionary` describing every field and the target.
+fn data_dictionary(schema: &Schema) -> String {
+    let mut fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|field| match field.kind {
+            FieldKind::Categorical(ref categories) => format!(
+                "    <DataField name=\"{}\" optype=\"categorical\" dataType=\"string\">\n{}\n    </DataField>",
+                escape(&field.name),
+                categories
+                    .iter()
+                    .map(|c| format!("      <Value value=\"{}\"/>", escape(c)))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            FieldKind::Continuous => format!(
+                "    <DataField name=\"{}\" optype=\"continuous\" dataType=\"double\"/>",
+                escape(&field.name)
+            ),
+        })
+        .collect();
+
+    fields.push(format!(
+        "    <DataField name=\"{}\" optype=\"categorical\" dataType=\"string\">\n{}\n    </DataField>",
+        escape(&schema.target),
+        schema
+            .classes
+            .iter()
+            .map(|c| format!("      <Value value=\"{}\"/>", escape(c)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    ));
+
+    format!(
+        "  <DataDictionary numberOfFields=\"{}\">\n{}\n  </DataDictionary>",
+        fields.len(),
+        fields.join("\n")
+    )
+}
+
+/// The `MiningSchema` telling the consumer which fields are inputs and which is the target.
+fn mining_schema(schema: &Schema, indent: &str) -> String {
+    let mut fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{}  <MiningField name=\"{}\" usageType=\"active\"/>",
+                indent,
+                escape(&field.name)
+            )
+        })
+        .collect();
+    fields.push(format!(
+        "{}  <MiningField name=\"{}\" usageType=\"target\"/>",
+        indent,
+        escape(&schema.target)
+    ));
+
+    format!(
+        "{}<MiningSchema>\n{}\n{}</MiningSchema>",
+        indent,
+        fields.join("\n"),
+        indent
+    )
+}
+
+/// A value as it appears in a predicate's `value` attribute.
+fn value(value: &Col) -> String {
+    match value {
+        Col::Null => String::new(),
+        Col::Text(ref text) => escape(text),
+        Col::Int(ref num) => num.to_string(),
+        Col::Float(ref num) => num.to_string(),
+    }
+}
+
+///
+/// The predicate for the true branch of a decision.
+///
+/// Ranges are an `and` of two comparisons and sets are a `SimpleSetPredicate`.
+/// There is no `not` in PMML, so a negation is an `xor` with `<True/>`. With
+/// `missingValueStrategy="none"` a comparison against a missing value is false,
+/// so its negation is true just as in `Predicate::matches`.
+///
+fn predicate(schema: &Schema, question_col: usize, predicate: &Predicate) -> String {
+    let field = escape(&schema.fields[question_col].name);
+    let simple = |operator: &str, v: &Col| {
+        format!(
+            "<SimplePredicate field=\"{}\" operator=\"{}\" value=\"{}\"/>",
+            field,
+            operator,
+            value(v)
+        )
+    };
+
+    match predicate {
+        Predicate::Equal(v) => simple("equal", v),
+        Predicate::Less(v) => simple("lessThan", v),
+        Predicate::AtMost(v) => simple("lessOrEqual", v),
+        Predicate::AtLeast(v) => simple("greaterOrEqual", v),
+        Predicate::Greater(v) => simple("greaterThan", v),
+        Predicate::Between { low, high } => format!(
+            "<CompoundPredicate booleanOperator=\"and\">{}{}</CompoundPredicate>",
+            simple("greaterOrEqual", low),
+            simple("lessOrEqual", high)
+        ),
+        Predicate::In(values) => {
+            let kind = match values.first() {
+                Some(Col::Int(_)) => "int",
+                Some(Col::Float(_)) => "real",
+                _ => "string",
+            };
+            let items: Vec<String> = values
+                .iter()
+                .map(|v| match v {
+                    Col::Text(ref text) => format!("\"{}\"", escape(&text.replace('"', "\\\""))),
+                    other => value(other),
+                })
+                .collect();
+            format!(
+                "<SimpleSetPredicate field=\"{}\" booleanOperator=\"isIn\"><Array n=\"{}\" type=\"{}\">{}</Array></SimpleSetPredicate>",
+                field,
+                values.len(),
+                kind,
+                items.join(" ")
+            )
+        }
+        Predicate::IsNull => format!("<SimplePredicate field=\"{}\" operator=\"isMissing\"/>", field),
+        Predicate::Not(inner) => format!(
+            "<CompoundPredicate booleanOperator=\"xor\"><True/>{}</CompoundPredicate>",
+            self::predicate(schema, question_col, inner)
+        ),
+    }
+}
+
+///
+/// Write out a node and all its children.
+///
+/// Each decision becomes two child nodes, the first holding the question's
+/// predicate and the second always true. With `missingValueStrategy="none"`
+/// a comparison against a missing value is false, so missing values fall
+/// through to the false branch just as they do in `Question::matchit`.
+///
+fn node(schema: &Schema, node: &Node, predicate_xml: &str, id: &mut usize, indent: usize) -> String {
+    *id += 1;
+    let pad = " ".repeat(indent);
+
+    match node {
+        Node::Leaf { predictions } => {
+            let distribution = schema.distribution(predictions);
+            let (score, _) = schema
+                .classes
+                .iter()
+                .zip(distribution.iter())
+                .fold((&schema.classes[0], -1.0), |(best, best_p), (class, &p)| {
+                    if p > best_p {
+                        (class, p)
+                    } else {
+                        (best, best_p)
+                    }
+                });
+            let total: f64 = predictions.values().sum();
+
+            let scores: Vec<String> = schema
+                .classes
+                .iter()
+                .zip(distribution.iter())
+                .map(|(class, p)| {
+                    format!(
+                        "{}  <ScoreDistribution value=\"{}\" recordCount=\"{}\" probability=\"{}\"/>",
+                        pad,
+                        escape(class),
+                        predictions.get(class).unwrap_or(&0.0),
+                        p
+                    )
+                })
+                .collect();
+
+            format!(
+                "{}<Node id=\"{}\" score=\"{}\" recordCount=\"{}\">\n{}  {}\n{}\n{}</Node>",
+                pad,
+                id,
+                escape(score),
+                total,
+                pad,
+                predicate_xml,
+                scores.join("\n"),
+                pad
+            )
+        }
+        Node::Decision {
+            question,
+            true_branch,
+            false_branch,
+        } => {
+            let this_id = *id;
+            let true_xml = self::node(
+                schema,
+                true_branch,
+                &predicate(schema, question.col(), question.predicate()),
+                id,
+                indent + 2,
+            );
+            let false_xml = self::node(schema, false_branch, "<True/>", id, indent + 2);
+
+            format!(
+                "{}<Node id=\"{}\">\n{}  {}\n{}\n{}\n{}</Node>",
+                pad, this_id, pad, predicate_xml, true_xml, false_xml, pad
+            )
+        }
+    }
+}
+
+/// A `TreeModel` element for a single tree.
+fn tree_model(schema: &Schema, tree: &Node, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+    let mut id = 0;
+    format!(
+        "{}<TreeModel functionName=\"classification\" splitCharacteristic=\"binarySplit\" \
+         missingValueStrategy=\"none\" noTrueChildStrategy=\"returnNullPrediction\">\n{}\n{}\n{}</TreeModel>",
+        pad,
+        mining_schema(schema, &format!("{}  ", pad)),
+        node(schema, tree, "<True/>", &mut id, indent + 2),
+        pad
+    )
+}
+
+fn document(schema: &Schema, model: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <PMML xmlns=\"http://www.dmg.org/PMML-4_3\" version=\"4.3\">\n  \
+         <Header description=\"Decision tree\">\n    \
+         <Application name=\"decisions\" version=\"{}\"/>\n  \
+         </Header>\n{}\n{}\n</PMML>\n",
+        env!("CARGO_PKG_VERSION"),
+        data_dictionary(schema),
+        model
+    )
+}
+
+///
+/// Export a single tree as a PMML `TreeModel`.
+///
+pub fn tree_to_pmml(tree: &Node, schema: &Schema) -> String {
+    document(schema, &tree_model(schema, tree, 2))
+}
+
+///
+/// Export an ensemble of trees as a PMML `MiningModel`.
+/// Each tree is a segment, and the class probabilities of the segments
+/// are averaged to make the prediction.
+///
+pub fn ensemble_to_pmml(trees: &[Node], schema: &Schema) -> String {
+    let segments: Vec<String> = trees
+        .iter()
+        .enumerate()
+        .map(|(idx, tree)| {
+            format!(
+                "      <Segment id=\"{}\">\n        <True/>\n{}\n      </Segment>",
+                idx + 1,
+                tree_model(schema, tree, 8)
+            )
+        })
+        .collect();
+
+    let model = format!(
+        "  <MiningModel functionName=\"classification\">\n{}\n    \
+         <Segmentation multipleModelMethod=\"average\">\n{}\n    </Segmentation>\n  </MiningModel>",
+        mining_schema(schema, "    "),
+        segments.join("\n")
+    );
+
+    document(schema, &model)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+    use row::test::{training_data, Fruit};
+    use tree::build_tree;
+
+    /// Pull out each start tag with its attributes, so we can check the structure.
+    fn elements(xml: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let mut reader = Reader::from_str(xml);
+        let mut result = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(e) | Event::Empty(e) => {
+                    let name = String::from_utf8(e.name().as_ref().to_vec()).unwrap();
+                    let attributes = e
+                        .attributes()
+                        .map(|a| {
+                            let a = a.unwrap();
+                            (
+                                String::from_utf8(a.key.as_ref().to_vec()).unwrap(),
+                                a.unescape_value().unwrap().to_string(),
+                            )
+                        })
+                        .collect();
+                    result.push((name, attributes));
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+        result
+    }
+
+    fn attribute<'a>(attributes: &'a [(String, String)], key: &str) -> &'a str {
+        &attributes.iter().find(|(k, _)| k == key).unwrap().1
+    }
+
+    #[test]
+    fn test_tree_to_pmml() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let tree = build_tree(&data, None).unwrap();
+        let schema = Schema::from_rows(&data, "Label").unwrap();
+        let xml = tree_to_pmml(&tree, &schema);
+        let elements = elements(&xml);
+
+        let fields: Vec<&str> = elements
+            .iter()
+            .filter(|(name, _)| name == "DataField")
+            .map(|(_, a)| attribute(a, "name"))
+            .collect();
+        assert_eq!(vec!["Colour", "Things", "Fruit", "Label"], fields);
+
+        // Every decision has two children, so there is one more leaf than decisions.
+        let nodes = elements.iter().filter(|(name, _)| name == "Node").count();
+        let predicates: Vec<&Vec<(String, String)>> = elements
+            .iter()
+            .filter(|(name, _)| name == "SimplePredicate" || name == "SimpleSetPredicate")
+            .map(|(_, a)| a)
+            .collect();
+        let leaves = elements
+            .iter()
+            .filter(|(name, a)| name == "Node" && a.iter().any(|(k, _)| k == "score"))
+            .count();
+        assert_eq!(nodes, 2 * predicates.len() + 1);
+        assert_eq!(leaves, predicates.len() + 1);
+
+        for p in predicates {
+            match attribute(p, "field") {
+                "Things" => assert_eq!("greaterOrEqual", attribute(p, "operator")),
+                _ => assert!(p.iter().any(|(k, v)| (k == "operator" && v == "equal") || (k == "booleanOperator" && v == "isIn"))),
+            }
+        }
+
+        let model = elements.iter().find(|(name, _)| name == "TreeModel").unwrap();
+        assert_eq!("none", attribute(&model.1, "missingValueStrategy"));
+
+        // Probabilities in each leaf sum to one.
+        let probability: f64 = elements
+            .iter()
+            .filter(|(name, _)| name == "ScoreDistribution")
+            .map(|(_, a)| attribute(a, "probability").parse::<f64>().unwrap())
+            .sum();
+        assert!((probability - leaves as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_compound_predicates() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let schema = Schema::from_rows(&data, "Label").unwrap();
+        let predicate = |p: &Predicate| elements(&predicate(&schema, 0, p));
+
+        let colours = Predicate::In(vec![Col::Text("Green".to_string()), Col::Text("Pale \"red\"".to_string())]);
+        let set = predicate(&colours);
+        assert_eq!("SimpleSetPredicate", set[0].0);
+        assert_eq!("isIn", attribute(&set[0].1, "booleanOperator"));
+        assert_eq!("2", attribute(&set[1].1, "n"));
+        assert_eq!("string", attribute(&set[1].1, "type"));
+        // Values in the array are quoted, with any quotes inside them escaped.
+        assert!(self::predicate(&schema, 0, &colours).contains(">\"Green\" \"Pale \\&quot;red\\&quot;\"</Array>"));
+
+        let range = predicate(&Predicate::Between {
+            low: Col::Int(1),
+            high: Col::Int(3),
+        });
+        assert_eq!("and", attribute(&range[0].1, "booleanOperator"));
+        assert_eq!("greaterOrEqual", attribute(&range[1].1, "operator"));
+        assert_eq!("lessOrEqual", attribute(&range[2].1, "operator"));
+
+        // A negation is an xor with true.
+        let not = predicate(&Predicate::Less(Col::Int(2)).negate());
+        let names: Vec<&str> = not.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["CompoundPredicate", "True", "SimplePredicate"], names);
+        assert_eq!("xor", attribute(&not[0].1, "booleanOperator"));
+        assert_eq!("lessThan", attribute(&not[2].1, "operator"));
+
+        assert_eq!("isMissing", attribute(&predicate(&Predicate::IsNull)[0].1, "operator"));
+    }
+
+    #[test]
+    fn test_ensemble_to_pmml() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let trees = vec![build_tree(&data, None).unwrap(), build_tree(&data, Some(1)).unwrap()];
+        let schema = Schema::from_rows(&data, "Label").unwrap();
+        let elements = elements(&ensemble_to_pmml(&trees, &schema));
+
+        let segmentation = elements
+            .iter()
+            .find(|(name, _)| name == "Segmentation")
+            .unwrap();
+        assert_eq!("average", attribute(&segmentation.1, "multipleModelMethod"));
+        assert_eq!(2, elements.iter().filter(|(name, _)| name == "Segment").count());
+        assert_eq!(2, elements.iter().filter(|(name, _)| name == "TreeModel").count());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a &lt;&amp;&gt; &quot;b&apos;", escape("a <&> \"b'"));
+    }
+}
@@ -1,66 +1,307 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Display;
+use error::{DecisionsError, Result};
 use row::{Col, DataRow};
 
-#[derive(Debug, Clone)]
+///
+/// What a question asks of the value in a column.
+///
+/// A missing value only matches `IsNull`, so it goes down the false branch
+/// of every other predicate, and down the true branch of their negation.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Predicate {
+    /// `== value`
+    Equal(Col),
+    /// `< value`
+    Less(Col),
+    /// `<= value`
+    AtMost(Col),
+    /// `>= value`
+    AtLeast(Col),
+    /// `> value`
+    Greater(Col),
+    /// Between `low` and `high`, including both ends.
+    Between { low: Col, high: Col },
+    /// Equal to any one of the values, such as `Embarked in {C, Q}`.
+    In(Vec<Col>),
+    /// The value is missing.
+    IsNull,
+    /// Anything the inner predicate doesn't match.
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    /// The predicate that matches everything this one doesn't.
+    pub fn negate(self) -> Predicate {
+        match self {
+            Predicate::Not(inner) => *inner,
+            other => Predicate::Not(Box::new(other)),
+        }
+    }
+
+    /// Does the predicate match the value? `field_name` is only used for errors.
+    pub fn matches(&self, field_name: &str, value: &Col) -> Result<bool> {
+        let ordering = |us: &Col| compare(field_name, value, us);
+        match self {
+            Predicate::IsNull => Ok(*value == Col::Null),
+            Predicate::Not(inner) => Ok(!inner.matches(field_name, value)?),
+            _ if *value == Col::Null => Ok(false),
+            Predicate::Equal(us) => Ok(ordering(us)? == Some(Ordering::Equal)),
+            Predicate::Less(us) => Ok(ordering(us)? == Some(Ordering::Less)),
+            Predicate::AtMost(us) => Ok(matches!(ordering(us)?, Some(Ordering::Less) | Some(Ordering::Equal))),
+            Predicate::AtLeast(us) => Ok(matches!(ordering(us)?, Some(Ordering::Greater) | Some(Ordering::Equal))),
+            Predicate::Greater(us) => Ok(ordering(us)? == Some(Ordering::Greater)),
+            Predicate::Between { low, high } => Ok(Predicate::AtLeast(low.clone()).matches(field_name, value)?
+                && Predicate::AtMost(high.clone()).matches(field_name, value)?),
+            Predicate::In(values) => {
+                for us in values {
+                    if ordering(us)? == Some(Ordering::Equal) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+///
+/// Compare a row's value with a predicate's, None if they can't be ordered (NaN, or a null in the predicate).
+/// Ints and floats are compared by their value, so a column can hold either.
+///
+fn compare(field_name: &str, them: &Col, us: &Col) -> Result<Option<Ordering>> {
+    match (them, us) {
+        (_, Col::Null) => Ok(None),
+        (Col::Text(ref them), Col::Text(ref us)) => Ok(Some(them.cmp(us))),
+        (Col::Int(ref them), Col::Int(ref us)) => Ok(Some(them.cmp(us))),
+        (Col::Int(_), Col::Float(_)) | (Col::Float(_), Col::Int(_)) | (Col::Float(_), Col::Float(_)) => {
+            Ok(them.number().partial_cmp(&us.number()))
+        }
+        (them, us) => Err(DecisionsError::TypeMismatch {
+            column: field_name.to_string(),
+            expected: us.type_name().to_string(),
+            found: them.type_name().to_string()
+        })
+    }
+}
+
+/// Write out a value for display, with nulls spelt out.
+fn show(value: &Col) -> String {
+    match value {
+        Col::Null => "null".to_string(),
+        Col::Text(ref text) => text.to_string(),
+        Col::Int(ref num) => num.to_string(),
+        Col::Float(ref num) => num.to_string()
+    }
+}
+
+impl Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Predicate::Equal(value) => write!(f, "== {}", show(value)),
+            Predicate::Less(value) => write!(f, "< {}", show(value)),
+            Predicate::AtMost(value) => write!(f, "<= {}", show(value)),
+            Predicate::AtLeast(value) => write!(f, ">= {}", show(value)),
+            Predicate::Greater(value) => write!(f, "> {}", show(value)),
+            Predicate::Between { low, high } => write!(f, "between {} and {}", show(low), show(high)),
+            Predicate::In(values) => {
+                let values: Vec<String> = values.iter().map(show).collect();
+                write!(f, "in {{{}}}", values.join(", "))
+            }
+            Predicate::IsNull => write!(f, "is null"),
+            Predicate::Not(inner) => match **inner {
+                Predicate::Equal(ref value) => write!(f, "!= {}", show(value)),
+                Predicate::IsNull => write!(f, "is not null"),
+                ref inner => write!(f, "not {}", inner),
+            },
+        }
+    }
+}
+
+///
+/// A question that splits the rows at a decision node.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SavedQuestion")]
 pub struct Question {
     field_name: String,
     col: usize,
-    val: Col
+    predicate: Predicate
+}
+
+///
+/// A question as it is saved. Models saved before predicates were added
+/// have a `val` instead, which text columns matched on equality and
+/// numbers matched when they were `>=`.
+///
+#[derive(Deserialize)]
+struct SavedQuestion {
+    field_name: String,
+    col: usize,
+    #[serde(default)]
+    predicate: Option<Predicate>,
+    #[serde(default)]
+    val: Option<Col>
+}
+
+impl TryFrom<SavedQuestion> for Question {
+    type Error = String;
+
+    fn try_from(saved: SavedQuestion) -> std::result::Result<Self, Self::Error> {
+        match (saved.predicate, saved.val) {
+            (Some(predicate), _) => Ok(Question::with_predicate(saved.field_name, saved.col, predicate)),
+            (None, Some(val)) => Ok(Question::new(saved.field_name, saved.col, val)),
+            (None, None) => Err(format!("The question about {} has no predicate", saved.field_name))
+        }
+    }
 }
 
 impl Question {
+    /// The usual question for a value: text columns match on equality,
+    /// numbers match when they are `>=` the value and null matches missing values.
     pub fn new(field_name: String, col: usize, val: Col) -> Self {
+        let predicate = match val {
+            Col::Null => Predicate::IsNull,
+            Col::Text(_) => Predicate::Equal(val),
+            Col::Int(_) | Col::Float(_) => Predicate::AtLeast(val)
+        };
+        Question::with_predicate(field_name, col, predicate)
+    }
+
+    pub fn with_predicate(field_name: String, col: usize, predicate: Predicate) -> Self {
         Question {
             field_name,
             col,
-            val
+            predicate
         }
     }
-    
+
+    /// The name of the column this question asks about.
+    pub fn field_name(&self) -> &str {
+        &self.field_name
+    }
+
+    /// The index of the column this question asks about.
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    /// What we ask of the column.
+    pub fn predicate(&self) -> &Predicate {
+        &self.predicate
+    }
+
     /// Does this question match the given row?
-    pub fn matchit<T>(&self, example: &T) -> bool 
+    pub fn matchit<T>(&self, example: &T) -> Result<bool> 
     where T: DataRow {
-        match (example.value(self.col), &self.val) {
-            (Col::Null, _) => false,
-            (_, Col::Null) => false,
-            (Col::Text(ref them), Col::Text(ref us)) => them == us,
-            (Col::Int(ref them), Col::Int(ref us)) => them >= us,
-            (Col::Float(ref them), Col::Float(ref us)) => them >= us,
-            _ => panic!("Column types didn't match. You did something thick.")
-        }
+        self.predicate.matches(&self.field_name, &example.value(self.col)?)
     }
 }
 
 impl Display for Question {
-     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-         let (condition, value) = match &self.val {
-             Col::Null => ("is".to_string(), "null".to_string()),
-             Col::Text(ref text) => ("==".to_string(), text.to_string()),
-             Col::Int(ref num) => (">=".to_string(), num.to_string()),
-             Col::Float(ref num) => (">=".to_string(), num.to_string())
-         };
-         write!(f, "Is {} {} {}", self.field_name, condition, value)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Is {} {}", self.field_name, self.predicate)
     }   
 }
 
 
-
 #[cfg(test)]
 mod test {
     use super::*;
-    use row::test::{Fruit, training_data};
+    use row::test::Fruit;
 
     #[test]
     fn test_question_matches() {
         let q = Question::new("Colour".to_string(), 0, Col::Text("Red".to_string()));
 
-        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 1, "toenails".to_string())));
-        assert!(!q.matchit(&Fruit::new(2, "Green".to_string(), 1, "spleen".to_string())));
+        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 1, "toenails".to_string())).unwrap());
+        assert!(!q.matchit(&Fruit::new(2, "Green".to_string(), 1, "spleen".to_string())).unwrap());
         
         let q = Question::new("Colour".to_string(), 1, Col::Int(42));
-        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 42, "toenails".to_string())));
-        assert!(!q.matchit(&Fruit::new(2, "Red".to_string(), 1, "spleen".to_string())));
+        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 42, "toenails".to_string())).unwrap());
+        assert!(!q.matchit(&Fruit::new(2, "Red".to_string(), 1, "spleen".to_string())).unwrap());
+    }
+
+    #[test]
+    fn test_question_type_mismatch() {
+        let q = Question::new("Things".to_string(), 1, Col::Text("many".to_string()));
+        match q.matchit(&Fruit::new(1, "Red".to_string(), 1, "toenails".to_string())) {
+            Err(DecisionsError::TypeMismatch { column, expected, found }) => {
+                assert_eq!("Things", column);
+                assert_eq!("text", expected);
+                assert_eq!("int", found);
+            }
+            other => panic!("Expected a type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_mixed_numbers() {
+        // Ints and floats in the same column are compared by value.
+        let q = Question::new("Things".to_string(), 1, Col::Float(4.2));
+        assert!(!q.matchit(&Fruit::new(1, "Red".to_string(), 4, "toenails".to_string())).unwrap());
+        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 5, "toenails".to_string())).unwrap());
+        assert!(Predicate::Equal(Col::Int(3)).matches("Things", &Col::Float(3.0)).unwrap());
+        assert!(Predicate::Less(Col::Int(3)).matches("Things", &Col::Float(2.5)).unwrap());
+    }
+
+    #[test]
+    fn test_predicates() {
+        let five = Col::Int(5);
+        let matches = |predicate: Predicate, value: &Col| predicate.matches("Age", value).unwrap();
+
+        assert!(matches(Predicate::Less(Col::Int(6)), &five));
+        assert!(!matches(Predicate::Less(Col::Int(5)), &five));
+        assert!(matches(Predicate::AtMost(Col::Int(5)), &five));
+        assert!(!matches(Predicate::Greater(Col::Int(5)), &five));
+        assert!(matches(Predicate::Between { low: Col::Int(5), high: Col::Int(7) }, &five));
+        assert!(!matches(Predicate::Between { low: Col::Int(6), high: Col::Int(7) }, &five));
+        assert!(!matches(Predicate::Less(Col::Float(1.0)), &Col::Float(f64::NAN)));
+
+        let ports = Predicate::In(vec![Col::Text("C".to_string()), Col::Text("Q".to_string())]);
+        assert!(matches(ports.clone(), &Col::Text("Q".to_string())));
+        assert!(!matches(ports.clone(), &Col::Text("S".to_string())));
+        assert!(matches(ports.clone().negate(), &Col::Text("S".to_string())));
+
+        // Missing values only match null, and so match the negation of everything else.
+        assert!(!matches(ports.clone(), &Col::Null));
+        assert!(matches(ports.clone().negate(), &Col::Null));
+        assert!(matches(Predicate::IsNull, &Col::Null));
+        assert!(!matches(Predicate::IsNull.negate(), &Col::Null));
+        assert!(!matches(Predicate::IsNull, &five));
+
+        assert_eq!(ports, ports.clone().negate().negate());
+        assert!(Predicate::Less(Col::Text("C".to_string())).matches("Age", &five).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let question = |predicate| Question::with_predicate("Embarked".to_string(), 0, predicate).to_string();
+        let c = || Col::Text("C".to_string());
+
+        assert_eq!("Is Embarked == C", question(Predicate::Equal(c())));
+        assert_eq!("Is Embarked != C", question(Predicate::Equal(c()).negate()));
+        assert_eq!("Is Embarked in {C, Q}", question(Predicate::In(vec![c(), Col::Text("Q".to_string())])));
+        assert_eq!("Is Embarked is not null", question(Predicate::IsNull.negate()));
+        assert_eq!("Is Embarked not < C", question(Predicate::Less(c()).negate()));
+        assert_eq!("Is Embarked between 1 and 2.5", question(Predicate::Between { low: Col::Int(1), high: Col::Float(2.5) }));
+        assert_eq!("Is Embarked <= 3", question(Predicate::AtMost(Col::Int(3))));
+    }
+
+    #[test]
+    fn test_saved_questions() {
+        // Questions saved before there were predicates still load.
+        let old: Question = serde_json::from_str(r#"{"field_name":"Age","col":1,"val":{"Int":18}}"#).unwrap();
+        assert_eq!(&Predicate::AtLeast(Col::Int(18)), old.predicate());
+
+        let question = Question::with_predicate("Age".to_string(), 1, Predicate::Less(Col::Int(18)).negate());
+        let saved = serde_json::to_string(&question).unwrap();
+        assert_eq!(question, serde_json::from_str(&saved).unwrap());
+
+        assert!(serde_json::from_str::<Question>(r#"{"field_name":"Age","col":1}"#).is_err());
     }
     
 }
@@ -0,0 +1,118 @@
+//!
+//! A small seedable random number generator, so that anything random
+//! we do can be repeated by using the same seed.
+//!
+//! This is SplitMix64, which is fast and good enough for sampling and
+//! shuffling. It is not for anything that needs to be secure.
+//!
+
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, n)`.
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0);
+        // Multiply rather than take the remainder to avoid favouring small numbers.
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// A float in `[low, high)`.
+    pub fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + self.float() * (high - low)
+    }
+
+    /// A number from the normal distribution with the given mean and standard deviation.
+    pub fn normal(&mut self, mean: f64, std: f64) -> f64 {
+        // Box-Muller, 1 - float() is in (0, 1] so we never take the log of zero.
+        let u = 1.0 - self.float();
+        let v = self.float();
+        mean + std * (-2.0 * u.ln()).sqrt() * (2.0 * ::std::f64::consts::PI * v).cos()
+    }
+
+    /// Shuffle the items in place.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+
+    /// A new generator for a separate stream of numbers, for example for
+    /// each of a number of jobs run in parallel.
+    pub fn split(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repeatable() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let a: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        let b: Vec<u64> = (0..10).map(|_| b.next_u64()).collect();
+        let c: Vec<u64> = (0..10).map(|_| c.next_u64()).collect();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(1);
+        let mut seen = [0; 5];
+        for _ in 0..1000 {
+            seen[rng.below(5)] += 1;
+            let f = rng.range(-1.0, 2.0);
+            assert!((-1.0..2.0).contains(&f));
+        }
+        // Every number turns up, roughly as often as the others.
+        assert!(seen.iter().all(|&count| count > 150 && count < 250));
+    }
+
+    #[test]
+    fn test_normal() {
+        let mut rng = Rng::new(3);
+        let samples: Vec<f64> = (0..10000).map(|_| rng.normal(5.0, 2.0)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let var = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+
+        assert!((mean - 5.0).abs() < 0.1);
+        assert!((var.sqrt() - 2.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut rng = Rng::new(7);
+        let mut items: Vec<usize> = (0..20).collect();
+        rng.shuffle(&mut items);
+
+        assert_ne!((0..20).collect::<Vec<usize>>(), items);
+        items.sort();
+        assert_eq!((0..20).collect::<Vec<usize>>(), items);
+    }
+}
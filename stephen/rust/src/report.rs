@@ -0,0 +1,194 @@
+//!
+//! Write out results as a table for people to read, or as json or csv
+//! for scripts.
+//!
+
+use csv;
+use error::{DecisionsError, Result};
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::str::FromStr;
+
+/// The formats we can write a report in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Columns lined up for reading in a terminal.
+    Table,
+    /// An array with an object for each row.
+    Json,
+    /// A header line then a line for each row.
+    Csv,
+}
+
+impl Format {
+    /// The names the formats can be given on the command line.
+    pub const NAMES: &'static [&'static str] = &["table", "json", "csv"];
+}
+
+impl FromStr for Format {
+    type Err = DecisionsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(DecisionsError::Config(format!(
+                "Unknown format {}, expected one of {}",
+                s,
+                Format::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+///
+/// Rows of results with a named value in each column.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    headers: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+/// How a value is shown in a table or csv.
+fn text(value: &Value, table: bool) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Number(n) if table && !n.is_i64() && !n.is_u64() => {
+            format!("{:.4}", n.as_f64().unwrap_or(f64::NAN))
+        }
+        value => value.to_string(),
+    }
+}
+
+impl Report {
+    pub fn new(headers: &[&str]) -> Self {
+        Report {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row, which should have a value for each header.
+    pub fn push(&mut self, row: Vec<Value>) {
+        assert_eq!(self.headers.len(), row.len());
+        self.rows.push(row);
+    }
+
+    pub fn write<W>(&self, format: Format, mut out: W) -> Result<()>
+    where
+        W: Write,
+    {
+        match format {
+            Format::Table => {
+                let cells: Vec<Vec<String>> = self
+                    .rows
+                    .iter()
+                    .map(|row| row.iter().map(|value| text(value, true)).collect())
+                    .collect();
+                let widths: Vec<usize> = self
+                    .headers
+                    .iter()
+                    .enumerate()
+                    .map(|(col, header)| {
+                        cells
+                            .iter()
+                            .map(|row| row[col].len())
+                            .fold(header.len(), usize::max)
+                    })
+                    .collect();
+
+                let line = |row: &[String]| {
+                    row.iter()
+                        .zip(widths.iter())
+                        .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                        .collect::<Vec<String>>()
+                        .join("  ")
+                        .trim_end()
+                        .to_string()
+                };
+
+                writeln!(out, "{}", line(&self.headers))?;
+                let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+                writeln!(out, "{}", line(&rule))?;
+                for row in &cells {
+                    writeln!(out, "{}", line(row))?;
+                }
+            }
+            Format::Json => {
+                let rows: Vec<Value> = self
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        Value::Object(
+                            self.headers
+                                .iter()
+                                .cloned()
+                                .zip(row.iter().cloned())
+                                .collect::<Map<String, Value>>(),
+                        )
+                    })
+                    .collect();
+                serde_json::to_writer_pretty(&mut out, &rows)?;
+                writeln!(out)?;
+            }
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(&self.headers)?;
+                for row in &self.rows {
+                    writer.write_record(row.iter().map(|value| text(value, false)))?;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn report() -> Report {
+        let mut report = Report::new(&["Depth", "Accuracy"]);
+        report.push(vec![json!(3), json!(0.8125)]);
+        report.push(vec![json!(10), json!("n/a")]);
+        report
+    }
+
+    fn written(format: Format) -> String {
+        let mut out = Vec::new();
+        report().write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            "Depth  Accuracy\n-----  --------\n3      0.8125\n10     n/a\n",
+            written(Format::Table)
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!("Depth,Accuracy\n3,0.8125\n10,n/a\n", written(Format::Csv));
+    }
+
+    #[test]
+    fn test_json() {
+        let rows: Value = serde_json::from_str(&written(Format::Json)).unwrap();
+        assert_eq!(json!([{"Depth": 3, "Accuracy": 0.8125}, {"Depth": 10, "Accuracy": "n/a"}]), rows);
+    }
+
+    #[test]
+    fn test_format_names() {
+        for name in Format::NAMES {
+            assert!(name.parse::<Format>().is_ok());
+        }
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
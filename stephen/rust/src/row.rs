@@ -1,48 +1,260 @@
+use error::{DecisionsError, Result};
 use std::cmp::Ordering;
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+/// The value in a column of a row.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum Col {
+    #[default]
     Null,
     Text (String),
     Int (i64),
     Float (f64)
 }
 
+impl PartialEq for Col {
+    fn eq(&self, other: &Col) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
 impl Eq for Col {}
 
-/// We can't automatically implement Ord for floats,
-/// so we have to do it by hand.
+impl PartialOrd for Col {
+    fn partial_cmp(&self, other: &Col) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+///
+/// A total ordering so columns can be sorted: nulls, then numbers, then text.
+/// Ints and floats are ordered by their value, so an int and a float of the
+/// same value are equal, and NaN comes after every other number.
+///
 impl Ord for Col {
     fn cmp(&self, other: &Col) -> Ordering {
         match (self, other) {
             (Col::Text(a), Col::Text(b)) => a.cmp(b),
             (Col::Int(a), Col::Int(b)) => a.cmp(b),
-            (Col::Float(a), Col::Float(b)) => 
-             if a < b {
-                 Ordering::Less
-             } else {
-                 Ordering::Greater
-             },
-             _ => Ordering::Equal
+            (Col::Float(a), Col::Float(b)) => compare_floats(*a, *b),
+            (Col::Int(a), Col::Float(b)) => compare_int_float(*a, *b),
+            (Col::Float(a), Col::Int(b)) => compare_int_float(*b, *a).reverse(),
+            _ => self.rank().cmp(&other.rank()),
         }
     }
 }
 
-impl Default for Col {
-    fn default() -> Self { Col::Null }
+/// Order floats with NaN after everything else, and equal to itself.
+fn compare_floats(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
 }
 
+/// Order an int against a float by value. Big ints don't fit in a float
+/// exactly, so when they look the same the float is a whole number and we
+/// compare them as ints instead.
+fn compare_int_float(a: i64, b: f64) -> Ordering {
+    compare_floats(a as f64, b).then_with(|| (a as i128).cmp(&(b as i128)))
+}
+
+impl Col {
+    /// Where each type of value goes when sorting.
+    fn rank(&self) -> u8 {
+        match self {
+            Col::Null => 0,
+            Col::Int(_) | Col::Float(_) => 1,
+            Col::Text(_) => 2,
+        }
+    }
+
+    /// The value as a float if it is a number.
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Col::Int(num) => Some(*num as f64),
+            Col::Float(num) => Some(*num),
+            _ => None,
+        }
+    }
+
+    /// A float half way between two numbers, for a threshold that splits them.
+    /// If they are so close there isn't room for one, it's the higher of the two.
+    /// None if either isn't a number, or is NaN.
+    pub fn midpoint(&self, other: &Col) -> Option<Col> {
+        let (low, high) = (self.number()?, other.number()?);
+        if low.is_nan() || high.is_nan() {
+            return None;
+        }
+        let mid = low + (high - low) / 2.0;
+        Some(Col::Float(if mid > low { mid } else { high }))
+    }
+
+    /// The name of the type of value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Col::Null => "null",
+            Col::Text(_) => "text",
+            Col::Int(_) => "int",
+            Col::Float(_) => "float",
+        }
+    }
+}
+
+///
+/// A row of data that we can train on or make predictions for.
+///
 pub trait DataRow {
+    /// Identifies the row, used when outputting predictions.
     fn id(&self) -> i64;
-    fn name(&self, col: usize) -> String;
-    fn value(&self, col: usize) -> Col;
-    fn label(&self) -> String;
+    /// The name of the given column.
+    fn name(&self, col: usize) -> Result<String>;
+    /// The value of the given column.
+    fn value(&self, col: usize) -> Result<Col>;
+    /// The label of the row. Rows we are predicting may not have one.
+    fn label(&self) -> Result<String>;
+    /// The number of columns.
     fn len(&self) -> usize;
+
+    /// The labels of a row with more than one thing to predict. Most rows
+    /// have just the one, their `label`.
+    fn labels(&self) -> Result<Vec<String>> {
+        Ok(vec![self.label()?])
+    }
+
+    /// Does the row have no columns?
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How much the row counts for when training and scoring, as if it
+    /// appeared this many times in the data.
+    fn weight(&self) -> f64 {
+        1.0
+    }
+}
+
+///
+/// A row with its own weight, for giving weights to rows that don't have them.
+///
+#[derive(Debug, Clone)]
+pub struct Weighted<'a, T: 'a> {
+    row: &'a T,
+    weight: f64,
+}
+
+impl<'a, T> Weighted<'a, T>
+where
+    T: DataRow,
+{
+    pub fn new(row: &'a T, weight: f64) -> Self {
+        Weighted { row, weight }
+    }
+}
+
+impl<'a, T> DataRow for Weighted<'a, T>
+where
+    T: DataRow,
+{
+    fn id(&self) -> i64 {
+        self.row.id()
+    }
+
+    fn name(&self, col: usize) -> Result<String> {
+        self.row.name(col)
+    }
+
+    fn value(&self, col: usize) -> Result<Col> {
+        self.row.value(col)
+    }
+
+    fn label(&self) -> Result<String> {
+        self.row.label()
+    }
+
+    fn labels(&self) -> Result<Vec<String>> {
+        self.row.labels()
+    }
+
+    fn len(&self) -> usize {
+        self.row.len()
+    }
+
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+}
+
+///
+/// A row with the value of one column replaced, such as to shuffle a column
+/// between rows or to try a row with different values.
+///
+#[derive(Debug, Clone)]
+pub struct Replaced<'a, T: 'a> {
+    row: &'a T,
+    col: usize,
+    value: Col,
+}
+
+impl<'a, T> Replaced<'a, T>
+where
+    T: DataRow,
+{
+    pub fn new(row: &'a T, col: usize, value: Col) -> Self {
+        Replaced { row, col, value }
+    }
+}
+
+impl<'a, T> DataRow for Replaced<'a, T>
+where
+    T: DataRow,
+{
+    fn id(&self) -> i64 {
+        self.row.id()
+    }
+
+    fn name(&self, col: usize) -> Result<String> {
+        self.row.name(col)
+    }
+
+    fn value(&self, col: usize) -> Result<Col> {
+        if col == self.col {
+            Ok(self.value.clone())
+        } else {
+            self.row.value(col)
+        }
+    }
+
+    fn label(&self) -> Result<String> {
+        self.row.label()
+    }
+
+    fn labels(&self) -> Result<Vec<String>> {
+        self.row.labels()
+    }
+
+    fn len(&self) -> usize {
+        self.row.len()
+    }
+
+    fn weight(&self) -> f64 {
+        self.row.weight()
+    }
+}
+
+/// The error for a column a row doesn't have.
+pub fn no_column<T>(row: &T, col: usize) -> DecisionsError
+where
+    T: DataRow + ?Sized,
+{
+    DecisionsError::Schema(format!(
+        "Row {} has {} columns, so there is no column {}",
+        row.id(),
+        row.len(),
+        col
+    ))
 }
 
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use random::Rng;
     
     pub struct Fruit {
         id: i64,
@@ -58,24 +270,24 @@ pub mod test {
     }
     
     impl DataRow for Fruit {
-        fn name(&self, col: usize) -> String {
+        fn name(&self, col: usize) -> Result<String> {
             match col {
-                0 => "Colour".to_string(),
-                1 => "Things".to_string(),
-                2 => "Fruit".to_string(),
-                _ => panic!("Silly column")
+                0 => Ok("Colour".to_string()),
+                1 => Ok("Things".to_string()),
+                2 => Ok("Fruit".to_string()),
+                _ => Err(no_column(self, col))
             }
         }
-        fn value(&self, col: usize) -> Col {
+        fn value(&self, col: usize) -> Result<Col> {
             match col {
-                0 => Col::Text(self.colour.clone()),
-                1 => Col::Int(self.things),
-                2 => Col::Text(self.fruit.clone()),
-                _ => panic!("Silly column")
+                0 => Ok(Col::Text(self.colour.clone())),
+                1 => Ok(Col::Int(self.things)),
+                2 => Ok(Col::Text(self.fruit.clone())),
+                _ => Err(no_column(self, col))
             }
         }
-        fn label(&self) -> String {
-            self.fruit.clone()
+        fn label(&self) -> Result<String> {
+            Ok(self.fruit.clone())
         }
         fn len(&self) -> usize {
             3   
@@ -85,11 +297,101 @@ pub mod test {
         }
     }
     
-    pub fn training_data<'a>() -> Vec<Fruit> {
+    pub fn training_data() -> Vec<Fruit> {
         vec![Fruit::new(1, "Green".to_string(), 3, "Apple".to_string()),
              Fruit::new(2, "Yellow".to_string(), 3, "Apple".to_string()),
              Fruit::new(3, "Red".to_string(), 1, "Grape".to_string()),
              Fruit::new(4, "Red".to_string(), 1, "Grape".to_string()),
              Fruit::new(5, "Yellow".to_string(), 3, "Lemon".to_string())]
     }
+
+    /// A row with two numbers, for tests that need more than a handful of rows.
+    #[derive(Debug, Clone)]
+    pub struct Point {
+        pub id: i64,
+        pub x: i64,
+        pub y: f64,
+        pub label: String,
+    }
+
+    impl DataRow for Point {
+        fn name(&self, col: usize) -> Result<String> {
+            match col {
+                0 => Ok("X".to_string()),
+                1 => Ok("Y".to_string()),
+                _ => Err(no_column(self, col)),
+            }
+        }
+        fn value(&self, col: usize) -> Result<Col> {
+            match col {
+                0 => Ok(Col::Int(self.x)),
+                1 => Ok(Col::Float(self.y)),
+                _ => Err(no_column(self, col)),
+            }
+        }
+        fn label(&self) -> Result<String> {
+            Ok(self.label.clone())
+        }
+        fn len(&self) -> usize {
+            2
+        }
+        fn id(&self) -> i64 {
+            self.id
+        }
+    }
+
+    /// Points that are mostly "Yes" when x + y is big, with some noise so
+    /// the trees have something to do.
+    pub fn points(n: usize, seed: u64) -> Vec<Point> {
+        let mut rng = Rng::new(seed);
+        (0..n)
+            .map(|id| {
+                let x = rng.below(10) as i64;
+                let y = (rng.range(0.0, 10.0) * 10.0).round() / 10.0;
+                let yes = x as f64 + y + rng.normal(0.0, 2.0) > 10.0;
+                Point {
+                    id: id as i64,
+                    x,
+                    y,
+                    label: if yes { "Yes" } else { "No" }.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_col_order() {
+        let mut values = [
+            Col::Text("a".to_string()),
+            Col::Float(f64::NAN),
+            Col::Float(2.5),
+            Col::Int(2),
+            Col::Null,
+            Col::Float(2.0),
+            Col::Float(f64::NEG_INFINITY),
+        ];
+        values.sort();
+        assert_eq!(Col::Null, values[0]);
+        assert_eq!(Col::Float(f64::NEG_INFINITY), values[1]);
+        // An int and a float of the same value are equal.
+        assert_eq!(Col::Int(2), values[2]);
+        assert_eq!(Col::Float(2.0), values[3]);
+        assert_eq!(Col::Int(2), Col::Float(2.0));
+        assert_eq!(Ordering::Less, Col::Int(2).cmp(&Col::Float(2.5)));
+        assert_eq!(Ordering::Greater, Col::Float(2.5).cmp(&Col::Int(2)));
+        assert_eq!(Col::Float(2.5), values[4]);
+        // Even when the int is too big to be a float exactly.
+        let big = 1i64 << 53;
+        assert_eq!(Ordering::Greater, Col::Int(big + 1).cmp(&Col::Float(big as f64)));
+        assert_eq!(Ordering::Less, Col::Float(big as f64).cmp(&Col::Int(big + 1)));
+        assert_eq!(Ordering::Less, Col::Int(i64::MAX).cmp(&Col::Float(i64::MAX as f64)));
+        // NaN is after every other number, and equal to itself so it can be deduplicated.
+        assert!(matches!(values[5], Col::Float(num) if num.is_nan()));
+        assert_eq!(Col::Float(f64::NAN), Col::Float(f64::NAN));
+        assert_eq!(Col::Text("a".to_string()), values[6]);
+
+        assert_eq!(Some(Col::Float(2.25)), Col::Int(2).midpoint(&Col::Float(2.5)));
+        assert_eq!(None, Col::Int(2).midpoint(&Col::Float(f64::NAN)));
+        assert_eq!(None, Col::Int(2).midpoint(&Col::Null));
+    }
 }
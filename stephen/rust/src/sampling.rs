@@ -0,0 +1,479 @@
+//!
+//! Resample the training rows so the classes are closer to the same size.
+//!
+//! Random undersampling drops rows from the bigger classes, random
+//! oversampling repeats rows from the smaller ones, and SMOTE-NC makes up
+//! new rows for the smaller classes between each row and its nearest
+//! neighbours. Numbers are interpolated, text columns take the value most
+//! of the neighbours have.
+//!
+//! Only ever resample the rows a model is trained on. Scoring a model on
+//! resampled rows, or on rows made up from the ones it was trained on,
+//! makes it look better than it is. `cross_validation::validate_resampled`
+//! resamples just the training folds.
+//!
+
+use error::{DecisionsError, Result};
+use random::Rng;
+use row::{no_column, Col, DataRow};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How to resample the rows.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Method {
+    /// Drop random rows from the bigger classes.
+    Under,
+    /// Repeat random rows from the smaller classes.
+    Over,
+    /// Make up new rows for the smaller classes with SMOTE-NC.
+    Smote,
+}
+
+impl Method {
+    /// The names the methods can be given on the command line.
+    pub const NAMES: &'static [&'static str] = &["under", "over", "smote"];
+}
+
+impl FromStr for Method {
+    type Err = DecisionsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "under" => Ok(Method::Under),
+            "over" => Ok(Method::Over),
+            "smote" => Ok(Method::Smote),
+            _ => Err(DecisionsError::Config(format!(
+                "Unknown sampling method {}, expected one of {}",
+                s,
+                Method::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+///
+/// The settings for resampling.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sampling {
+    pub method: Method,
+    /// How big each smaller class should be compared to the biggest once
+    /// we are done, between 0 and 1. Classes that are already big enough are left alone.
+    pub ratio: f64,
+    /// The number of neighbours SMOTE-NC picks from.
+    pub neighbours: usize,
+    pub seed: u64,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            method: Method::Over,
+            ratio: 1.0,
+            neighbours: 5,
+            seed: 0,
+        }
+    }
+}
+
+///
+/// A row made up by SMOTE-NC. The names of the columns are shared with
+/// the other made up rows.
+///
+#[derive(Debug, Clone)]
+pub struct Synthetic {
+    id: i64,
+    names: Arc<Vec<String>>,
+    values: Vec<Col>,
+    label: String,
+}
+
+impl DataRow for Synthetic {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn name(&self, col: usize) -> Result<String> {
+        self.names.get(col).cloned().ok_or_else(|| no_column(self, col))
+    }
+
+    fn value(&self, col: usize) -> Result<Col> {
+        self.values.get(col).cloned().ok_or_else(|| no_column(self, col))
+    }
+
+    fn label(&self) -> Result<String> {
+        Ok(self.label.clone())
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+}
+
+///
+/// A row after resampling, either one of the rows we started with or one
+/// that was made up.
+///
+#[derive(Debug, Clone)]
+pub enum Sampled<'a, T: 'a> {
+    Original(&'a T),
+    Synthetic(Synthetic),
+}
+
+impl<'a, T> DataRow for Sampled<'a, T>
+where
+    T: DataRow,
+{
+    fn id(&self) -> i64 {
+        match self {
+            Sampled::Original(row) => row.id(),
+            Sampled::Synthetic(row) => row.id(),
+        }
+    }
+
+    fn name(&self, col: usize) -> Result<String> {
+        match self {
+            Sampled::Original(row) => row.name(col),
+            Sampled::Synthetic(row) => row.name(col),
+        }
+    }
+
+    fn value(&self, col: usize) -> Result<Col> {
+        match self {
+            Sampled::Original(row) => row.value(col),
+            Sampled::Synthetic(row) => row.value(col),
+        }
+    }
+
+    fn label(&self) -> Result<String> {
+        match self {
+            Sampled::Original(row) => row.label(),
+            Sampled::Synthetic(row) => row.label(),
+        }
+    }
+
+    fn labels(&self) -> Result<Vec<String>> {
+        match self {
+            Sampled::Original(row) => row.labels(),
+            Sampled::Synthetic(row) => row.labels(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Sampled::Original(row) => row.len(),
+            Sampled::Synthetic(row) => row.len(),
+        }
+    }
+
+    fn weight(&self) -> f64 {
+        match self {
+            Sampled::Original(row) => row.weight(),
+            Sampled::Synthetic(row) => row.weight(),
+        }
+    }
+}
+
+impl Sampling {
+    /// Resample the rows with the seed in the settings.
+    pub fn resample<'a, T>(&self, rows: &[&'a T]) -> Result<Vec<Sampled<'a, T>>>
+    where
+        T: DataRow,
+    {
+        if !(self.ratio > 0.0 && self.ratio <= 1.0) {
+            return Err(DecisionsError::Config(format!(
+                "The sampling ratio should be more than 0 and at most 1, not {}",
+                self.ratio
+            )));
+        }
+
+        let mut rng = Rng::new(self.seed);
+        let classes = classes(rows)?;
+        let sizes = classes.values().map(|rows| rows.len());
+        let (smallest, biggest) = (sizes.clone().min().unwrap_or(0), sizes.max().unwrap_or(0));
+
+        let mut sampled: Vec<Sampled<T>> = Vec::new();
+        for class_rows in classes.values() {
+            match self.method {
+                Method::Under => {
+                    let target = (smallest as f64 / self.ratio).ceil() as usize;
+                    let mut keep: Vec<usize> = (0..class_rows.len()).collect();
+                    if class_rows.len() > target {
+                        rng.shuffle(&mut keep);
+                        keep.truncate(target);
+                        keep.sort();
+                    }
+                    sampled.extend(keep.into_iter().map(|idx| Sampled::Original(class_rows[idx])));
+                }
+                Method::Over => {
+                    let target = (biggest as f64 * self.ratio).ceil() as usize;
+                    sampled.extend(class_rows.iter().map(|row| Sampled::Original(*row)));
+                    for _ in class_rows.len()..target {
+                        sampled.push(Sampled::Original(class_rows[rng.below(class_rows.len())]));
+                    }
+                }
+                Method::Smote => {
+                    let target = (biggest as f64 * self.ratio).ceil() as usize;
+                    sampled.extend(class_rows.iter().map(|row| Sampled::Original(*row)));
+                    if class_rows.len() < target {
+                        let id = -(sampled.len() as i64) - 1;
+                        let made = smote(class_rows, target - class_rows.len(), self.neighbours, id, &mut rng)?;
+                        sampled.extend(made.into_iter().map(Sampled::Synthetic));
+                    }
+                }
+            }
+        }
+
+        Ok(sampled)
+    }
+}
+
+/// The rows for each label.
+fn classes<'a, T>(rows: &[&'a T]) -> Result<BTreeMap<String, Vec<&'a T>>>
+where
+    T: DataRow,
+{
+    let mut classes = BTreeMap::new();
+    for row in rows {
+        classes.entry(row.label()?).or_insert_with(Vec::new).push(*row);
+    }
+    Ok(classes)
+}
+
+/// The median of the standard deviations of the numeric columns, which SMOTE-NC
+/// uses as the distance between two different values in a text column.
+fn median_std(values: &[Vec<Col>]) -> f64 {
+    let columns = values.first().map_or(0, |row| row.len());
+    let mut stds: Vec<f64> = (0..columns)
+        .filter_map(|col| {
+            let numbers: Vec<f64> = values.iter().filter_map(|row| row[col].number()).collect();
+            if numbers.is_empty() {
+                return None;
+            }
+            let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+            let var = numbers.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / numbers.len() as f64;
+            Some(var.sqrt())
+        })
+        .collect();
+    stds.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+    match stds.len() {
+        0 => 1.0,
+        n if n % 2 == 1 => stds[n / 2],
+        n => (stds[n / 2 - 1] + stds[n / 2]) / 2.0,
+    }
+}
+
+/// The squared SMOTE-NC distance between two rows.
+fn distance(a: &[Col], b: &[Col], median_std: f64) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| match (a.number(), b.number()) {
+            (Some(a), Some(b)) => (a - b).powi(2),
+            _ if a == b => 0.0,
+            _ => median_std.powi(2),
+        })
+        .sum()
+}
+
+/// A value between `a` and `b`, keeping ints as ints.
+fn between(a: &Col, b: &Col, gap: f64) -> Col {
+    match (a, b) {
+        (Col::Int(a), Col::Int(b)) => Col::Int((*a as f64 + gap * (*b - *a) as f64).round() as i64),
+        _ => match (a.number(), b.number()) {
+            (Some(x), Some(y)) => Col::Float(x + gap * (y - x)),
+            // We can't interpolate a missing value, so keep the row's own.
+            _ => a.clone(),
+        },
+    }
+}
+
+/// The value most of the neighbours have, the first of them if there is a tie.
+fn most_common(values: Vec<&Col>) -> Col {
+    let mut counts: Vec<(&Col, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .fold(None, |best: Option<(&Col, usize)>, (value, count)| match best {
+            Some((_, best_count)) if best_count >= count => best,
+            _ => Some((value, count)),
+        })
+        .map(|(value, _)| value.clone())
+        .unwrap_or_default()
+}
+
+///
+/// Make up `count` new rows from the rows of one class with SMOTE-NC.
+///
+/// Each new row starts from a random row and one of its nearest neighbours
+/// in the class. Numbers are a random point on the line between the two,
+/// text takes the most common value among all the neighbours.
+///
+fn smote<T>(rows: &[&T], count: usize, neighbours: usize, first_id: i64, rng: &mut Rng) -> Result<Vec<Synthetic>>
+where
+    T: DataRow,
+{
+    let first = rows[0];
+    let names = Arc::new((0..first.len()).map(|col| first.name(col)).collect::<Result<Vec<String>>>()?);
+    let label = first.label()?;
+    let values = rows
+        .iter()
+        .map(|row| (0..names.len()).map(|col| row.value(col)).collect::<Result<Vec<Col>>>())
+        .collect::<Result<Vec<Vec<Col>>>>()?;
+
+    let median_std = median_std(&values);
+    let k = neighbours.min(rows.len() - 1);
+    let nearest: Vec<Vec<usize>> = (0..values.len())
+        .map(|i| {
+            let mut others: Vec<(f64, usize)> = (0..values.len())
+                .filter(|&j| j != i)
+                .map(|j| (distance(&values[i], &values[j], median_std), j))
+                .collect();
+            others.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+            others.into_iter().take(k).map(|(_, j)| j).collect()
+        })
+        .collect();
+
+    Ok((0..count)
+        .map(|n| {
+            let i = rng.below(values.len());
+            let row = &values[i];
+            let values = if nearest[i].is_empty() {
+                // A class with one row has nothing to interpolate with.
+                row.clone()
+            } else {
+                let neighbour = &values[nearest[i][rng.below(nearest[i].len())]];
+                let gap = rng.float();
+                row.iter()
+                    .enumerate()
+                    .map(|(col, value)| match value.number() {
+                        Some(_) => between(value, &neighbour[col], gap),
+                        None => most_common(nearest[i].iter().map(|&j| &values[j][col]).collect()),
+                    })
+                    .collect()
+            };
+            Synthetic {
+                id: first_id - n as i64,
+                names: names.clone(),
+                values,
+                label: label.clone(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{points, Point};
+
+    /// The number of rows with each label.
+    fn sizes<T: DataRow>(rows: &[T]) -> BTreeMap<String, usize> {
+        let mut sizes = BTreeMap::new();
+        for row in rows {
+            *sizes.entry(row.label().unwrap()).or_insert(0) += 1;
+        }
+        sizes
+    }
+
+    fn imbalanced() -> Vec<Point> {
+        // Keep every No but only one in four of the Yes.
+        points(400, 11)
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, point)| point.label == "No" || idx % 4 == 0)
+            .map(|(_, point)| point)
+            .collect()
+    }
+
+    #[test]
+    fn test_under_and_over() {
+        let data = imbalanced();
+        let before = sizes(&data);
+        let (no, yes) = (before["No"], before["Yes"]);
+        assert!(no > 2 * yes);
+        let data: Vec<&Point> = data.iter().collect();
+
+        let under = Sampling {
+            method: Method::Under,
+            ..Sampling::default()
+        };
+        let sampled = under.resample(&data).unwrap();
+        assert_eq!(yes, sizes(&sampled)["No"]);
+        assert_eq!(yes, sizes(&sampled)["Yes"]);
+
+        let over = Sampling {
+            method: Method::Over,
+            ratio: 0.5,
+            ..Sampling::default()
+        };
+        let sampled = over.resample(&data).unwrap();
+        assert_eq!(no, sizes(&sampled)["No"]);
+        assert_eq!((no as f64 * 0.5).ceil() as usize, sizes(&sampled)["Yes"]);
+        assert!(sampled.iter().all(|row| matches!(row, Sampled::Original(_))));
+
+        // The same seed gives the same rows.
+        let again = over.resample(&data).unwrap();
+        let ids = |rows: &[Sampled<Point>]| rows.iter().map(|row| row.id()).collect::<Vec<i64>>();
+        assert_eq!(ids(&sampled), ids(&again));
+
+        let bad = Sampling {
+            ratio: 1.5,
+            ..Sampling::default()
+        };
+        assert!(bad.resample(&data).is_err());
+    }
+
+    #[test]
+    fn test_smote() {
+        let data = imbalanced();
+        let (no, yes) = (sizes(&data)["No"], sizes(&data)["Yes"]);
+        let data: Vec<&Point> = data.iter().collect();
+        let smote = Sampling {
+            method: Method::Smote,
+            ..Sampling::default()
+        };
+        let sampled = smote.resample(&data).unwrap();
+        assert_eq!(no, sizes(&sampled)["Yes"]);
+
+        // The made up rows are between real Yes rows, so stay in the same ranges.
+        let made: Vec<&Sampled<Point>> = sampled.iter().filter(|row| matches!(row, Sampled::Synthetic(_))).collect();
+        assert_eq!(no - yes, made.len());
+        for row in made {
+            assert!(row.id() < 0);
+            assert_eq!("Yes", row.label().unwrap());
+            match (row.value(0).unwrap(), row.value(1).unwrap()) {
+                (Col::Int(x), Col::Float(y)) => {
+                    assert!((0..10).contains(&x));
+                    assert!((0.0..=10.0).contains(&y));
+                }
+                other => panic!("Unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_smote_text() {
+        let row = |colour: &str, size| vec![Col::Text(colour.to_string()), Col::Float(size)];
+        let values = vec![row("Red", 1.0), row("Red", 2.0), row("Green", 1.5), row("Red", 10.0)];
+
+        let median_std = median_std(&values);
+        assert!(distance(&values[0], &values[1], median_std) < distance(&values[0], &values[2], median_std));
+
+        assert_eq!(Col::Int(3), between(&Col::Int(2), &Col::Int(4), 0.5));
+        assert_eq!(Col::Float(1.5), between(&Col::Float(1.0), &Col::Float(2.0), 0.5));
+        assert_eq!(Col::Float(1.0), between(&Col::Float(1.0), &Col::Null, 0.5));
+
+        let red = Col::Text("Red".to_string());
+        let green = Col::Text("Green".to_string());
+        assert_eq!(red, most_common(vec![&green, &red, &red]));
+        assert_eq!(green, most_common(vec![&green, &red]));
+        assert_eq!(Col::Int(2), most_common(vec![&Col::Int(2), &Col::Float(3.0), &Col::Float(2.0)]));
+    }
+}
@@ -0,0 +1,257 @@
+use calibration::Calibration;
+use cost::CostMatrix;
+use error::{create, open, DecisionsError, Result};
+use forest::Forest;
+use model::{most_likely, Model};
+use monotonic::Monotonic;
+use row::DataRow;
+use schema::Schema;
+use serde_json;
+use std::collections::HashMap;
+use titanic::Tickets;
+use transform::Pipeline;
+use tree::Node;
+
+///
+/// Everything we need to make predictions, so it can be
+/// written out after training and loaded back in later.
+///
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedModel {
+    /// The feature transforms fitted on the training data, if there were any.
+    pub pipeline: Option<Pipeline>,
+    /// The fields the tree was trained on, which the exporters need.
+    /// Models saved before we kept this don't have one.
+    #[serde(default)]
+    pub schema: Option<Schema>,
+    /// The tree or trees that make the predictions.
+    #[serde(flatten)]
+    pub learner: Learner,
+    /// The costs of each mistake the tree was trained with, if it was.
+    /// Predictions then have the lowest expected cost rather than being the most likely.
+    #[serde(default)]
+    pub costs: Option<CostMatrix>,
+    /// The calibration for the tree's probabilities, if it has been calibrated.
+    #[serde(default)]
+    pub calibration: Option<Calibration>,
+    /// The monotonic constraints the trees were grown with, if they were.
+    #[serde(default)]
+    pub monotonic: Option<Monotonic>,
+    /// Who was on each ticket in the training rows, to share out the fares
+    /// of the rows we predict. Models saved before we kept these, or
+    /// streamed without them, have no fare per person.
+    #[serde(default)]
+    pub tickets: Option<Tickets>,
+}
+
+///
+/// What was trained. It is saved under its own name, so models saved
+/// before there was a choice still load as a tree.
+///
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Learner {
+    Tree(Node),
+    Forest(Forest),
+}
+
+impl Learner {
+    /// The trees, just the one unless it is a forest.
+    pub fn trees(&self) -> &[Node] {
+        match self {
+            Learner::Tree(tree) => std::slice::from_ref(tree),
+            Learner::Forest(forest) => &forest.trees,
+        }
+    }
+}
+
+impl Model for Learner {
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        match self {
+            Learner::Tree(tree) => tree.predict_proba(row),
+            Learner::Forest(forest) => forest.predict_proba(row),
+        }
+    }
+
+    fn predict<T>(&self, row: &T) -> Result<String>
+    where
+        T: DataRow,
+    {
+        match self {
+            Learner::Tree(tree) => tree.predict(row),
+            Learner::Forest(forest) => forest.predict(row),
+        }
+    }
+}
+
+impl SavedModel {
+    pub fn save(&self, file: &str) -> Result<()> {
+        serde_json::to_writer(create(file)?, self)?;
+        Ok(())
+    }
+
+    pub fn load(file: &str) -> Result<Self> {
+        Ok(serde_json::from_reader(open(file)?)?)
+    }
+
+    ///
+    /// The schema to export the trees with. The PMML and ONNX exports only
+    /// hold the trees, so a model that decides with costs, calibrates its
+    /// probabilities or transforms its rows first can't be exported as it
+    /// would predict something different.
+    ///
+    pub fn export_schema(&self) -> Result<&Schema> {
+        let cant_hold = |what: &str| {
+            Err(DecisionsError::Model(format!(
+                "The model {}, which the exports can't hold, so it can't be exported",
+                what
+            )))
+        };
+        if self.costs.is_some() {
+            return cant_hold("predicts with costs");
+        }
+        if self.calibration.is_some() {
+            return cant_hold("calibrates its probabilities");
+        }
+        if self.pipeline.is_some() {
+            return cant_hold("transforms the rows with a pipeline");
+        }
+        self.schema.as_ref().ok_or_else(|| {
+            DecisionsError::Model("The model doesn't have a schema, train it again to export it".to_string())
+        })
+    }
+}
+
+///
+/// Predictions for rows that have already been through the pipeline.
+///
+impl Model for SavedModel {
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        let probabilities = self.learner.predict_proba(row)?;
+        Ok(match self.calibration {
+            Some(ref calibration) => calibration.apply(&probabilities),
+            None => probabilities,
+        })
+    }
+
+    fn predict<T>(&self, row: &T) -> Result<String>
+    where
+        T: DataRow,
+    {
+        match self.costs {
+            Some(ref costs) => Ok(costs.decide(&self.predict_proba(row)?)),
+            None if self.calibration.is_some() => Ok(most_likely(self.predict_proba(row)?)),
+            None => self.learner.predict(row),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use calibration::Method;
+    use forest::ExtraTreesParams;
+    use model::Estimator;
+    use row::test::{training_data, Fruit};
+    use tree::build_tree;
+
+    #[test]
+    fn test_learners() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let tree = build_tree(&data, None).unwrap();
+        let forest = ExtraTreesParams {
+            trees: 3,
+            ..ExtraTreesParams::default()
+        }
+        .fit(&data)
+        .unwrap();
+
+        // A tree is saved as it always was, so older models still load.
+        let saved = SavedModel {
+            pipeline: None,
+            schema: None,
+            learner: Learner::Tree(tree.clone()),
+            costs: None,
+            calibration: None,
+            monotonic: None,
+            tickets: None,
+        };
+        let json = serde_json::to_value(&saved).unwrap();
+        assert_eq!(serde_json::to_value(&tree).unwrap(), json["tree"]);
+        let legacy = serde_json::json!({ "pipeline": null, "tree": json["tree"] });
+        let loaded: SavedModel = serde_json::from_value(legacy).unwrap();
+        assert_eq!(1, loaded.learner.trees().len());
+
+        let saved = SavedModel {
+            learner: Learner::Forest(forest),
+            ..saved
+        };
+        let loaded: SavedModel = serde_json::from_str(&serde_json::to_string(&saved).unwrap()).unwrap();
+        assert_eq!(3, loaded.learner.trees().len());
+        for row in &data {
+            assert_eq!(saved.predict_proba(*row).unwrap(), loaded.predict_proba(*row).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_export_schema() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let saved = SavedModel {
+            pipeline: None,
+            schema: None,
+            learner: Learner::Tree(build_tree(&data, None).unwrap()),
+            costs: None,
+            calibration: None,
+            monotonic: None,
+            tickets: None,
+        };
+        assert!(matches!(saved.export_schema(), Err(DecisionsError::Model(_))));
+
+        let saved = SavedModel {
+            schema: Some(Schema::from_rows(&data, "Fruit").unwrap()),
+            ..saved
+        };
+        assert_eq!(saved.schema.as_ref(), Some(saved.export_schema().unwrap()));
+
+        // The exports would pick the most likely label rather than the cheapest.
+        let mut costs = CostMatrix::new();
+        costs.set("Apple", "Grape", 5.0).unwrap();
+        let costed = SavedModel {
+            costs: Some(costs),
+            ..saved
+        };
+        assert!(matches!(costed.export_schema(), Err(DecisionsError::Model(_))));
+
+        // Nor do they hold the calibration or the pipeline.
+        let saved = SavedModel {
+            costs: None,
+            ..costed
+        };
+        let probabilities: Vec<HashMap<String, f64>> =
+            data.iter().map(|row| saved.predict_proba(*row).unwrap()).collect();
+        let labels: Vec<String> = data.iter().map(|row| row.label().unwrap()).collect();
+        let calibrated = SavedModel {
+            calibration: Some(Calibration::fit(Method::Platt, &probabilities, &labels)),
+            ..saved
+        };
+        assert!(matches!(calibrated.export_schema(), Err(DecisionsError::Model(_))));
+
+        let saved = SavedModel {
+            calibration: None,
+            ..calibrated
+        };
+        let transformed = SavedModel {
+            pipeline: Some(Pipeline::default()),
+            ..saved
+        };
+        assert!(matches!(transformed.export_schema(), Err(DecisionsError::Model(_))));
+    }
+}
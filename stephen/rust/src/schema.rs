@@ -0,0 +1,159 @@
+use error::{DecisionsError, Result};
+use row::{Col, DataRow};
+use std::collections::{BTreeSet, HashMap};
+
+/// What sort of data a field holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldKind {
+    /// A text column along with every category we have seen for it.
+    Categorical(Vec<String>),
+    /// An int or float column.
+    Continuous,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    pub kind: FieldKind,
+}
+
+///
+/// Describes the inputs and output of a model so that it can
+/// be exported to formats that need to know about them up front.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    pub fields: Vec<Field>,
+    pub target: String,
+    pub classes: Vec<String>,
+}
+
+impl Schema {
+    ///
+    /// Work out the schema from the rows we trained with.
+    /// Field names come from `DataRow::name`, and the type of each
+    /// field is taken from the first non null value in the column.
+    ///
+    /// #Arguments
+    ///
+    /// * `rows` - The training rows.
+    /// * `target` - The name to give the label we are predicting.
+    ///
+    pub fn from_rows<T>(rows: &[&T], target: &str) -> Result<Self>
+    where
+        T: DataRow,
+    {
+        if rows.is_empty() {
+            return Err(DecisionsError::Config("There are no rows to find the fields from".to_string()));
+        }
+        let mut builder = SchemaBuilder::default();
+        for row in rows {
+            builder.add(*row)?;
+        }
+        Ok(builder.build(target))
+    }
+
+    /// The categories of the given column, or None if it is continuous.
+    pub fn categories(&self, col: usize) -> Option<&[String]> {
+        match self.fields[col].kind {
+            FieldKind::Categorical(ref categories) => Some(categories),
+            FieldKind::Continuous => None,
+        }
+    }
+
+    /// The probability of each of our classes, in schema order, from a leaf's counts.
+    /// A leaf with no weight gives every class a probability of 0.
+    pub fn distribution(&self, predictions: &HashMap<String, f64>) -> Vec<f64> {
+        let total: f64 = predictions.values().sum();
+        if total <= 0.0 {
+            return vec![0.0; self.classes.len()];
+        }
+        self.classes
+            .iter()
+            .map(|class| predictions.get(class).unwrap_or(&0.0) / total)
+            .collect()
+    }
+}
+
+///
+/// Works out a schema a row at a time, for when the rows are streamed
+/// rather than all held in memory. It only keeps the categories it has
+/// seen, so it gives the same schema as `Schema::from_rows`.
+///
+#[derive(Debug, Default)]
+pub struct SchemaBuilder {
+    /// The name of each field, whether its first non null value was text, and its categories.
+    fields: Vec<(String, Option<bool>, BTreeSet<String>)>,
+    classes: BTreeSet<String>,
+}
+
+impl SchemaBuilder {
+    pub fn add<T>(&mut self, row: &T) -> Result<()>
+    where
+        T: DataRow,
+    {
+        if self.fields.is_empty() {
+            for col in 0..row.len() {
+                self.fields.push((row.name(col)?, None, BTreeSet::new()));
+            }
+        }
+        for (col, (_, text, categories)) in self.fields.iter_mut().enumerate() {
+            let value = row.value(col)?;
+            if text.is_none() && value != Col::Null {
+                *text = Some(matches!(value, Col::Text(_)));
+            }
+            if let Col::Text(category) = value {
+                categories.insert(category);
+            }
+        }
+        self.classes.insert(row.label()?);
+        Ok(())
+    }
+
+    pub fn build(self, target: &str) -> Schema {
+        let fields = self
+            .fields
+            .into_iter()
+            .map(|(name, text, categories)| Field {
+                name,
+                kind: match text {
+                    Some(true) => FieldKind::Categorical(categories.into_iter().collect()),
+                    _ => FieldKind::Continuous,
+                },
+            })
+            .collect();
+        Schema {
+            fields,
+            target: target.to_string(),
+            classes: self.classes.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{training_data, Fruit};
+
+    #[test]
+    fn test_no_rows() {
+        let empty: Vec<&Fruit> = Vec::new();
+        assert!(Schema::from_rows(&empty, "Fruit").is_err());
+    }
+
+    #[test]
+    fn test_from_rows() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let schema = Schema::from_rows(&data, "Fruit").unwrap();
+
+        assert_eq!(3, schema.fields.len());
+        assert_eq!("Colour", schema.fields[0].name);
+        assert_eq!(
+            Some(&["Green".to_string(), "Red".to_string(), "Yellow".to_string()][..]),
+            schema.categories(0)
+        );
+        assert_eq!(FieldKind::Continuous, schema.fields[1].kind);
+        assert_eq!(vec!["Apple", "Grape", "Lemon"], schema.classes);
+    }
+}
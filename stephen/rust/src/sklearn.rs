@@ -0,0 +1,428 @@
+//!
+//! Import a decision tree trained by scikit-learn.
+//!
+//! The tree is read from a JSON dump of a fitted `DecisionTreeClassifier`,
+//! made from the arrays on its `tree_` attribute :
+//!
+//! ```python
+//! import json
+//! t = clf.tree_
+//! json.dump({
+//!     "feature_names": list(clf.feature_names_in_),
+//!     "classes": [str(c) for c in clf.classes_],
+//!     "children_left": t.children_left.tolist(),
+//!     "children_right": t.children_right.tolist(),
+//!     "feature": t.feature.tolist(),
+//!     "threshold": t.threshold.tolist(),
+//!     "value": t.value[:, 0, :].tolist(),
+//!     "n_node_samples": t.n_node_samples.tolist(),
+//!     "weighted_n_node_samples": t.weighted_n_node_samples.tolist(),
+//!     "missing_go_to_left": t.missing_go_to_left.tolist(),
+//! }, open("tree.json", "w"))
+//! ```
+//!
+//! Each feature name is either the name of one of our columns, or
+//! `column=category` for a one hot encoded text column.
+//!
+//! scikit-learn sends a row left when `float32(x) <= threshold`, whereas our
+//! questions are `x >= value` (or `x == category`) with the true branch on
+//! the right. So the right child becomes the true branch, and the value is
+//! the smallest float that rounds to a float32 above the threshold. That is
+//! the same for int and float columns, and for columns with both in them.
+//! For one hot columns the question becomes `column == category`. The split
+//! is then exactly the same.
+//!
+//! Missing values take the left child, unless `missing_go_to_left` says
+//! a node sends them right. Dumps without it send them all left, as
+//! scikit-learn did before it learnt to split on missing values. A missing
+//! text value is taken to be none of the categories, so it goes left.
+//!
+//! Each leaf keeps its class distribution scaled by the weight of the rows
+//! that reached it, so trees trained with sample or class weights predict
+//! the same probabilities. Older dumps without `weighted_n_node_samples`
+//! use the number of rows instead.
+//!
+
+use error::{open, DecisionsError, Result};
+use std::io::Read;
+use row::{Col, DataRow};
+use question::{Predicate, Question};
+use serde_json;
+use std::collections::HashMap;
+use tree::Node;
+
+/// Makes the question for a feature given the sklearn threshold, and
+/// whether missing values go left.
+type MakeQuestion = Box<dyn Fn(f64, bool) -> Question>;
+
+#[derive(Debug, Deserialize)]
+pub struct SklearnTree {
+    feature_names: Vec<String>,
+    classes: Vec<String>,
+    children_left: Vec<i64>,
+    children_right: Vec<i64>,
+    feature: Vec<i64>,
+    threshold: Vec<f64>,
+    value: Vec<Vec<f64>>,
+    n_node_samples: Vec<usize>,
+    #[serde(default)]
+    weighted_n_node_samples: Option<Vec<f64>>,
+    #[serde(default)]
+    missing_go_to_left: Option<Vec<u8>>,
+}
+
+///
+/// Turn a sklearn `float32(x) <= threshold` into the value for our
+/// `x >= value` question. The row goes right when x rounds to the first
+/// float32 above the threshold or beyond, so the value is the point half
+/// way between that float32 and the one below it, nudged up if a tie
+/// there would round down to the even one below.
+///
+fn threshold(threshold: f64) -> Col {
+    let nearest = threshold as f32;
+    let above = if nearest as f64 > threshold { nearest } else { nearest.next_up() };
+    let below = above.next_down();
+    let half_way = (below as f64 + above as f64) / 2.0;
+    Col::Float(if above.to_bits() % 2 == 0 { half_way } else { half_way.next_up() })
+}
+
+/// The first value in the column that isn't null, so we know its type.
+fn sample_value<T>(rows: &[&T], col: usize) -> Result<Col>
+where
+    T: DataRow,
+{
+    for row in rows {
+        let value = row.value(col)?;
+        if value != Col::Null {
+            return Ok(value);
+        }
+    }
+    Ok(Col::Null)
+}
+
+impl SklearnTree {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let tree: SklearnTree = serde_json::from_str(json)?;
+        let nodes = tree.children_left.len();
+        if tree.children_right.len() != nodes
+            || tree.feature.len() != nodes
+            || tree.threshold.len() != nodes
+            || tree.value.len() != nodes
+            || tree.n_node_samples.len() != nodes
+            || tree.weighted_n_node_samples.as_ref().is_some_and(|weights| weights.len() != nodes)
+            || tree.missing_go_to_left.as_ref().is_some_and(|missing| missing.len() != nodes)
+        {
+            return Err(DecisionsError::Model(
+                "The node arrays in the tree are different lengths".to_string(),
+            ));
+        }
+        Ok(tree)
+    }
+
+    ///
+    /// Work out the question for each of the features in the tree.
+    ///
+    fn questions<T>(&self, rows: &[&T]) -> Result<Vec<MakeQuestion>>
+    where
+        T: DataRow,
+    {
+        let first_row = rows.first().ok_or_else(|| {
+            DecisionsError::Config("There are no rows to find the columns of the features in".to_string())
+        })?;
+        let columns = (0..first_row.len())
+            .map(|col| Ok((first_row.name(col)?, col)))
+            .collect::<Result<HashMap<String, usize>>>()?;
+
+        self.feature_names
+            .iter()
+            .map(|feature| {
+                let (name, category) = match columns.get(feature) {
+                    Some(_) => (feature.clone(), None),
+                    None => match feature.find('=') {
+                        Some(idx) => (
+                            feature[..idx].to_string(),
+                            Some(feature[idx + 1..].to_string()),
+                        ),
+                        None => (feature.clone(), None),
+                    },
+                };
+
+                let col = *columns
+                    .get(&name)
+                    .ok_or_else(|| DecisionsError::Schema(format!("Unknown feature {}", feature)))?;
+                let sample = sample_value(rows, col)?;
+
+                let question: MakeQuestion = match (category, &sample) {
+                    (Some(category), _) => Box::new(move |_, _| {
+                        Question::new(name.clone(), col, Col::Text(category.clone()))
+                    }),
+                    (None, Col::Text(_)) => {
+                        return Err(DecisionsError::Schema(format!(
+                            "{} is a text column so needs to be one hot encoded as {}=category",
+                            feature, feature
+                        )))
+                    }
+                    (None, _) => Box::new(move |t, missing_left| {
+                        let predicate = if missing_left {
+                            Predicate::AtLeast(threshold(t))
+                        } else {
+                            Predicate::Less(threshold(t)).negate()
+                        };
+                        Question::with_predicate(name.clone(), col, predicate)
+                    }),
+                };
+
+                Ok(question)
+            })
+            .collect()
+    }
+
+    /// Turn the class distribution at a node into the weight of each class,
+    /// keeping the proportions as they are.
+    fn predictions(&self, node: usize) -> HashMap<String, f64> {
+        let total: f64 = self.value[node].iter().sum();
+        let weight = match self.weighted_n_node_samples {
+            Some(ref weights) => weights[node],
+            None => self.n_node_samples[node] as f64,
+        };
+        self.classes
+            .iter()
+            .zip(self.value[node].iter())
+            .filter(|(_, &v)| v > 0.0)
+            .map(|(class, v)| (class.clone(), v / total * weight))
+            .collect()
+    }
+
+    /// The index of a child of the node, if it is a node of the tree.
+    fn child(&self, node: usize, child: i64) -> Result<usize> {
+        if child >= 0 && (child as usize) < self.children_left.len() {
+            Ok(child as usize)
+        } else {
+            Err(DecisionsError::Model(format!("Node {} has a child {} that isn't in the tree", node, child)))
+        }
+    }
+
+    /// Convert the node and everything under it, checking that no node is reached twice.
+    fn node(&self, questions: &[MakeQuestion], node: usize, visited: &mut [bool]) -> Result<Node> {
+        if visited[node] {
+            return Err(DecisionsError::Model(format!("Node {} is reached more than once", node)));
+        }
+        visited[node] = true;
+
+        if self.children_left[node] < 0 && self.children_right[node] < 0 {
+            return Ok(Node::Leaf {
+                predictions: self.predictions(node),
+            });
+        }
+        let left = self.child(node, self.children_left[node])?;
+        let right = self.child(node, self.children_right[node])?;
+
+        let feature = self.feature[node] as usize;
+        let question = questions
+            .get(feature)
+            .ok_or_else(|| DecisionsError::Model(format!("Node {} uses unknown feature {}", node, feature)))?;
+
+        // Right is `x > threshold`, which is the true branch of our question.
+        let true_branch = self.node(questions, right, visited)?;
+        let false_branch = self.node(questions, left, visited)?;
+        let missing_left = self.missing_go_to_left.as_ref().is_none_or(|missing| missing[node] != 0);
+
+        Ok(Node::new_decision(
+            question(self.threshold[node], missing_left),
+            true_branch,
+            false_branch,
+        ))
+    }
+
+    ///
+    /// Convert into one of our trees.
+    ///
+    /// #Arguments
+    ///
+    /// * `rows` - Rows of the data the tree is going to be used on. We use these
+    ///   to map the feature names onto columns and to find the type of each column.
+    ///
+    pub fn to_tree<T>(&self, rows: &[&T]) -> Result<Node>
+    where
+        T: DataRow,
+    {
+        let questions = self.questions(rows)?;
+        if self.children_left.is_empty() {
+            return Err(DecisionsError::Model("The tree has no nodes".to_string()));
+        }
+        self.node(&questions, 0, &mut vec![false; self.children_left.len()])
+    }
+}
+
+///
+/// Load a scikit-learn tree dump from the given file.
+///
+pub fn load_tree<T>(file: &str, rows: &[&T]) -> Result<Node>
+where
+    T: DataRow,
+{
+    let mut json = String::new();
+    open(file)?.read_to_string(&mut json)?;
+    SklearnTree::from_json(&json)?.to_tree(rows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::Model;
+    use row::test::{training_data, Fruit};
+    use tree::classify;
+
+    // `Things <= 2` splits off the grapes, then `Colour=Yellow <= 0.5` separates
+    // the apples from the lemons.
+    const TREE: &str = r#"{
+        "feature_names": ["Colour=Yellow", "Things"],
+        "classes": ["Apple", "Grape", "Lemon"],
+        "children_left":  [1, -1, 3, -1, -1],
+        "children_right": [2, -1, 4, -1, -1],
+        "feature":   [1, -2, 0, -2, -2],
+        "threshold": [2.0, -2.0, 0.5, -2.0, -2.0],
+        "value": [[0.4, 0.4, 0.2], [0.0, 1.0, 0.0], [0.3333, 0.0, 0.6666],
+                  [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+        "n_node_samples": [5, 2, 3, 1, 2]
+    }"#;
+
+    #[test]
+    fn test_import() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let tree = SklearnTree::from_json(TREE).unwrap().to_tree(&data).unwrap();
+
+        match tree {
+            Node::Decision { ref question, .. } => {
+                assert_eq!("Things", question.field_name());
+                assert_eq!(&Predicate::AtLeast(threshold(2.0)), question.predicate());
+            }
+            _ => panic!("Expected a decision"),
+        }
+
+        let fruit = |colour: &str, things| Fruit::new(1, colour.to_string(), things, "".to_string());
+        assert_eq!("Grape", classify(&fruit("Red", 2), &tree).unwrap());
+        assert_eq!("Apple", classify(&fruit("Red", 3), &tree).unwrap());
+        assert_eq!("Lemon", classify(&fruit("Yellow", 3), &tree).unwrap());
+        assert_eq!("Apple", classify(&fruit("Green", 4), &tree).unwrap());
+
+        if let Node::Decision { ref true_branch, .. } = tree {
+            if let Node::Decision { ref false_branch, .. } = **true_branch {
+                let mut expected = HashMap::new();
+                expected.insert("Apple".to_string(), 1.0);
+                match **false_branch {
+                    Node::Leaf { ref predictions } => assert_eq!(&expected, predictions),
+                    _ => panic!("Expected a leaf"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_threshold() {
+        // Whether sklearn sends the value right.
+        let right = |t: f64, x: &Col| {
+            Predicate::AtLeast(threshold(t)).matches("x", x).unwrap()
+        };
+
+        // Ints and floats in the same column go right when they are above the threshold.
+        assert!(!right(2.5, &Col::Int(2)));
+        assert!(right(2.5, &Col::Float(2.7)));
+        assert!(right(2.5, &Col::Int(3)));
+        assert!(!right(2.0, &Col::Int(2)));
+        assert!(right(-2.5, &Col::Int(-2)));
+        assert!(!right(-2.5, &Col::Float(-2.5)));
+
+        // Values that round down to the threshold as a float32 go left.
+        let ulp = 2.5_f32.next_up() as f64 - 2.5;
+        assert!(!right(2.5, &Col::Float(2.5 + ulp / 4.0)));
+        assert!(right(2.5, &Col::Float(2.5 + ulp)));
+        assert!(right(2.5, &Col::Float(2.5 + ulp * 0.75)));
+
+        // The value is exactly where sklearn starts sending rows right, ties included.
+        for t in &[2.5, 3.0, 28.5, 1e-3, -7.25, 100000.1, 0.0, -1e-40] {
+            let sklearn = |x: f64| (x as f32) as f64 > *t;
+            match threshold(*t) {
+                Col::Float(v) => {
+                    assert!(sklearn(v));
+                    assert!(!sklearn(v.next_down()));
+                }
+                _ => panic!("Expected a float"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_values() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let root = |json: &str| match SklearnTree::from_json(json).unwrap().to_tree(&data).unwrap() {
+            Node::Decision { question, .. } => question,
+            _ => panic!("Expected a decision"),
+        };
+
+        // Missing values go left, our false branch, unless the node says otherwise.
+        let question = root(TREE);
+        assert!(!question.predicate().matches("Things", &Col::Null).unwrap());
+        let question = root(&TREE.replace("\"n_node_samples\"", "\"missing_go_to_left\": [0, 1, 1, 1, 1], \"n_node_samples\""));
+        assert!(question.predicate().matches("Things", &Col::Null).unwrap());
+        assert!(!question.predicate().matches("Things", &Col::Int(2)).unwrap());
+        assert!(question.predicate().matches("Things", &Col::Int(3)).unwrap());
+
+        let json = TREE.replace("\"n_node_samples\"", "\"missing_go_to_left\": [1], \"n_node_samples\"");
+        assert!(SklearnTree::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn test_unknown_feature() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let json = TREE.replace("\"Things\"]", "\"Weight\"]");
+        assert!(SklearnTree::from_json(&json).unwrap().to_tree(&data).is_err());
+    }
+
+    #[test]
+    fn test_text_without_category() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let json = TREE.replace("\"Colour=Yellow\"", "\"Colour\"");
+        assert!(SklearnTree::from_json(&json).unwrap().to_tree(&data).is_err());
+    }
+
+    #[test]
+    fn test_weighted_leaves() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let json = TREE
+            .replace("[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]", "[1.0, 0.0, 0.0], [0.7, 0.0, 0.3]]")
+            .replace(
+                "\"n_node_samples\": [5, 2, 3, 1, 2]",
+                "\"n_node_samples\": [5, 2, 3, 1, 2], \"weighted_n_node_samples\": [5.0, 2.0, 3.0, 1.0, 2.0]",
+            );
+        let tree = SklearnTree::from_json(&json).unwrap().to_tree(&data).unwrap();
+
+        // The proportions aren't rounded into a tie.
+        let fruit = Fruit::new(1, "Yellow".to_string(), 3, "".to_string());
+        let probabilities = tree.predict_proba(&fruit).unwrap();
+        assert!((probabilities["Apple"] - 0.7).abs() < 1e-12);
+        assert!((probabilities["Lemon"] - 0.3).abs() < 1e-12);
+        assert_eq!("Apple", classify(&fruit, &tree).unwrap());
+    }
+
+    #[test]
+    fn test_bad_children() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+
+        // A right child that is missing, or past the end of the tree.
+        for right in &["[2, -1, -1, -1, -1]", "[2, -1, 9, -1, -1]"] {
+            let json = TREE.replace("[2, -1, 4, -1, -1]", right);
+            assert!(SklearnTree::from_json(&json).unwrap().to_tree(&data).is_err());
+        }
+
+        // A node that leads back to the root.
+        let json = TREE.replace("[2, -1, 4, -1, -1]", "[2, -1, 0, -1, -1]");
+        assert!(SklearnTree::from_json(&json).unwrap().to_tree(&data).is_err());
+    }
+}
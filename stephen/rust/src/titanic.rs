@@ -1,9 +1,10 @@
-use std::collections::HashMap;
-use std::error::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
 use csv;
-use row::{Col, DataRow};
+use error::{open, DecisionsError, Result};
+use row::{no_column, Col, DataRow};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Titanic {
     id: i64,
     survived: Option<i64>,
@@ -13,38 +14,122 @@ pub struct Titanic {
     age: Option<f64>,
     sibsp: i64,
     parch: i64,
-    ticket: String
+    ticket: String,
+    fare: Option<f64>,
+    cabin: Option<String>,
+    embarked: Option<String>,
+    /// The number of passengers travelling on the same ticket, if we have
+    /// the `Tickets` from the training rows to count them with.
+    #[serde(skip)]
+    ticket_size: Option<usize>
+}
+
+impl Titanic {
+    ///
+    /// The passenger's title, taken from between the comma and the full stop
+    /// in their name ("Braund, Mr. Owen Harris"). The French and unusual
+    /// titles are folded into the common ones, anything else is "Rare".
+    ///
+    fn title(&self) -> Option<&'static str> {
+        let start = self.name.find(',')? + 1;
+        let end = start + self.name[start..].find('.')?;
+        Some(match self.name[start..end].trim() {
+            "Mr" => "Mr",
+            "Mrs" | "Mme" => "Mrs",
+            "Miss" | "Mlle" | "Ms" => "Miss",
+            "Master" => "Master",
+            _ => "Rare"
+        })
+    }
+
+    /// Siblings, spouses, parents and children on board, plus the passenger.
+    fn family_size(&self) -> i64 {
+        self.sibsp + self.parch + 1
+    }
+
+    /// The deck is the letter at the start of the cabin.
+    fn deck(&self) -> Option<String> {
+        self.cabin.as_ref()
+            .and_then(|cabin| cabin.chars().next())
+            .map(|deck| deck.to_string())
+    }
+
+    /// The fare is for the whole ticket, so share it out amongst everyone on it.
+    fn fare_per_person(&self) -> Option<f64> {
+        Some(self.fare? / self.ticket_size? as f64)
+    }
+
+    ///
+    /// The letters at the start of the ticket, tidied up so
+    /// "STON/O2. 3101282" and "STON/O 2. 3101290" both give "STONO2".
+    /// Tickets that are just a number have a prefix of "None".
+    ///
+    fn ticket_prefix(&self) -> String {
+        let ticket = self.ticket.trim();
+        match ticket.rfind(' ') {
+            Some(idx) => ticket[..idx]
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_uppercase(),
+            None => "None".to_string()
+        }
+    }
+}
+
+/// Empty strings in the csv are missing values.
+fn text(value: &Option<String>) -> Col {
+    match value {
+        Some(ref value) if !value.is_empty() => Col::Text(value.clone()),
+        _ => Col::Null
+    }
 }
 
 impl DataRow for Titanic {
-    fn name(&self, col: usize) -> String {
-        (match col {
+    fn name(&self, col: usize) -> Result<String> {
+        Ok((match col {
             0 => "Class",
             1 => "Sex",
             2 => "Age",
             3 => "Siblings",
             4 => "Parch",
-            _ => panic!("Silly column")
-        }).to_string()
+            5 => "Title",
+            6 => "FamilySize",
+            7 => "IsAlone",
+            8 => "Deck",
+            9 => "Fare",
+            10 => "FarePerPerson",
+            11 => "Embarked",
+            12 => "TicketPrefix",
+            _ => return Err(no_column(self, col))
+        }).to_string())
     }
 
-    fn value(&self, col: usize) -> Col {
-        match col {
+    fn value(&self, col: usize) -> Result<Col> {
+        Ok(match col {
             0 => Col::Int(self.pclass),
             1 => Col::Text(self.sex.clone()),
-            2 => self.age.map(|a| Col::Float(a)).unwrap_or(Col::Null),
+            2 => self.age.map(Col::Float).unwrap_or(Col::Null),
             3 => Col::Int(self.sibsp),
             4 => Col::Int(self.parch),
-            _ => panic!("Silly column")
-        }
+            5 => self.title().map(|t| Col::Text(t.to_string())).unwrap_or(Col::Null),
+            6 => Col::Int(self.family_size()),
+            7 => Col::Text((if self.family_size() == 1 { "Yes" } else { "No" }).to_string()),
+            8 => text(&self.deck()),
+            9 => self.fare.map(Col::Float).unwrap_or(Col::Null),
+            10 => self.fare_per_person().map(Col::Float).unwrap_or(Col::Null),
+            11 => text(&self.embarked),
+            12 => Col::Text(self.ticket_prefix()),
+            _ => return Err(no_column(self, col))
+        })
     }
 
-    fn label(&self) -> String {
-        (if self.survived.unwrap() == 1 {
-            "Lived"
-        } else {
-            "Died"
-        }).to_string()
+    fn label(&self) -> Result<String> {
+        match self.survived {
+            Some(1) => Ok("Lived".to_string()),
+            Some(_) => Ok("Died".to_string()),
+            None => Err(DecisionsError::MissingLabel { id: self.id })
+        }
     }
 
     fn id(&self) -> i64 {
@@ -52,12 +137,52 @@ impl DataRow for Titanic {
     }
 
     fn len(&self) -> usize {
-        5
+        13
+    }
+}
+
+///
+/// The passengers on each ticket in the training rows, saved with the model
+/// so that the fare per person is shared out the same way for the rows it
+/// is trained on and the rows it predicts.
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tickets {
+    passengers: BTreeMap<String, Vec<i64>>,
+}
+
+impl Tickets {
+    pub fn fit(rows: &[Titanic]) -> Self {
+        let mut passengers: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+        for row in rows {
+            passengers.entry(row.ticket.trim().to_string()).or_default().push(row.id);
+        }
+        Tickets { passengers }
+    }
+
+    /// The training passengers on the passenger's ticket, plus the passenger if they aren't one of them.
+    pub fn size(&self, passenger: &Titanic) -> usize {
+        match self.passengers.get(passenger.ticket.trim()) {
+            Some(ids) if ids.contains(&passenger.id) => ids.len(),
+            Some(ids) => ids.len() + 1,
+            None => 1,
+        }
+    }
+
+    /// Count who each of the passengers shares a ticket with, for their fare per person.
+    pub fn apply(&self, rows: &mut [Titanic]) {
+        for row in rows {
+            row.ticket_size = Some(self.size(row));
+        }
     }
 }
 
-pub fn load_titanic(file: &str) -> Result<Vec<Titanic>, Box<Error>> {
-    let mut rdr = csv::Reader::from_path(file)?;
+///
+/// Read the passengers from the file. Their fare per person is missing
+/// until `Tickets::apply` counts who they share a ticket with.
+///
+pub fn load_titanic(file: &str) -> Result<Vec<Titanic>> {
+    let mut rdr = csv::Reader::from_reader(open(file)?);
     let mut result = Vec::new();
     for record in rdr.deserialize() { 
         let record: Titanic = record?;
@@ -67,15 +192,26 @@ pub fn load_titanic(file: &str) -> Result<Vec<Titanic>, Box<Error>> {
     Ok(result)
 }
 
+///
+/// Read the passengers one at a time, so a file of any size can be
+/// streamed through without holding it in memory. Without the whole file
+/// we can't count who shares a ticket, so the fare per person is missing.
+///
+pub fn stream_titanic<R: Read>(reader: R) -> impl Iterator<Item = Result<Titanic>> {
+    csv::Reader::from_reader(reader)
+        .into_deserialize()
+        .map(|record| Ok(record?))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Check {
     id: i64,
     survived: i64
 }
 
-pub fn load_check(file: &str) -> Result<HashMap<i64, String>, Box<Error>> {
+pub fn load_check(file: &str) -> Result<HashMap<i64, String>> {
     let mut result = HashMap::new();
-    let mut rdr = csv::Reader::from_path(file)?;
+    let mut rdr = csv::Reader::from_reader(open(file)?);
     for record in rdr.deserialize() { 
         let record: Check = record?;
         result.insert(record.id, 
@@ -88,3 +224,127 @@ pub fn load_check(file: &str) -> Result<HashMap<i64, String>, Box<Error>> {
     
     Ok(result)
 }
+
+///
+/// Label the test rows with whether each passenger lived, from the check
+/// file's answers, so they can be scored like the training rows.
+///
+pub fn add_labels(rows: &mut [Titanic], check: &HashMap<i64, String>) -> Result<()> {
+    for row in rows {
+        let label = check.get(&row.id).ok_or(DecisionsError::MissingLabel { id: row.id })?;
+        row.survived = Some(if label == "Lived" { 1 } else { 0 });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn passenger(name: &str, ticket: &str, cabin: Option<&str>) -> Titanic {
+        Titanic {
+            id: 1,
+            survived: Some(1),
+            pclass: 1,
+            name: name.to_string(),
+            sex: "female".to_string(),
+            age: Some(38.0),
+            sibsp: 1,
+            parch: 0,
+            ticket: ticket.to_string(),
+            fare: Some(71.2833),
+            cabin: cabin.map(|c| c.to_string()),
+            embarked: Some("".to_string()),
+            ticket_size: Some(2)
+        }
+    }
+
+    #[test]
+    fn test_title() {
+        let p = passenger("Cumings, Mrs. John Bradley (Florence Briggs Thayer)", "PC 17599", None);
+        assert_eq!(Col::Text("Mrs".to_string()), p.value(5).unwrap());
+
+        let p = passenger("Rothes, the Countess. of (Lucy Noel Martha Dyer-Edwards)", "110152", None);
+        assert_eq!(Col::Text("Rare".to_string()), p.value(5).unwrap());
+
+        let p = passenger("Aubart, Mme. Leontine Pauline", "PC 17477", None);
+        assert_eq!(Col::Text("Mrs".to_string()), p.value(5).unwrap());
+    }
+
+    #[test]
+    fn test_family() {
+        let p = passenger("Cumings, Mrs. John Bradley", "PC 17599", None);
+        assert_eq!(Col::Int(2), p.value(6).unwrap());
+        assert_eq!(Col::Text("No".to_string()), p.value(7).unwrap());
+    }
+
+    #[test]
+    fn test_cabin_and_fare() {
+        let p = passenger("Cumings, Mrs. John Bradley", "PC 17599", Some("C85"));
+        assert_eq!(Col::Text("C".to_string()), p.value(8).unwrap());
+        assert_eq!(Col::Float(71.2833 / 2.0), p.value(10).unwrap());
+        assert_eq!(Col::Null, p.value(11).unwrap());
+
+        let p = passenger("Cumings, Mrs. John Bradley", "PC 17599", None);
+        assert_eq!(Col::Null, p.value(8).unwrap());
+    }
+
+    #[test]
+    fn test_ticket_prefix() {
+        assert_eq!("STONO2", passenger("", "STON/O2. 3101282", None).ticket_prefix());
+        assert_eq!("STONO2", passenger("", "STON/O 2. 3101290", None).ticket_prefix());
+        assert_eq!("A5", passenger("", "A/5 21171", None).ticket_prefix());
+        assert_eq!("None", passenger("", "113803", None).ticket_prefix());
+        assert_eq!("PC", passenger("", "  PC 17599 ", None).ticket_prefix());
+    }
+
+    #[test]
+    fn test_tickets() {
+        let mut train = vec![passenger("", "PC 17599", None), passenger("", "PC 17599 ", None), passenger("", "113803", None)];
+        for (id, row) in train.iter_mut().enumerate() {
+            row.id = id as i64 + 1;
+        }
+        let tickets = Tickets::fit(&train);
+        tickets.apply(&mut train);
+        assert_eq!(Some(2), train[0].ticket_size);
+        assert_eq!(Some(1), train[2].ticket_size);
+
+        // A new passenger counts themselves along with the training ones.
+        let mut test = vec![passenger("", "PC 17599", None), passenger("", "A/5 21171", None)];
+        test[0].id = 4;
+        tickets.apply(&mut test);
+        assert_eq!(Col::Float(71.2833 / 3.0), test[0].value(10).unwrap());
+        assert_eq!(Col::Float(71.2833), test[1].value(10).unwrap());
+
+        // Without the tickets there is no fare per person.
+        let mut streamed = passenger("", "PC 17599", None);
+        streamed.ticket_size = None;
+        assert_eq!(Col::Null, streamed.value(10).unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut p = passenger("Kelly, Mr. James", "330911", None);
+        p.survived = None;
+        match p.label() {
+            Err(DecisionsError::MissingLabel { id }) => assert_eq!(1, id),
+            other => panic!("Expected a missing label, got {:?}", other)
+        }
+
+        assert!(matches!(p.value(13), Err(DecisionsError::Schema(_))));
+        assert!(matches!(p.name(13), Err(DecisionsError::Schema(_))));
+    }
+
+    #[test]
+    fn test_add_labels() {
+        let mut rows = vec![passenger("Kelly, Mr. James", "330911", None)];
+        rows[0].survived = None;
+        let mut check = HashMap::new();
+        check.insert(1, "Died".to_string());
+        add_labels(&mut rows, &check).unwrap();
+        assert_eq!("Died", rows[0].label().unwrap());
+
+        check.clear();
+        assert!(matches!(add_labels(&mut rows, &check), Err(DecisionsError::MissingLabel { id: 1 })));
+    }
+}
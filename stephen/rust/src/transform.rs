@@ -0,0 +1,790 @@
+//!
+//! A pipeline of feature transforms, configured in a TOML file :
+//!
+//! ```toml
+//! [[step]]
+//! kind = "impute"
+//! column = "Age"
+//! strategy = "median"
+//!
+//! [[step]]
+//! kind = "one_hot"
+//! column = "Embarked"
+//! ```
+//!
+//! Each step is fitted on the training set (so the median age, the categories
+//! to one hot encode and so on are learnt from it) and the fitted pipeline is
+//! saved along with the tree, so exactly the same transform is applied to the
+//! rows we later predict.
+//!
+
+use error::{open, DecisionsError, Result};
+use std::io::Read;
+use regex::Regex;
+use row::{no_column, Col, DataRow};
+use std::sync::Arc;
+use toml;
+
+/// How to fill in missing values.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    Mean,
+    Median,
+    Mode,
+    Constant,
+}
+
+/// A literal value given in the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Literal {
+    fn to_col(&self) -> Col {
+        match self {
+            Literal::Int(num) => Col::Int(*num),
+            Literal::Float(num) => Col::Float(*num),
+            Literal::Text(text) => Col::Text(text.clone()),
+        }
+    }
+}
+
+/// Either side of a derived column, a number or the name of a column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Operand {
+    Number(f64),
+    Column(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Op {
+    #[serde(rename = "+")]
+    Add,
+    #[serde(rename = "-")]
+    Subtract,
+    #[serde(rename = "*")]
+    Multiply,
+    #[serde(rename = "/")]
+    Divide,
+}
+
+/// A step of the pipeline as it is written in the config, before it has been fitted.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Step {
+    /// Fill in missing values. `value` is needed for the constant strategy.
+    Impute {
+        column: String,
+        strategy: Strategy,
+        value: Option<Literal>,
+    },
+    /// Replace a number with the index of the bin it falls in. Either give the
+    /// `edges` between the bins, or the number of `bins` to split the training
+    /// data into, with roughly the same number of rows in each.
+    Bin {
+        column: String,
+        bins: Option<usize>,
+        edges: Option<Vec<f64>>,
+    },
+    /// Replace a text column with a 0/1 column for each category, named `column=category`.
+    OneHot { column: String },
+    /// Replace a text column with the index of its category.
+    Ordinal { column: String },
+    /// Replace a number `x` with `ln(1 + x)`.
+    Log { column: String },
+    /// Scale a number to have a mean of zero and a standard deviation of one.
+    Standardize { column: String },
+    /// Add a text column `name` holding the first capture group of `pattern` in `column`.
+    Extract {
+        column: String,
+        pattern: String,
+        name: String,
+    },
+    /// Add a column `name` holding `left op right`.
+    Derive {
+        name: String,
+        left: Operand,
+        op: Op,
+        right: Operand,
+    },
+}
+
+/// A step with everything it learnt from the training data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Fitted {
+    Impute { column: String, value: Col },
+    Bin { column: String, edges: Vec<f64> },
+    OneHot { column: String, categories: Vec<String> },
+    Ordinal { column: String, categories: Vec<String> },
+    Log { column: String },
+    Standardize { column: String, mean: f64, std: f64 },
+    Extract { column: String, pattern: String, name: String },
+    Derive { name: String, left: Operand, op: Op, right: Operand },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Config {
+    #[serde(rename = "step", default)]
+    pub steps: Vec<Step>,
+}
+
+pub fn load_config(file: &str) -> Result<Config> {
+    let mut config = String::new();
+    open(file)?.read_to_string(&mut config)?;
+    Ok(toml::from_str(&config)?)
+}
+
+///
+/// A row after it has been through the pipeline.
+/// The id and label still come from the original row.
+///
+#[derive(Debug, Clone)]
+pub struct Transformed<'a, T: 'a> {
+    row: &'a T,
+    names: Arc<Vec<String>>,
+    values: Vec<Col>,
+}
+
+impl<'a, T> DataRow for Transformed<'a, T>
+where
+    T: DataRow,
+{
+    fn id(&self) -> i64 {
+        self.row.id()
+    }
+
+    fn name(&self, col: usize) -> Result<String> {
+        self.names.get(col).cloned().ok_or_else(|| no_column(self, col))
+    }
+
+    fn value(&self, col: usize) -> Result<Col> {
+        self.values.get(col).cloned().ok_or_else(|| no_column(self, col))
+    }
+
+    fn label(&self) -> Result<String> {
+        self.row.label()
+    }
+
+    fn labels(&self) -> Result<Vec<String>> {
+        self.row.labels()
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn weight(&self) -> f64 {
+        self.row.weight()
+    }
+}
+
+/// The data as a table that the steps work on a column at a time.
+struct Table {
+    names: Vec<String>,
+    rows: Vec<Vec<Col>>,
+}
+
+impl Table {
+    fn column(&self, name: &str) -> Result<usize> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .ok_or_else(|| DecisionsError::Schema(format!("No column called {}", name)))
+    }
+
+    fn values(&self, col: usize) -> impl Iterator<Item = &Col> {
+        self.rows.iter().map(move |row| &row[col])
+    }
+
+    /// The numbers in the column to fit a step on, leaving out NaN which a csv can hold as "NaN".
+    fn numbers(&self, col: usize) -> Vec<f64> {
+        self.values(col)
+            .filter_map(Col::number)
+            .filter(|number| !number.is_nan())
+            .collect()
+    }
+
+    fn push_column(&mut self, name: &str, values: Vec<Col>) {
+        self.names.push(name.to_string());
+        for (row, value) in self.rows.iter_mut().zip(values) {
+            row.push(value);
+        }
+    }
+
+    fn map_column<F>(&mut self, col: usize, f: F)
+    where
+        F: Fn(&Col) -> Col,
+    {
+        for row in &mut self.rows {
+            row[col] = f(&row[col]);
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// The distinct text values of a column, sorted.
+fn categories(table: &Table, col: usize) -> Vec<String> {
+    let mut categories: Vec<String> = table
+        .values(col)
+        .filter_map(|value| match value {
+            Col::Text(text) => Some(text.clone()),
+            _ => None,
+        })
+        .collect();
+    categories.sort();
+    categories.dedup();
+    categories
+}
+
+impl Step {
+    fn fit(&self, table: &Table) -> Result<Fitted> {
+        Ok(match self {
+            Step::Impute {
+                column,
+                strategy,
+                value,
+            } => {
+                let col = table.column(column)?;
+                let numbers = table.numbers(col);
+                let ints = table.values(col).all(|v| !matches!(v, Col::Float(_)));
+                let numeric = |num: f64| {
+                    if ints {
+                        Col::Int(num.round() as i64)
+                    } else {
+                        Col::Float(num)
+                    }
+                };
+
+                let value = match strategy {
+                    Strategy::Constant => value
+                        .as_ref()
+                        .ok_or_else(|| {
+                            DecisionsError::Config(format!("Imputing {} with a constant needs a value", column))
+                        })?
+                        .to_col(),
+                    Strategy::Mode => {
+                        let mut counts: Vec<(Col, usize)> = Vec::new();
+                        for value in table.values(col).filter(|v| **v != Col::Null) {
+                            match counts.iter_mut().find(|(v, _)| v == value) {
+                                Some(entry) => entry.1 += 1,
+                                None => counts.push((value.clone(), 1)),
+                            }
+                        }
+                        counts
+                            .into_iter()
+                            .fold((Col::Null, 0), |best, count| if count.1 > best.1 { count } else { best })
+                            .0
+                    }
+                    Strategy::Mean | Strategy::Median if numbers.is_empty() => {
+                        return Err(DecisionsError::Config(format!("Can't take the average of {}", column)))
+                    }
+                    Strategy::Mean => numeric(mean(&numbers)),
+                    Strategy::Median => numeric(median(&numbers)),
+                };
+
+                Fitted::Impute {
+                    column: column.clone(),
+                    value,
+                }
+            }
+            Step::Bin {
+                column,
+                bins,
+                edges,
+            } => {
+                let col = table.column(column)?;
+                let edges = match (edges, bins) {
+                    (Some(edges), _) => edges.clone(),
+                    (None, Some(bins)) => {
+                        let mut numbers = table.numbers(col);
+                        numbers.sort_by(f64::total_cmp);
+                        let mut edges: Vec<f64> = (1..*bins)
+                            .filter_map(|bin| numbers.get(bin * numbers.len() / bins).cloned())
+                            .collect();
+                        edges.dedup();
+                        edges
+                    }
+                    (None, None) => {
+                        return Err(DecisionsError::Config(format!(
+                            "Binning {} needs either bins or edges",
+                            column
+                        )))
+                    }
+                };
+
+                Fitted::Bin {
+                    column: column.clone(),
+                    edges,
+                }
+            }
+            Step::OneHot { column } => Fitted::OneHot {
+                column: column.clone(),
+                categories: categories(table, table.column(column)?),
+            },
+            Step::Ordinal { column } => Fitted::Ordinal {
+                column: column.clone(),
+                categories: categories(table, table.column(column)?),
+            },
+            Step::Log { column } => {
+                table.column(column)?;
+                Fitted::Log {
+                    column: column.clone(),
+                }
+            }
+            Step::Standardize { column } => {
+                let numbers = table.numbers(table.column(column)?);
+                if numbers.is_empty() {
+                    return Err(DecisionsError::Config(format!("Can't standardize {}", column)));
+                }
+                let mean = mean(&numbers);
+                let variance = numbers.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / numbers.len() as f64;
+
+                Fitted::Standardize {
+                    column: column.clone(),
+                    mean,
+                    std: if variance > 0.0 { variance.sqrt() } else { 1.0 },
+                }
+            }
+            Step::Extract {
+                column,
+                pattern,
+                name,
+            } => {
+                table.column(column)?;
+                Regex::new(pattern)?;
+                Fitted::Extract {
+                    column: column.clone(),
+                    pattern: pattern.clone(),
+                    name: name.clone(),
+                }
+            }
+            Step::Derive {
+                name,
+                left,
+                op,
+                right,
+            } => {
+                for operand in &[left, right] {
+                    if let Operand::Column(column) = operand {
+                        table.column(column)?;
+                    }
+                }
+                Fitted::Derive {
+                    name: name.clone(),
+                    left: left.clone(),
+                    op: *op,
+                    right: right.clone(),
+                }
+            }
+        })
+    }
+}
+
+impl Fitted {
+    fn apply(&self, table: &mut Table) -> Result<()> {
+        match self {
+            Fitted::Impute { column, value } => {
+                let col = table.column(column)?;
+                table.map_column(col, |v| match v {
+                    Col::Null => value.clone(),
+                    v => v.clone(),
+                });
+            }
+            Fitted::Bin { column, edges } => {
+                let col = table.column(column)?;
+                table.map_column(col, |v| match v.number() {
+                    Some(num) => Col::Int(edges.iter().filter(|&&edge| num >= edge).count() as i64),
+                    None => Col::Null,
+                });
+            }
+            Fitted::OneHot { column, categories } => {
+                let col = table.column(column)?;
+                for category in categories {
+                    let values = table
+                        .values(col)
+                        .map(|v| match v {
+                            Col::Text(text) if text == category => Col::Int(1),
+                            _ => Col::Int(0),
+                        })
+                        .collect();
+                    table.push_column(&format!("{}={}", column, category), values);
+                }
+                table.names.remove(col);
+                for row in &mut table.rows {
+                    row.remove(col);
+                }
+            }
+            Fitted::Ordinal { column, categories } => {
+                let col = table.column(column)?;
+                table.map_column(col, |v| match v {
+                    Col::Text(text) => categories
+                        .binary_search(text)
+                        .map(|idx| Col::Int(idx as i64))
+                        .unwrap_or(Col::Null),
+                    _ => Col::Null,
+                });
+            }
+            Fitted::Log { column } => {
+                let col = table.column(column)?;
+                table.map_column(col, |v| match v.number() {
+                    Some(num) if num > -1.0 => Col::Float(num.ln_1p()),
+                    _ => Col::Null,
+                });
+            }
+            Fitted::Standardize { column, mean, std } => {
+                let col = table.column(column)?;
+                table.map_column(col, |v| match v.number() {
+                    Some(num) => Col::Float((num - mean) / std),
+                    None => Col::Null,
+                });
+            }
+            Fitted::Extract {
+                column,
+                pattern,
+                name,
+            } => {
+                let col = table.column(column)?;
+                let regex = Regex::new(pattern)?;
+                let values = table
+                    .values(col)
+                    .map(|v| match v {
+                        Col::Text(text) => regex
+                            .captures(text)
+                            .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                            .map(|m| Col::Text(m.as_str().to_string()))
+                            .unwrap_or(Col::Null),
+                        _ => Col::Null,
+                    })
+                    .collect();
+                table.push_column(name, values);
+            }
+            Fitted::Derive {
+                name,
+                left,
+                op,
+                right,
+            } => {
+                let operand = |operand: &Operand| -> Result<Vec<Option<f64>>> {
+                    Ok(match operand {
+                        Operand::Number(num) => vec![Some(*num); table.rows.len()],
+                        Operand::Column(column) => {
+                            table.values(table.column(column)?).map(Col::number).collect()
+                        }
+                    })
+                };
+                let values = operand(left)?
+                    .into_iter()
+                    .zip(operand(right)?)
+                    .map(|pair| match pair {
+                        (Some(l), Some(r)) => {
+                            let result = match op {
+                                Op::Add => l + r,
+                                Op::Subtract => l - r,
+                                Op::Multiply => l * r,
+                                Op::Divide => l / r,
+                            };
+                            if result.is_finite() {
+                                Col::Float(result)
+                            } else {
+                                Col::Null
+                            }
+                        }
+                        _ => Col::Null,
+                    })
+                    .collect();
+                table.push_column(name, values);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A pipeline that has been fitted to the training data.
+/// The default pipeline has no steps so leaves the rows as they are.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    steps: Vec<Fitted>,
+}
+
+fn table<T>(rows: &[&T]) -> Result<Table>
+where
+    T: DataRow,
+{
+    let names = match rows.first() {
+        Some(row) => (0..row.len()).map(|col| row.name(col)).collect::<Result<Vec<String>>>()?,
+        None => Vec::new(),
+    };
+    Ok(Table {
+        rows: rows
+            .iter()
+            .map(|row| (0..names.len()).map(|col| row.value(col)).collect())
+            .collect::<Result<Vec<Vec<Col>>>>()?,
+        names,
+    })
+}
+
+fn rows<'a, T>(rows: &[&'a T], table: Table) -> Vec<Transformed<'a, T>> {
+    let names = Arc::new(table.names);
+    rows.iter()
+        .zip(table.rows)
+        .map(|(row, values)| Transformed {
+            row: *row,
+            names: names.clone(),
+            values,
+        })
+        .collect()
+}
+
+impl Pipeline {
+    ///
+    /// Fit each step of the config in turn, each one seeing the
+    /// training data as transformed by the steps before it.
+    ///
+    pub fn fit<T>(config: &Config, train: &[&T]) -> Result<Self>
+    where
+        T: DataRow,
+    {
+        let mut table = table(train)?;
+        let mut steps = Vec::new();
+        for step in &config.steps {
+            let fitted = step.fit(&table)?;
+            fitted.apply(&mut table)?;
+            steps.push(fitted);
+        }
+
+        Ok(Pipeline { steps })
+    }
+
+    /// The fitted steps, in the order they are applied.
+    pub fn steps(&self) -> &[Fitted] {
+        &self.steps
+    }
+
+    /// Transform the rows with the fitted steps.
+    pub fn apply<'a, T>(&self, data: &[&'a T]) -> Result<Vec<Transformed<'a, T>>>
+    where
+        T: DataRow,
+    {
+        let mut table = table(data)?;
+        for step in &self.steps {
+            step.apply(&mut table)?;
+        }
+
+        Ok(rows(data, table))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use row::test::{training_data, Fruit};
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn column<T: DataRow>(rows: &[T], name: &str) -> Vec<Col> {
+        let col = (0..rows[0].len()).find(|&c| rows[0].name(c).unwrap() == name).unwrap();
+        rows.iter().map(|row| row.value(col).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_one_hot_and_ordinal() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let config = config(
+            r#"
+            [[step]]
+            kind = "one_hot"
+            column = "Colour"
+
+            [[step]]
+            kind = "ordinal"
+            column = "Fruit"
+            "#,
+        );
+        let rows = Pipeline::fit(&config, &data).unwrap().apply(&data).unwrap();
+
+        let names: Vec<String> = (0..rows[0].len()).map(|c| rows[0].name(c).unwrap()).collect();
+        assert_eq!(vec!["Things", "Fruit", "Colour=Green", "Colour=Red", "Colour=Yellow"], names);
+        assert_eq!(
+            vec![Col::Int(0), Col::Int(0), Col::Int(1), Col::Int(1), Col::Int(0)],
+            column(&rows, "Colour=Red")
+        );
+        assert_eq!(
+            vec![Col::Int(0), Col::Int(0), Col::Int(1), Col::Int(1), Col::Int(2)],
+            column(&rows, "Fruit")
+        );
+        assert_eq!("Apple", rows[0].label().unwrap());
+    }
+
+    #[test]
+    fn test_fitted_on_train_applied_to_test() {
+        let train = training_data();
+        let train: Vec<&Fruit> = train.iter().collect();
+        let config = config(
+            r#"
+            [[step]]
+            kind = "standardize"
+            column = "Things"
+
+            [[step]]
+            kind = "one_hot"
+            column = "Colour"
+            "#,
+        );
+        let pipeline = Pipeline::fit(&config, &train).unwrap();
+
+        // The mean and categories come from the training data, not the test data.
+        let test = [
+            Fruit::new(6, "Blue".to_string(), 2, "Plum".to_string()),
+            Fruit::new(7, "Red".to_string(), 2, "Plum".to_string()),
+        ];
+        let test: Vec<&Fruit> = test.iter().collect();
+        let rows = pipeline.apply(&test).unwrap();
+
+        let things = column(&rows, "Things");
+        match things[0] {
+            Col::Float(num) => assert!((num - (2.0 - 2.2) / 0.9797958971132712).abs() < 1e-9),
+            _ => panic!("Expected a float"),
+        }
+        assert_eq!(vec![Col::Int(0), Col::Int(0)], column(&rows, "Colour=Green"));
+        assert_eq!(vec![Col::Int(0), Col::Int(1)], column(&rows, "Colour=Red"));
+
+        // And round trips through json unchanged.
+        let json = serde_json::to_string(&pipeline).unwrap();
+        assert_eq!(pipeline, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn test_impute_bin_log_extract_derive() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let config = config(
+            r#"
+            [[step]]
+            kind = "extract"
+            column = "Colour"
+            pattern = "^(.)"
+            name = "Initial"
+
+            [[step]]
+            kind = "derive"
+            name = "Double"
+            left = "Things"
+            op = "*"
+            right = 2
+
+            [[step]]
+            kind = "bin"
+            column = "Things"
+            edges = [2.0]
+
+            [[step]]
+            kind = "log"
+            column = "Double"
+
+            [[step]]
+            kind = "impute"
+            column = "Colour"
+            strategy = "mode"
+            "#,
+        );
+        let rows = Pipeline::fit(&config, &data).unwrap().apply(&data).unwrap();
+
+        assert_eq!(Col::Text("G".to_string()), column(&rows, "Initial")[0]);
+        assert_eq!(
+            vec![Col::Int(1), Col::Int(1), Col::Int(0), Col::Int(0), Col::Int(1)],
+            column(&rows, "Things")
+        );
+        assert_eq!(Col::Float(7.0_f64.ln()), column(&rows, "Double")[0]);
+    }
+
+    #[test]
+    fn test_impute() {
+        let mut table = Table {
+            names: vec!["Age".to_string()],
+            rows: vec![vec![Col::Int(1)], vec![Col::Null], vec![Col::Int(4)], vec![Col::Int(4)]],
+        };
+
+        let step = |strategy| Step::Impute {
+            column: "Age".to_string(),
+            strategy,
+            value: Some(Literal::Int(9)),
+        };
+        let value = |strategy| match step(strategy).fit(&table).unwrap() {
+            Fitted::Impute { value, .. } => value,
+            _ => panic!("Expected impute"),
+        };
+
+        assert_eq!(Col::Int(3), value(Strategy::Mean));
+        assert_eq!(Col::Int(4), value(Strategy::Median));
+        assert_eq!(Col::Int(4), value(Strategy::Mode));
+        assert_eq!(Col::Int(9), value(Strategy::Constant));
+
+        step(Strategy::Median).fit(&table).unwrap().apply(&mut table).unwrap();
+        assert_eq!(Col::Int(4), table.rows[1][0]);
+    }
+
+    #[test]
+    fn test_nan() {
+        let table = Table {
+            names: vec!["Fare".to_string()],
+            rows: vec![vec![Col::Float(1.0)], vec![Col::Float(f64::NAN)], vec![Col::Float(3.0)], vec![Col::Float(8.0)]],
+        };
+
+        let median = Step::Impute {
+            column: "Fare".to_string(),
+            strategy: Strategy::Median,
+            value: None,
+        };
+        assert_eq!(
+            Fitted::Impute {
+                column: "Fare".to_string(),
+                value: Col::Float(3.0)
+            },
+            median.fit(&table).unwrap()
+        );
+
+        let bin = Step::Bin {
+            column: "Fare".to_string(),
+            bins: Some(2),
+            edges: None,
+        };
+        assert_eq!(
+            Fitted::Bin {
+                column: "Fare".to_string(),
+                edges: vec![3.0]
+            },
+            bin.fit(&table).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unknown_column() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let config = config("[[step]]\nkind = \"log\"\ncolumn = \"Weight\"\n");
+        assert!(Pipeline::fit(&config, &data).is_err());
+    }
+}
@@ -1,12 +1,154 @@
-use itertools::Itertools;
-use question::Question;
-use row::{Col, DataRow};
-use std::collections::HashMap;
+use cost::CostMatrix;
+use dot::{self, DotNode};
+use error::{DecisionsError, Result};
+use model::{Estimator, Model};
+use monotonic::{Bounds, Monotonic};
+use question::{Predicate, Question};
+use random::Rng;
+use row::{Col, DataRow, Weighted};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug)]
+/// How we measure the impurity of a set of rows when choosing a split.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Criterion {
+    Gini,
+    Entropy,
+}
+
+///
+/// How much the rows of each class count for when growing a tree, on top
+/// of the rows' own weights.
+///
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum ClassWeight {
+    /// Every class counts the same.
+    #[default]
+    Uniform,
+    /// Weight each class inversely to its total weight in the training
+    /// rows, so that all the classes count for the same.
+    Balanced,
+    /// The weight for each class. Classes that aren't given have a weight of 1.
+    Given(BTreeMap<String, f64>),
+}
+
+impl ClassWeight {
+    /// The weight for each of the labels in the rows.
+    fn weights<T>(&self, rows: &[&T]) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        let (total, counts) = class_counts_iter(rows.iter())?;
+        let classes = counts.len() as f64;
+        Ok(counts
+            .into_iter()
+            .map(|(label, count)| {
+                let weight = match self {
+                    ClassWeight::Uniform => 1.0,
+                    ClassWeight::Balanced => total / (classes * count),
+                    ClassWeight::Given(weights) => *weights.get(&label).unwrap_or(&1.0),
+                };
+                (label, weight)
+            })
+            .collect())
+    }
+}
+
+///
+/// Parses `uniform`, `balanced` or a weight for each class such as `Lived=2,Died=1`.
+///
+impl FromStr for ClassWeight {
+    type Err = DecisionsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uniform" => Ok(ClassWeight::Uniform),
+            "balanced" => Ok(ClassWeight::Balanced),
+            _ => s
+                .split(',')
+                .map(|pair| {
+                    let weight = pair.find('=').and_then(|idx| {
+                        let weight = pair[idx + 1..].trim().parse::<f64>().ok()?;
+                        Some((pair[..idx].trim().to_string(), weight))
+                    });
+                    match weight {
+                        Some((class, weight)) if weight.is_finite() && weight >= 0.0 => {
+                            Ok((class, weight))
+                        }
+                        _ => Err(DecisionsError::Config(format!(
+                            "Bad class weight {}, expected uniform, balanced or class=weight,...",
+                            pair
+                        ))),
+                    }
+                })
+                .collect::<Result<BTreeMap<String, f64>>>()
+                .map(ClassWeight::Given),
+        }
+    }
+}
+
+impl fmt::Display for ClassWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClassWeight::Uniform => write!(f, "uniform"),
+            ClassWeight::Balanced => write!(f, "balanced"),
+            ClassWeight::Given(weights) => {
+                let pairs: Vec<String> = weights
+                    .iter()
+                    .map(|(class, weight)| format!("{}={}", class, weight))
+                    .collect();
+                write!(f, "{}", pairs.join(","))
+            }
+        }
+    }
+}
+
+///
+/// The settings that control how a tree is grown.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeParams {
+    /// The maximum depth of the tree. None if we want it as deep as we can.
+    pub max_depth: Option<u64>,
+    /// Nodes with fewer rows than this become leaves.
+    pub min_samples_split: usize,
+    /// Splits that would leave fewer rows than this on either side aren't considered.
+    pub min_samples_leaf: usize,
+    pub criterion: Criterion,
+    #[serde(default)]
+    pub class_weight: ClassWeight,
+    /// The cost of each mistake, to make the splits care more about the
+    /// expensive ones. Wrap the tree in a `MinimumCost` to choose the label
+    /// at each leaf by cost as well.
+    #[serde(default)]
+    pub costs: Option<CostMatrix>,
+    /// The columns the probability of a label can only go up or only go down with.
+    #[serde(default)]
+    pub monotonic: Option<Monotonic>,
+}
+
+impl Default for TreeParams {
+    fn default() -> Self {
+        TreeParams {
+            max_depth: None,
+            min_samples_split: 2,
+            min_samples_leaf: 1,
+            criterion: Criterion::Gini,
+            class_weight: ClassWeight::Uniform,
+            costs: None,
+            monotonic: None,
+        }
+    }
+}
+
+/// A node in our decision tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Node {
+    /// The total weight of the training rows with each label that ended up in the leaf.
     Leaf {
-        predictions: HashMap<String, usize>,
+        predictions: HashMap<String, f64>,
     },
     Decision {
         question: Question,
@@ -16,16 +158,21 @@ pub enum Node {
 }
 
 impl Node {
-    fn new_leaf<T>(rows: &[&T]) -> Self
+    /// A leaf for the rows, kept within the bounds if the tree is constrained.
+    fn new_leaf<T>(rows: &[&T], limits: Option<&Limits>) -> Result<Self>
     where
         T: DataRow,
     {
-        Node::Leaf {
-            predictions: class_counts(rows),
-        }
+        let predictions = class_counts(rows)?;
+        Ok(Node::Leaf {
+            predictions: match limits {
+                Some(limits) => limits.bounds.clamp_counts(limits.monotonic, &limits.other, predictions),
+                None => predictions,
+            },
+        })
     }
 
-    fn new_decision(question: Question, true_branch: Node, false_branch: Node) -> Self {
+    pub fn new_decision(question: Question, true_branch: Node, false_branch: Node) -> Self {
         Node::Decision {
             question,
             true_branch: Box::new(true_branch),
@@ -33,216 +180,370 @@ impl Node {
         }
     }
 
-    /// Create a dot format for our tree.
-    pub fn to_dot(&self) -> String {
-        let mut id = 0;
-        let mut next_id = || {
-            id = id + 1;
-            id
-        };
-
+    /// Every node in the tree along with how deep it is, the root being at 1.
+    fn walk(&self) -> Vec<(usize, &Node)> {
         let mut nodes = Vec::new();
-        let mut edges = Vec::new();
+        let mut stack = vec![(1, self)];
+        while let Some((depth, node)) = stack.pop() {
+            if let Node::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } = node
+            {
+                stack.push((depth + 1, &**false_branch));
+                stack.push((depth + 1, &**true_branch));
+            }
+            nodes.push((depth, node));
+        }
+        nodes
+    }
 
-        // We won't use recursion which risks blowing up the stack.
-        let mut traversal = vec![(next_id(), self)];
+    /// The number of levels in the tree, so a tree that is just a leaf has a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.walk().iter().map(|(depth, _)| *depth).max().unwrap_or(0)
+    }
 
-        while traversal.len() > 0 {
-            let next = traversal
-                .iter()
-                .flat_map(|(id, node)| match node {
-                    Node::Leaf { .. } => Vec::new(),
-                    Node::Decision {
-                        true_branch,
-                        false_branch,
-                        ..
-                    } => {
-                        let t_id = next_id();
-                        let f_id = next_id();
-
-                        edges.push(format!("\t{}->{}[fontsize=32,label=\"yes\"];", id, t_id));
-                        edges.push(format!("\t{}->{}[fontsize=32,label=\"no\"];", id, f_id));
-
-                        vec![(t_id, &**true_branch), (f_id, &**false_branch)]
-                    }
-                })
-                .collect();
+    /// The number of nodes in the tree, both decisions and leaves.
+    pub fn node_count(&self) -> usize {
+        self.walk().len()
+    }
 
-            let mut labels = traversal
-                .iter()
-                .filter_map(|(id, node)| match node {
-                    Node::Leaf { predictions } => Some(format!(
-                        "\t{}[shape=circle,label=\"{}\"];",
-                        id,
-                        format!("{:?}", predictions).replace("\"", "")
-                    )),
-                    Node::Decision { question, .. } => {
-                        Some(format!("\t{}[shape=box,label=\"{}\"];", id, question))
-                    }
-                })
-                .collect();
+    /// The number of leaves in the tree.
+    pub fn leaf_count(&self) -> usize {
+        self.walk()
+            .iter()
+            .filter(|(_, node)| matches!(node, Node::Leaf { .. }))
+            .count()
+    }
 
-            nodes.append(&mut labels);
+    /// Create a dot format for our tree.
+    pub fn to_dot(&self) -> String {
+        dot::to_dot(self)
+    }
+}
 
-            traversal = next;
+impl DotNode for Node {
+    fn label(&self) -> String {
+        match self {
+            Node::Leaf { predictions } => format!("{:?}", predictions).replace("\"", ""),
+            Node::Decision { question, .. } => question.to_string(),
         }
+    }
 
-        format!(
-            "digraph Tree {{\n{}\n{}\n}}",
-            nodes.join("\n"),
-            edges.join("\n")
-        )
+    fn children(&self) -> Vec<(String, &Node)> {
+        match self {
+            Node::Leaf { .. } => Vec::new(),
+            Node::Decision {
+                true_branch,
+                false_branch,
+                ..
+            } => vec![("yes".to_string(), &**true_branch), ("no".to_string(), &**false_branch)],
+        }
     }
 }
 
 ///
-/// Returns a count of each label in the dataset.
-/// Groups by the label and then sums the occurrences for each label.
+/// Returns a count of each label in the dataset, weighted by the rows' weights.
 ///
-fn class_counts<T>(rows: &[&T]) -> HashMap<String, usize>
+fn class_counts<T>(rows: &[&T]) -> Result<HashMap<String, f64>>
 where
     T: DataRow,
 {
-    rows.iter()
-        .group_by(|row| row.label().to_owned())
-        .into_iter()
-        .map(|(label, count)| (label, count.count()))
-        .collect()
+    let (_, counts) = class_counts_iter(rows.iter())?;
+    Ok(counts.into_iter().collect())
 }
 
 ///
 /// Returns a count of each label in the dataset and a total count of the iterator.
-/// Groups by the label and then sums the occurrences for each label.
+/// Groups by the label and then sums the weights of the rows for each label.
 ///
-/// Gets everything we need by just going through it once.
+/// Gets everything we need by just going through it once. The labels are
+/// kept in order so that the sums are always added up the same way.
 ///
-fn class_counts_iter<'a, T, I>(rows: I) -> (usize, HashMap<String, usize>)
+pub fn class_counts_iter<'a, T, I>(rows: I) -> Result<(f64, BTreeMap<String, f64>)>
 where
     T: DataRow,
     I: Iterator<Item = &'a &'a T>,
     T: 'a,
 {
-    rows.fold((0, HashMap::new()), |(len, mut group), row| {
-        let count = match group.get(&row.label()) {
-            Some(count) => *count,
-            None => 0,
-        };
+    let mut len = 0.0;
+    let mut group = BTreeMap::new();
+    for row in rows {
+        let weight = row.weight();
+        *group.entry(row.label()?).or_insert(0.0) += weight;
+        len += weight;
+    }
+
+    Ok((len, group))
+}
 
-        group.insert(row.label(), count + 1);
+///
+/// Calculate the gini score from the count of each label.
+///
+pub fn gini(len: f64, counts: &BTreeMap<String, f64>) -> f64 {
+    counts.values().filter(|&&count| count > 0.0).fold(1.0, |impurity, &count| {
+        let probability = count / len;
+        impurity - probability.powf(2.0)
+    })
+}
 
-        (len + 1, group)
+///
+/// Calculate the entropy, in bits, from the count of each label.
+///
+pub fn entropy(len: f64, counts: &BTreeMap<String, f64>) -> f64 {
+    counts.values().filter(|&&count| count > 0.0).fold(0.0, |entropy, &count| {
+        let probability = count / len;
+        entropy - probability * probability.log2()
     })
 }
 
 ///
-/// Calculate the gini score for the dataset
-/// Returns the length of the iterator as well since we
+/// The count of each label, and the total, as the impurity sees them.
+///
+/// With a cost matrix each row counts for as much as it costs to get it
+/// wrong, so the splits try harder to separate out the expensive classes.
+///
+fn weighted_counts<'a, T, I>(rows: I, params: &TreeParams) -> Result<(f64, BTreeMap<String, f64>)>
+where
+    T: DataRow,
+    I: Iterator<Item = &'a &'a T>,
+    T: 'a,
+{
+    let (mut len, mut counts) = class_counts_iter(rows)?;
+    if let Some(ref costs) = params.costs {
+        for (label, count) in counts.iter_mut() {
+            *count *= costs.weight(label);
+        }
+        len = counts.values().sum();
+    }
+    Ok((len, counts))
+}
+
+///
+/// The impurity of the dataset measured with the criterion in the settings.
+/// Returns the total weight of the rows as well since we
 /// have calculated it whilst exhausting our iterator.
 ///
-fn gini<'a, T, I>(rows: I) -> (usize, f64)
+fn impurity<'a, T, I>(rows: I, params: &TreeParams) -> Result<(f64, f64)>
 where
     T: DataRow,
     I: Iterator<Item = &'a &'a T>,
     T: 'a,
 {
-    let (len, counts) = class_counts_iter(rows);
-
-    (
-        len,
-        counts.values().fold(1.0, |impurity, &count| {
-            let probability = count as f64 / len as f64;
-            impurity - probability.powf(2.0)
-        }),
-    )
+    let (len, counts) = weighted_counts(rows, params)?;
+    match params.criterion {
+        Criterion::Gini => Ok((len, gini(len, &counts))),
+        Criterion::Entropy => Ok((len, entropy(len, &counts))),
+    }
 }
 
 /// Returns a distinct list of the values in this column.
-fn column_values<'a, T, I>(rows: I, col: usize) -> Vec<Col>
+pub fn column_values<'a, T, I>(rows: I, col: usize) -> Result<Vec<Col>>
 where
     T: DataRow,
     I: Iterator<Item = &'a &'a T>,
     T: 'a,
 {
-    let mut column: Vec<Col> = rows.map(|row| row.value(col).clone()).collect();
+    let mut column = rows.map(|row| row.value(col)).collect::<Result<Vec<Col>>>()?;
     column.sort();
     column.dedup();
 
-    column
+    Ok(column)
 }
 
 /// Partitions the dataset into rows that are true and rows that are false
 /// according to the given question.
-fn partition<'a, T>(rows: &'a [&T], question: &Question) -> (Vec<&'a T>, Vec<&'a T>)
+pub fn partition<'a, T>(rows: &[&'a T], question: &Question) -> Result<(Vec<&'a T>, Vec<&'a T>)>
 where
     T: DataRow,
 {
-    rows.iter().partition(|&row| question.matchit(*row))
-}
+    let mut true_rows = Vec::new();
+    let mut false_rows = Vec::new();
+    for row in rows {
+        if question.matchit(*row)? {
+            true_rows.push(*row);
+        } else {
+            false_rows.push(*row);
+        }
+    }
 
-///
-/// Partitions the dataset into rows that are true and rows that are false
-/// according to the given question.
-///
-fn partition_iter<'a, T, I>(
-    rows: I,
-    question: &'a Question,
-) -> (
-    impl Iterator<Item = &'a &'a T>,
-    impl Iterator<Item = &'a &'a T>,
-)
-where
-    T: DataRow,
-    I: Iterator<Item = &'a &'a T> + Clone,
-    T: 'a,
-{
-    (
-        rows.clone().filter(move |&a| question.matchit(*a)),
-        rows.filter(move |&a| !question.matchit(*a)),
-    )
+    Ok((true_rows, false_rows))
 }
 
 /// The uncertainty of the starting node, minus the weighted impurity of
 /// two child nodes.
-fn info_gain<'a, T, I, J>(true_rows: I, false_rows: J, current: f64) -> f64
+fn info_gain<'a, T, I, J>(true_rows: I, false_rows: J, current: f64, params: &TreeParams) -> Result<f64>
 where
     T: DataRow,
     I: Iterator<Item = &'a &'a T>,
     J: Iterator<Item = &'a &'a T>,
     T: 'a,
 {
-    let (true_len, true_gini) = gini(true_rows);
-    let (false_len, false_gini) = gini(false_rows);
+    let (true_len, true_impurity) = impurity(true_rows, params)?;
+    let (false_len, false_impurity) = impurity(false_rows, params)?;
+
+    if true_len + false_len <= 0.0 {
+        return Ok(0.0);
+    }
+    let p = true_len / (true_len + false_len);
+    Ok(current - p * true_impurity - (1.0 - p) * false_impurity)
+}
+
+///
+/// The questions worth asking of a numeric column, whether each value is at
+/// least half way to the next one up, so a value that wasn't in the training
+/// rows goes with the nearer of its neighbours. Ints and floats are treated
+/// as the same, and missing values and NaN always go down the false branch.
+///
+pub fn thresholds(values: &[Col]) -> Vec<Predicate> {
+    let mut numbers: Vec<f64> = values
+        .iter()
+        .filter_map(Col::number)
+        .filter(|number| !number.is_nan())
+        .collect();
+    numbers.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    numbers.dedup();
+    numbers
+        .windows(2)
+        .filter_map(|pair| Col::Float(pair[0]).midpoint(&Col::Float(pair[1])))
+        .map(Predicate::AtLeast)
+        .collect()
+}
+
+/// Text columns with more than two classes and at most this many categories
+/// have every way of dividing the categories in two tried.
+const EXHAUSTIVE_CATEGORIES: usize = 8;
+
+///
+/// The sets of categories worth trying as the true branch of a split on a text column.
+///
+/// With two classes, ordering the categories by the proportion of the first
+/// class and taking each run from the start of that order is guaranteed to
+/// include the best split (Breiman et al., 1984), for both gini and entropy.
+/// With more classes every subset is tried when there are few categories,
+/// otherwise the runs from ordering by each class in turn.
+///
+pub fn category_subsets<T>(rows: &[&T], col: usize, params: &TreeParams) -> Result<Vec<Predicate>>
+where
+    T: DataRow,
+{
+    let mut groups: BTreeMap<String, Vec<&T>> = BTreeMap::new();
+    for row in rows {
+        if let Col::Text(category) = row.value(col)? {
+            groups.entry(category).or_default().push(*row);
+        }
+    }
+
+    let mut categories = Vec::new();
+    for (category, group) in &groups {
+        let (len, counts) = weighted_counts(group.iter(), params)?;
+        categories.push((category.clone(), len, counts));
+    }
+    let labels: BTreeSet<&String> = categories.iter().flat_map(|(_, _, counts)| counts.keys()).collect();
+
+    let mut subsets: BTreeSet<Vec<String>> = BTreeSet::new();
+    if labels.len() > 2 && categories.len() <= EXHAUSTIVE_CATEGORIES {
+        // Every subset with the first category in it, apart from all of them.
+        let rest = categories.len().saturating_sub(1);
+        for mask in 0..(1usize << rest) - 1 {
+            let subset = categories
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx == 0 || mask & (1 << (idx - 1)) != 0)
+                .map(|(_, (category, _, _))| category.clone())
+                .collect();
+            subsets.insert(subset);
+        }
+    } else {
+        let orderings = if labels.len() > 2 { labels.len() } else { 1 };
+        for label in labels.iter().take(orderings) {
+            let proportion = |len: f64, counts: &BTreeMap<String, f64>| {
+                if len > 0.0 {
+                    counts.get(*label).unwrap_or(&0.0) / len
+                } else {
+                    0.0
+                }
+            };
+            let mut ordered: Vec<&(String, f64, BTreeMap<String, f64>)> = categories.iter().collect();
+            ordered.sort_by(|(_, a_len, a), (_, b_len, b)| {
+                proportion(*a_len, a)
+                    .partial_cmp(&proportion(*b_len, b))
+                    .unwrap_or(Ordering::Equal)
+            });
+            for end in 1..ordered.len() {
+                let mut subset: Vec<String> = ordered[..end].iter().map(|(category, _, _)| category.clone()).collect();
+                subset.sort();
+                subsets.insert(subset);
+            }
+        }
+    }
+
+    // A single category is a split on its own, even if it is the only one.
+    if categories.len() == 1 {
+        subsets.insert(vec![categories[0].0.clone()]);
+    }
 
-    let p = true_len as f64 / (true_len + false_len) as f64;
-    current - p * true_gini - (1.0 - p) * false_gini
+    Ok(subsets
+        .into_iter()
+        .map(|mut subset| {
+            if subset.len() == 1 {
+                Predicate::Equal(Col::Text(subset.remove(0)))
+            } else {
+                Predicate::In(subset.into_iter().map(Col::Text).collect())
+            }
+        })
+        .collect())
+}
+
+/// Does the split keep to the monotonic constraints, if there are any?
+fn keeps_to_constraints<T>(question: &Question, true_rows: &[&T], false_rows: &[&T], params: &TreeParams) -> Result<bool>
+where
+    T: DataRow,
+{
+    match params.monotonic {
+        Some(ref monotonic) => monotonic.allows(question, true_rows, false_rows),
+        None => Ok(true),
+    }
 }
 
 /// Find the best way to split the data
-fn find_best_split<T>(rows: &[&T]) -> (f64, Option<Question>)
+fn find_best_split<T>(rows: &[&T], params: &TreeParams) -> Result<(f64, Option<Question>)>
 where
     T: DataRow,
 {
-    assert!(rows.len() > 0);
+    let first_row = match rows.first() {
+        Some(row) => *row,
+        None => return Ok((0.0, None)),
+    };
     let mut best_gain = 0.0;
     let mut best_question = None;
-    let (_, current_uncertainty) = gini(rows.iter());
-    let first_row = rows[0];
-    let nfeatures = rows[0].len();
+    let (_, current_uncertainty) = impurity(rows.iter(), params)?;
+    let nfeatures = first_row.len();
 
     for col in 0..nfeatures {
-        let values = column_values(rows.iter(), col);
-        for val in values {
-            let question = Question::new(first_row.name(col).clone(), col, val);
-            let (mut true_rows, mut false_rows) = partition_iter(rows.iter(), &question);
-            let mut true_rows = true_rows.peekable();
-            let mut false_rows = false_rows.peekable();
-
-            if true_rows.peek().is_none() || false_rows.peek().is_none() {
+        let values = column_values(rows.iter(), col)?;
+        let predicates = if values.iter().any(|val| matches!(val, Col::Text(_))) {
+            category_subsets(rows, col, params)?
+        } else {
+            thresholds(&values)
+        };
+
+        for predicate in predicates {
+            let question = Question::with_predicate(first_row.name(col)?, col, predicate);
+            let (true_rows, false_rows) = partition(rows, &question)?;
+
+            if true_rows.is_empty()
+                || false_rows.is_empty()
+                || true_rows.len() < params.min_samples_leaf
+                || false_rows.len() < params.min_samples_leaf
+                || !keeps_to_constraints(&question, &true_rows, &false_rows, params)?
+            {
                 continue;
             }
 
             // Calculate the information gain from this split.
-            let gain = info_gain(true_rows, false_rows, current_uncertainty);
+            let gain = info_gain(true_rows.iter(), false_rows.iter(), current_uncertainty, params)?;
             if gain > best_gain {
                 best_gain = gain;
                 best_question = Some(question.clone());
@@ -250,7 +551,89 @@ where
         }
     }
 
-    (best_gain, best_question)
+    Ok((best_gain, best_question))
+}
+
+///
+/// A random split of a column, or None if there is no way to split it.
+///
+/// Numeric columns get a threshold drawn uniformly from above their smallest
+/// value up to their largest, so both sides get at least one row. Text columns
+/// get a random non-empty subset of their categories, leaving at least one out
+/// unless there are missing values to go down the false branch.
+///
+pub fn random_predicate(values: &[Col], rng: &mut Rng) -> Option<Predicate> {
+    if values.iter().any(|val| matches!(val, Col::Text(_))) {
+        let mut categories: Vec<Col> = values.iter().filter(|val| matches!(val, Col::Text(_))).cloned().collect();
+        let size = match categories.len() {
+            1 if values.len() > 1 => 1,
+            1 => return None,
+            len => 1 + rng.below(len - 1),
+        };
+        rng.shuffle(&mut categories);
+        categories.truncate(size);
+        categories.sort();
+        if size == 1 {
+            Some(Predicate::Equal(categories.remove(0)))
+        } else {
+            Some(Predicate::In(categories))
+        }
+    } else {
+        let numbers = values.iter().filter_map(Col::number).filter(|number| !number.is_nan());
+        let (low, high) = numbers.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), number| {
+            (low.min(number), high.max(number))
+        });
+        if low < high {
+            Some(Predicate::AtLeast(Col::Float(high - rng.float() * (high - low))))
+        } else {
+            None
+        }
+    }
+}
+
+/// Find the best of one random split for each of up to `max_features` columns.
+fn find_random_split<T>(rows: &[&T], params: &TreeParams, max_features: usize, rng: &mut Rng) -> Result<(f64, Option<Question>)>
+where
+    T: DataRow,
+{
+    let first_row = match rows.first() {
+        Some(row) => *row,
+        None => return Ok((0.0, None)),
+    };
+    let mut best_gain = 0.0;
+    let mut best_question = None;
+    let (_, current_uncertainty) = impurity(rows.iter(), params)?;
+    let mut columns: Vec<usize> = (0..first_row.len()).collect();
+    rng.shuffle(&mut columns);
+
+    let mut tried = 0;
+    for col in columns {
+        if tried == max_features {
+            break;
+        }
+        let predicate = match random_predicate(&column_values(rows.iter(), col)?, rng) {
+            Some(predicate) => predicate,
+            None => continue,
+        };
+        tried += 1;
+
+        let question = Question::with_predicate(first_row.name(col)?, col, predicate);
+        let (true_rows, false_rows) = partition(rows, &question)?;
+        if true_rows.len() < params.min_samples_leaf.max(1)
+            || false_rows.len() < params.min_samples_leaf.max(1)
+            || !keeps_to_constraints(&question, &true_rows, &false_rows, params)?
+        {
+            continue;
+        }
+
+        let gain = info_gain(true_rows.iter(), false_rows.iter(), current_uncertainty, params)?;
+        if gain > best_gain {
+            best_gain = gain;
+            best_question = Some(question);
+        }
+    }
+
+    Ok((best_gain, best_question))
 }
 
 ///
@@ -261,73 +644,251 @@ where
 /// * `rows` - The rows of our dataset. Each row is an object that satisfies the DataRow trait.
 /// * `depth` - The maximum depth of our tree. None if we want it as deep as we can.
 ///
-pub fn build_tree<T>(rows: &[&T], depth: Option<u64>) -> Node
+pub fn build_tree<T>(rows: &[&T], depth: Option<u64>) -> Result<Node>
 where
     T: DataRow,
 {
-    let depth = depth.map(|d| d - 1);
+    build_tree_with(
+        rows,
+        &TreeParams {
+            max_depth: depth,
+            ..TreeParams::default()
+        },
+    )
+}
+
+///
+/// Build up our decision tree with the given settings.
+///
+/// Each row counts for its weight times the weight of its class. The
+/// minimum samples for a split or leaf are still numbers of rows.
+///
+pub fn build_tree_with<T>(rows: &[&T], params: &TreeParams) -> Result<Node>
+where
+    T: DataRow,
+{
+    let weighted = weigh(rows, params)?;
+    let weighted: Vec<&Weighted<T>> = weighted.iter().collect();
+    let limits = limits(&weighted, params)?;
+
+    grow(&weighted, params, limits.as_ref(), &mut |rows: &[&Weighted<T>], params: &TreeParams| {
+        find_best_split(rows, params)
+    })
+}
+
+///
+/// Build an extremely randomised tree (Geurts, Ernst and Wehenkel, 2006).
+///
+/// Rather than trying every split, each node draws one random split for each
+/// of up to `max_features` columns and takes the best of those. Columns that
+/// only have one value left aren't counted. The rest of the settings work
+/// as they do in `build_tree_with`.
+///
+pub fn build_extra_tree<T>(rows: &[&T], params: &TreeParams, max_features: usize, rng: &mut Rng) -> Result<Node>
+where
+    T: DataRow,
+{
+    let weighted = weigh(rows, params)?;
+    let weighted: Vec<&Weighted<T>> = weighted.iter().collect();
+    let limits = limits(&weighted, params)?;
+
+    grow(&weighted, params, limits.as_ref(), &mut |rows: &[&Weighted<T>], params: &TreeParams| {
+        find_random_split(rows, params, max_features, rng)
+    })
+}
+
+/// Each row with its own weight times the weight of its class.
+fn weigh<'a, T>(rows: &[&'a T], params: &TreeParams) -> Result<Vec<Weighted<'a, T>>>
+where
+    T: DataRow,
+{
+    if rows.is_empty() {
+        return Err(DecisionsError::Config("A tree needs at least one row to train on".to_string()));
+    }
+    let class_weights = params.class_weight.weights(rows)?;
+    rows.iter()
+        .map(|row| {
+            let weight = row.weight() * class_weights.get(&row.label()?).unwrap_or(&1.0);
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(DecisionsError::Config(format!(
+                    "Row {} has a weight of {}, weights can't be negative",
+                    row.id(),
+                    weight
+                )));
+            }
+            Ok(Weighted::new(*row, weight))
+        })
+        .collect()
+}
+
+///
+/// What a tree with monotonic constraints needs to keep its leaves in
+/// order: the constraints, the label that isn't constrained, and the
+/// bounds of the node being grown.
+///
+#[derive(Debug, Clone)]
+struct Limits<'a> {
+    monotonic: &'a Monotonic,
+    other: String,
+    bounds: Bounds,
+}
+
+/// The limits for the root of the tree, if it has monotonic constraints.
+fn limits<'a, T>(rows: &[&T], params: &'a TreeParams) -> Result<Option<Limits<'a>>>
+where
+    T: DataRow,
+{
+    match params.monotonic {
+        Some(ref monotonic) => Ok(Some(Limits {
+            monotonic,
+            other: monotonic.check(rows)?,
+            bounds: Bounds::default(),
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Grow the tree on rows that already have their class weights, asking
+/// `split` for the question to split each node on.
+fn grow<T, F>(rows: &[&T], params: &TreeParams, limits: Option<&Limits>, split: &mut F) -> Result<Node>
+where
+    T: DataRow,
+    F: FnMut(&[&T], &TreeParams) -> Result<(f64, Option<Question>)>,
+{
+    let depth = params.max_depth.map(|d| d.saturating_sub(1));
     match depth {
-        Some(0) => Node::new_leaf(&rows), // We have gone as deep as we need
+        Some(0) => Node::new_leaf(rows, limits), // We have gone as deep as we need
+        _ if rows.len() < params.min_samples_split => Node::new_leaf(rows, limits),
         _ => {
-            let (gain, question) = find_best_split(&rows);
-            if gain == 0.0 {
-                Node::new_leaf(&rows)
-            } else {
-                // We have found a useful feature to partition on.
-                let question = question.unwrap();
-                let (true_rows, false_rows) = partition(&rows, &question);
-                let true_branch = build_tree(&true_rows, depth);
-                let false_branch = build_tree(&false_rows, depth);
+            let (gain, question) = split(rows, params)?;
+            match question {
+                Some(question) if gain > 0.0 => {
+                    // We have found a useful feature to partition on.
+                    let (true_rows, false_rows) = partition(rows, &question)?;
+                    let params = TreeParams {
+                        max_depth: depth,
+                        ..params.clone()
+                    };
+                    // A split on a constrained column divides the bounds between the branches.
+                    let (true_limits, false_limits) = match limits {
+                        Some(limits) => {
+                            let (true_bounds, false_bounds) = limits.bounds.split(
+                                limits.monotonic,
+                                &question,
+                                limits.monotonic.probability(&true_rows)?,
+                                limits.monotonic.probability(&false_rows)?,
+                            );
+                            (
+                                Some(Limits { bounds: true_bounds, ..limits.clone() }),
+                                Some(Limits { bounds: false_bounds, ..limits.clone() }),
+                            )
+                        }
+                        None => (None, None),
+                    };
+                    let true_branch = grow(&true_rows, &params, true_limits.as_ref(), split)?;
+                    let false_branch = grow(&false_rows, &params, false_limits.as_ref(), split)?;
 
-                Node::new_decision(question, true_branch, false_branch)
+                    Ok(Node::new_decision(question, true_branch, false_branch))
+                }
+                _ => Node::new_leaf(rows, limits),
             }
         }
     }
 }
 
-/// Takes the map of probabilities and chooses the most likely one.
-fn prediction(classification: &HashMap<String, usize>) -> String {
-    let (k, _v) = classification
+/// Takes the map of probabilities and chooses the most likely one,
+/// the first label alphabetically if there is a tie. A leaf without any
+/// labels has nothing to predict.
+fn prediction(classification: &HashMap<String, f64>) -> Result<String> {
+    classification
         .iter()
-        .max_by(|(_k1, v1), (_k2, v2)| v1.cmp(v2))
-        .unwrap();
-    k.to_owned()
+        .max_by(|(k1, v1), (k2, v2)| {
+            v1.partial_cmp(v2).unwrap_or(Ordering::Equal).then_with(|| k2.cmp(k1))
+        })
+        .map(|(k, _v)| k.to_owned())
+        .ok_or_else(|| DecisionsError::Model("The leaf has no labels to predict from".to_string()))
 }
 
-pub fn classify<'a, T>(row: &T, node: &'a Node) -> String
+/// Follow the tree down to the leaf for the row and return its counts of each label.
+fn leaf<'a, T>(row: &T, node: &'a Node) -> Result<&'a HashMap<String, f64>>
 where
     T: DataRow,
 {
     match node {
-        Node::Leaf { predictions } => prediction(predictions),
+        Node::Leaf { predictions } => Ok(predictions),
         Node::Decision {
             question,
             true_branch,
             false_branch,
         } => {
-            if question.matchit(row) {
-                classify(row, &*true_branch)
+            if question.matchit(row)? {
+                leaf(row, true_branch)
             } else {
-                classify(row, &*false_branch)
+                leaf(row, false_branch)
             }
         }
     }
 }
 
+/// Follow the tree down to a leaf and return the most likely label for the row.
+pub fn classify<T>(row: &T, node: &Node) -> Result<String>
+where
+    T: DataRow,
+{
+    prediction(leaf(row, node)?)
+}
+
+impl Model for Node {
+    /// The share of the weight of the training rows in the row's leaf that has each label.
+    fn predict_proba<T>(&self, row: &T) -> Result<HashMap<String, f64>>
+    where
+        T: DataRow,
+    {
+        let counts = leaf(row, self)?;
+        let total: f64 = counts.values().sum();
+        if total <= 0.0 {
+            return Ok(HashMap::new());
+        }
+        Ok(counts
+            .iter()
+            .map(|(label, count)| (label.clone(), count / total))
+            .collect())
+    }
+
+    fn predict<T>(&self, row: &T) -> Result<String>
+    where
+        T: DataRow,
+    {
+        classify(row, self)
+    }
+}
+
+impl Estimator for TreeParams {
+    type Model = Node;
+
+    fn fit<T>(&self, rows: &[&T]) -> Result<Node>
+    where
+        T: DataRow,
+    {
+        build_tree_with(rows, self)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use row::test::{training_data, Fruit};
+    use metrics::accuracy;
+    use row::test::{points, training_data, Fruit, Point};
 
     #[test]
     fn test_class_counts() {
         let data = training_data();
         let data: Vec<&Fruit> = data.iter().collect();
-        let counts = class_counts(&data);
+        let counts = class_counts(&data).unwrap();
         let mut expected = HashMap::new();
-        expected.insert("Lemon".to_string(), 1);
-        expected.insert("Apple".to_string(), 2);
-        expected.insert("Grape".to_string(), 2);
+        expected.insert("Lemon".to_string(), 1.0);
+        expected.insert("Apple".to_string(), 2.0);
+        expected.insert("Grape".to_string(), 2.0);
 
         assert_eq!(expected, counts);
     }
@@ -335,63 +896,83 @@ mod test {
     #[test]
     fn test_class_counts_iter() {
         let data = training_data();
-        let (count, counts) = class_counts_iter(data.iter());
-        let mut expected = HashMap::new();
-        expected.insert("Lemon".to_string(), 1);
-        expected.insert("Apple".to_string(), 2);
-        expected.insert("Grape".to_string(), 2);
+        let data: Vec<&Fruit> = data.iter().collect();
+        let (count, counts) = class_counts_iter(data.iter()).unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert("Lemon".to_string(), 1.0);
+        expected.insert("Apple".to_string(), 2.0);
+        expected.insert("Grape".to_string(), 2.0);
 
-        assert_eq!(5, count);
+        assert_eq!(5.0, count);
         assert_eq!(expected, counts);
     }
 
     #[test]
     fn test_gini_pure() {
-        let p = vec![
+        let p = [
             Fruit::new(1, "Red".to_string(), 3, "Apple".to_string()),
             Fruit::new(2, "Green".to_string(), 2, "Apple".to_string()),
         ];
         let p: Vec<&Fruit> = p.iter().collect();
 
-        assert_eq!(0.0, gini(&p));
+        assert_eq!((2.0, 0.0), impurity(p.iter(), &TreeParams::default()).unwrap());
     }
 
     #[test]
-    fn test_gini_pure() {
-        let p = vec![
+    fn test_gini_impure() {
+        let p = [
             Fruit::new(1, "Red".to_string(), 3, "Apple".to_string()),
-            Fruit::new(2, "Green".to_string(), 2, "Apple".to_string()),
+            Fruit::new(2, "Red".to_string(), 3, "Orange".to_string()),
         ];
+        let p: Vec<&Fruit> = p.iter().collect();
 
-        assert_eq!(0.0, gini(p.iter()));
+        assert_eq!((2.0, 0.5), impurity(p.iter(), &TreeParams::default()).unwrap());
     }
 
     #[test]
-    fn test_gini_impure() {
-        let p = vec![
+    fn test_entropy() {
+        let p = [
             Fruit::new(1, "Red".to_string(), 3, "Apple".to_string()),
             Fruit::new(2, "Red".to_string(), 3, "Orange".to_string()),
         ];
         let p: Vec<&Fruit> = p.iter().collect();
 
-        assert_eq!(0.5, gini(&p));
+        let params = TreeParams {
+            criterion: Criterion::Entropy,
+            ..TreeParams::default()
+        };
+
+        assert_eq!((2.0, 1.0), impurity(p.iter(), &params).unwrap());
+        assert_eq!((1.0, 0.0), impurity(p[..1].iter(), &params).unwrap());
     }
 
     #[test]
-    fn test_gini_impure() {
-        let p = vec![
-            Fruit::new(1, "Red".to_string(), 3, "Apple".to_string()),
-            Fruit::new(2, "Red".to_string(), 3, "Orange".to_string()),
-        ];
+    fn test_min_samples() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
 
-        assert_eq!(0.5, gini(p.iter()));
+        // Too few rows to split at all.
+        let params = TreeParams {
+            min_samples_split: 6,
+            ..TreeParams::default()
+        };
+        assert_eq!(1, build_tree_with(&data, &params).unwrap().node_count());
+
+        // Each leaf needs two rows, so the lemon can't be split off on its own.
+        let params = TreeParams {
+            min_samples_leaf: 2,
+            criterion: Criterion::Entropy,
+            ..TreeParams::default()
+        };
+        let tree = build_tree_with(&data, &params).unwrap();
+        assert_eq!(2, tree.leaf_count());
     }
 
     #[test]
     fn test_column_values() {
         let data = training_data();
         let data: Vec<&Fruit> = data.iter().collect();
-        let cols = column_values(&data, 1);
+        let cols = column_values(data.iter(), 1).unwrap();
         assert_eq!(vec![Col::Int(1), Col::Int(3)], cols);
     }
 
@@ -399,8 +980,8 @@ mod test {
     fn test_question_matches() {
         let q = Question::new("Colour".to_string(), 0, Col::Text("Red".to_string()));
 
-        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 1, "toenails".to_string())));
-        assert!(!q.matchit(&Fruit::new(2, "Green".to_string(), 1, "spleen".to_string())));
+        assert!(q.matchit(&Fruit::new(1, "Red".to_string(), 1, "toenails".to_string())).unwrap());
+        assert!(!q.matchit(&Fruit::new(2, "Green".to_string(), 1, "spleen".to_string())).unwrap());
 
         let q = Question::new("Colour".to_string(), 1, Col::Int(42));
         assert!(q.matchit(&Fruit::new(
@@ -408,8 +989,136 @@ mod test {
             "Red".to_string(),
             42,
             "toenails".to_string()
-        )));
-        assert!(!q.matchit(&Fruit::new(2, "Red".to_string(), 1, "spleen".to_string())));
+        ))
+        .unwrap());
+        assert!(!q.matchit(&Fruit::new(2, "Red".to_string(), 1, "spleen".to_string())).unwrap());
+    }
+
+    #[test]
+    fn test_model() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let params = TreeParams {
+            max_depth: Some(2),
+            ..TreeParams::default()
+        };
+        let tree = params.fit(&data).unwrap();
+
+        let lemon = Fruit::new(1, "Yellow".to_string(), 3, "".to_string());
+        let probabilities = tree.predict_proba(&lemon).unwrap();
+        assert_eq!(Some(&(2.0 / 3.0)), probabilities.get("Apple"));
+        assert_eq!(Some(&(1.0 / 3.0)), probabilities.get("Lemon"));
+        assert_eq!("Apple", tree.predict(&lemon).unwrap());
+
+        let grape = Fruit::new(1, "Red".to_string(), 1, "".to_string());
+        assert_eq!("Grape", tree.predict(&grape).unwrap());
+    }
+
+    #[test]
+    fn test_nothing_to_learn() {
+        let empty: Vec<&Fruit> = Vec::new();
+        assert!(matches!(build_tree(&empty, None), Err(DecisionsError::Config(_))));
+
+        let leaf = Node::Leaf {
+            predictions: HashMap::new(),
+        };
+        let grape = Fruit::new(1, "Red".to_string(), 1, "".to_string());
+        assert!(matches!(classify(&grape, &leaf), Err(DecisionsError::Model(_))));
+    }
+
+    #[test]
+    fn test_category_subsets() {
+        let fruit = |id, colour: &str, label: &str| Fruit::new(id, colour.to_string(), 1, label.to_string());
+        let text = |colours: &[&str]| colours.iter().map(|c| Col::Text(c.to_string())).collect::<Vec<Col>>();
+        let root = |tree: &Node| match tree {
+            Node::Decision { question, .. } => question.predicate().clone(),
+            Node::Leaf { .. } => panic!("Expected a decision"),
+        };
+
+        // Red and blue are yes, green and yellow no, so a single split can separate them.
+        let data = [
+            fruit(1, "Red", "Yes"),
+            fruit(2, "Green", "No"),
+            fruit(3, "Blue", "Yes"),
+            fruit(4, "Yellow", "No"),
+            fruit(5, "Red", "Yes"),
+            fruit(6, "Green", "No"),
+        ];
+        let data: Vec<&Fruit> = data.iter().collect();
+        // With two classes only the runs of the categories ordered by the proportion of No are tried.
+        let subsets = category_subsets(&data, 0, &TreeParams::default()).unwrap();
+        assert_eq!(3, subsets.len());
+        let tree = build_tree(&data, Some(2)).unwrap();
+        assert_eq!(Predicate::In(text(&["Blue", "Red"])), root(&tree));
+        assert_eq!(1.0, accuracy(&tree, &data).unwrap());
+
+        // With more classes every subset is tried.
+        let data = [
+            fruit(1, "Red", "Apple"),
+            fruit(2, "Blue", "Apple"),
+            fruit(3, "Green", "Grape"),
+            fruit(4, "Yellow", "Lemon"),
+            fruit(5, "Red", "Apple"),
+            fruit(6, "Blue", "Apple"),
+        ];
+        let data: Vec<&Fruit> = data.iter().collect();
+        assert_eq!(7, category_subsets(&data, 0, &TreeParams::default()).unwrap().len());
+        let tree = build_tree(&data, Some(2)).unwrap();
+        assert_eq!(Predicate::In(text(&["Blue", "Red"])), root(&tree));
+    }
+
+    #[test]
+    fn test_thresholds() {
+        // Half way between each pair of neighbours, whether they are ints or floats.
+        let values = vec![Col::Null, Col::Int(1), Col::Float(2.0), Col::Int(2), Col::Float(3.5), Col::Float(f64::NAN)];
+        let thresholds: Vec<Predicate> = thresholds(&values);
+        assert_eq!(vec![Predicate::AtLeast(Col::Float(1.5)), Predicate::AtLeast(Col::Float(2.75))], thresholds);
+
+        // A value between the ones in the training rows goes with the nearer of them.
+        let point = |id, x, label: &str| Point {
+            id,
+            x,
+            y: 0.0,
+            label: label.to_string(),
+        };
+        let data = [point(1, 1, "No"), point(2, 5, "Yes")];
+        let data: Vec<&Point> = data.iter().collect();
+        let tree = build_tree(&data, None).unwrap();
+        match tree {
+            Node::Decision { ref question, .. } => assert_eq!("Is X >= 3", question.to_string()),
+            _ => panic!("Expected a decision"),
+        }
+        assert_eq!("No", tree.predict(&point(3, 2, "")).unwrap());
+        assert_eq!("Yes", tree.predict(&point(4, 4, "")).unwrap());
+    }
+
+    #[test]
+    fn test_random_predicate() {
+        let mut rng = Rng::new(4);
+        let numbers = [Col::Null, Col::Int(1), Col::Float(2.5), Col::Int(4)];
+        let text = [Col::Text("C".to_string()), Col::Text("Q".to_string()), Col::Text("S".to_string())];
+        for _ in 0..100 {
+            // Both sides of a threshold always get a value.
+            match random_predicate(&numbers, &mut rng) {
+                Some(Predicate::AtLeast(Col::Float(threshold))) => assert!(threshold > 1.0 && threshold <= 4.0),
+                other => panic!("Expected a threshold, got {:?}", other),
+            }
+            // And at least one category is left out.
+            match random_predicate(&text, &mut rng) {
+                Some(Predicate::Equal(_)) => {}
+                Some(Predicate::In(categories)) => assert_eq!(2, categories.len()),
+                other => panic!("Expected categories, got {:?}", other),
+            }
+        }
+
+        // Nothing to split a column with one value on, unless some are missing.
+        assert_eq!(None, random_predicate(&[Col::Int(3)], &mut rng));
+        assert_eq!(None, random_predicate(&[Col::Null, Col::Int(3)], &mut rng));
+        assert_eq!(None, random_predicate(&text[..1], &mut rng));
+        assert_eq!(
+            Some(Predicate::Equal(text[0].clone())),
+            random_predicate(&[Col::Null, text[0].clone()], &mut rng)
+        );
     }
 
     #[test]
@@ -418,9 +1127,152 @@ mod test {
         let data: Vec<&Fruit> = data.iter().collect();
         let q = Question::new("Colour".to_string(), 0, Col::Text("Red".to_string()));
 
-        let (t, f) = partition(&data, &q);
+        let (t, f) = partition(&data, &q).unwrap();
 
         assert_eq!(2, t.len());
         assert_eq!(3, f.len());
     }
+
+    #[test]
+    fn test_tree_size() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+
+        let tree = build_tree(&data, None).unwrap();
+        assert_eq!(3, tree.depth());
+        assert_eq!(5, tree.node_count());
+        assert_eq!(3, tree.leaf_count());
+
+        let stump = build_tree(&data, Some(1)).unwrap();
+        assert_eq!(1, stump.depth());
+        assert_eq!(1, stump.node_count());
+    }
+
+    fn same_tree(a: &Node, b: &Node) -> bool {
+        serde_json::to_value(a).unwrap() == serde_json::to_value(b).unwrap()
+    }
+
+    #[test]
+    fn test_weights_are_duplicates() {
+        let data = points(80, 1);
+        let mut rng = Rng::new(2);
+        let weights: Vec<usize> = data.iter().map(|_| rng.below(3) + 1).collect();
+
+        let weighted: Vec<Weighted<Point>> = data
+            .iter()
+            .zip(weights.iter())
+            .map(|(row, &weight)| Weighted::new(row, weight as f64))
+            .collect();
+        let weighted: Vec<&Weighted<Point>> = weighted.iter().collect();
+        let duplicated: Vec<&Point> = data
+            .iter()
+            .zip(weights.iter())
+            .flat_map(|(row, &weight)| vec![row; weight])
+            .collect();
+
+        for criterion in &[Criterion::Gini, Criterion::Entropy] {
+            let params = TreeParams {
+                criterion: *criterion,
+                ..TreeParams::default()
+            };
+            let a = params.fit(&weighted).unwrap();
+            let b = params.fit(&duplicated).unwrap();
+            assert!(a.node_count() > 3);
+            assert!(same_tree(&a, &b));
+        }
+    }
+
+    #[test]
+    fn test_class_weight() {
+        let data = points(80, 3);
+        let data: Vec<&Point> = data.iter().collect();
+
+        // Doubling a class is the same as having each of its rows twice.
+        let mut given = BTreeMap::new();
+        given.insert("Yes".to_string(), 2.0);
+        let params = TreeParams {
+            max_depth: Some(4),
+            class_weight: ClassWeight::Given(given),
+            ..TreeParams::default()
+        };
+        let duplicated: Vec<&Point> = data
+            .iter()
+            .flat_map(|row| if row.label == "Yes" { vec![*row; 2] } else { vec![*row] })
+            .collect();
+        let plain = TreeParams {
+            max_depth: Some(4),
+            ..TreeParams::default()
+        };
+        assert!(same_tree(&params.fit(&data).unwrap(), &plain.fit(&duplicated).unwrap()));
+
+        // Balanced weights make every class add up to the same.
+        let fruit = training_data();
+        let fruit: Vec<&Fruit> = fruit.iter().collect();
+        let weights = ClassWeight::Balanced.weights(&fruit).unwrap();
+        assert_eq!(5.0 / 6.0, weights["Apple"]);
+        assert_eq!(5.0 / 3.0, weights["Lemon"]);
+
+        let params = TreeParams {
+            max_depth: Some(1),
+            class_weight: ClassWeight::Balanced,
+            ..TreeParams::default()
+        };
+        let stump = params.fit(&fruit).unwrap();
+        match stump {
+            Node::Leaf { ref predictions } => {
+                assert!(predictions.values().all(|&total| (total - 5.0 / 3.0).abs() < 1e-12))
+            }
+            _ => panic!("Expected a leaf"),
+        }
+        // A tie goes to the first label.
+        assert_eq!("Apple", classify(fruit[4], &stump).unwrap());
+    }
+
+    #[test]
+    fn test_zero_class_weight() {
+        let fruit = training_data();
+        let fruit: Vec<&Fruit> = fruit.iter().collect();
+
+        // A class with no weight gets no probability rather than NaN.
+        let mut given = BTreeMap::new();
+        given.insert("Grape".to_string(), 0.0);
+        let params = TreeParams {
+            class_weight: ClassWeight::Given(given),
+            ..TreeParams::default()
+        };
+        let tree = params.fit(&fruit).unwrap();
+        for row in &fruit {
+            let probs = tree.predict_proba(*row).unwrap();
+            assert!(probs.values().all(|p| p.is_finite()));
+            assert_eq!(0.0, *probs.get("Grape").unwrap_or(&0.0));
+        }
+
+        // When nothing has any weight there's nothing to share out.
+        let mut given = BTreeMap::new();
+        for label in &["Apple", "Grape", "Lemon"] {
+            given.insert(label.to_string(), 0.0);
+        }
+        let params = TreeParams {
+            class_weight: ClassWeight::Given(given),
+            ..TreeParams::default()
+        };
+        let tree = params.fit(&fruit).unwrap();
+        assert!(tree.predict_proba(fruit[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_class_weight() {
+        assert_eq!(ClassWeight::Uniform, "uniform".parse().unwrap());
+        assert_eq!(ClassWeight::Balanced, "balanced".parse().unwrap());
+
+        let weight: ClassWeight = "Lived=2, Died=0.5".parse().unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert("Lived".to_string(), 2.0);
+        expected.insert("Died".to_string(), 0.5);
+        assert_eq!(ClassWeight::Given(expected), weight);
+        assert_eq!("Died=0.5,Lived=2", weight.to_string());
+
+        assert!("Lived".parse::<ClassWeight>().is_err());
+        assert!("Lived=-1".parse::<ClassWeight>().is_err());
+    }
 }
@@ -0,0 +1,615 @@
+//!
+//! Search for the hyperparameters that give the best cross validation score.
+//!
+//! A `Space` names each hyperparameter along with the values it can take :
+//!
+//! ```text
+//! depth=1..=10                 ints from 1 to 10
+//! min_samples_leaf=1..5        ints from 1 to 4
+//! criterion=gini,entropy       one of a list of values
+//! learning_rate=0.01..0.3      floats, these can only be sampled by a random search
+//! ```
+//!
+//! A grid search tries every combination, a random search samples a number
+//! of them. Each candidate is passed by name to the training function, so
+//! anything that can be trained from named parameters can be tuned.
+//! `tree_params` turns them into the settings for a single tree, and
+//! `train_tree` trains one with them. `extra_trees_params` adds `trees`,
+//! `max_features` and `seed` for a forest of extra trees, and
+//! `train_learner` trains a forest when any of those are searched over and
+//! a single tree when they aren't.
+//!
+//! There is no boosting here, so nothing takes a learning rate. A float
+//! range like the one above can be searched by a training function of your
+//! own, but none of the ones in this module will accept it.
+//!
+//! The candidates are cross validated in parallel, ranked by their mean
+//! score, and the best is trained again on all of the data.
+//!
+
+use cross_validation::{fold_dataset, fold_scores};
+use metrics::accuracy;
+use model::{Estimator, Model};
+use error::{DecisionsError, Result};
+use forest::ExtraTreesParams;
+use random::Rng;
+use rayon::prelude::*;
+use row::DataRow;
+use saved::Learner;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use tree::{Criterion, Node, TreeParams};
+
+/// The value of a hyperparameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Text(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = DecisionsError;
+
+    /// Ints and floats are parsed as numbers, anything else is text.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        Ok(if let Ok(v) = s.parse() {
+            Value::Int(v)
+        } else if let Ok(v) = s.parse() {
+            Value::Float(v)
+        } else {
+            Value::Text(s.to_string())
+        })
+    }
+}
+
+/// The values a hyperparameter can take.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dimension {
+    /// One of the given values.
+    Choice(Vec<Value>),
+    /// An int in `[low, high)`.
+    Int { low: i64, high: i64 },
+    /// A float in `[low, high)`.
+    Float { low: f64, high: f64 },
+}
+
+impl FromStr for Dimension {
+    type Err = DecisionsError;
+
+    /// Parse `low..high`, `low..=high` or a comma separated list of values.
+    fn from_str(s: &str) -> Result<Self> {
+        let range = |sep: &str| s.find(sep).map(|idx| (s[..idx].parse(), s[idx + sep.len()..].parse()));
+        let dimension = match (range("..="), range("..")) {
+            (Some((low, high)), _) | (None, Some((low, high))) => {
+                let inclusive = s.contains("..=");
+                match (low?, high?) {
+                    (Value::Int(low), Value::Int(high)) => Dimension::Int {
+                        low,
+                        high: if inclusive { high + 1 } else { high },
+                    },
+                    (Value::Int(low), Value::Float(high)) => Dimension::Float { low: low as f64, high },
+                    (Value::Float(low), Value::Int(high)) => Dimension::Float { low, high: high as f64 },
+                    (Value::Float(low), Value::Float(high)) => Dimension::Float { low, high },
+                    _ => return Err(DecisionsError::Config(format!("{} isn't a range of numbers", s))),
+                }
+            }
+            (None, None) => Dimension::Choice(s.split(',').map(str::parse).collect::<Result<Vec<Value>>>()?),
+        };
+
+        let empty = match dimension {
+            Dimension::Choice(ref values) => values.is_empty(),
+            Dimension::Int { low, high } => low >= high,
+            Dimension::Float { low, high } => low >= high,
+        };
+        if empty {
+            return Err(DecisionsError::Config(format!("{} doesn't have any values", s)));
+        }
+        Ok(dimension)
+    }
+}
+
+impl Dimension {
+    /// Every value, for a grid search.
+    fn values(&self, name: &str) -> Result<Vec<Value>> {
+        match self {
+            Dimension::Choice(values) => Ok(values.clone()),
+            Dimension::Int { low, high } => Ok((*low..*high).map(Value::Int).collect()),
+            Dimension::Float { .. } => Err(DecisionsError::Config(format!(
+                "{} is a range of floats so can't be searched on a grid, give a list of values instead",
+                name
+            ))),
+        }
+    }
+
+    /// A random value, every value being equally likely.
+    pub fn sample(&self, rng: &mut Rng) -> Value {
+        match self {
+            Dimension::Choice(values) => values[rng.below(values.len())].clone(),
+            Dimension::Int { low, high } => Value::Int(low + rng.below((high - low) as usize) as i64),
+            Dimension::Float { low, high } => Value::Float(rng.range(*low, *high)),
+        }
+    }
+}
+
+/// A value for each hyperparameter, keyed by its name.
+pub type Params = BTreeMap<String, Value>;
+
+/// The hyperparameters to search over.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Space {
+    dimensions: BTreeMap<String, Dimension>,
+}
+
+impl Space {
+    pub fn new() -> Self {
+        Space::default()
+    }
+
+    pub fn add(&mut self, name: &str, dimension: Dimension) {
+        self.dimensions.insert(name.to_string(), dimension);
+    }
+
+    ///
+    /// Make the space from a list of `name=values`,
+    /// for example `["depth=1..=10", "criterion=gini,entropy"]`.
+    ///
+    pub fn parse(specs: &[&str]) -> Result<Self> {
+        let mut space = Space::new();
+        for spec in specs {
+            let idx = spec
+                .find('=')
+                .ok_or_else(|| DecisionsError::Config(format!("{} should look like name=values", spec)))?;
+            space.add(spec[..idx].trim(), spec[idx + 1..].parse()?);
+        }
+        Ok(space)
+    }
+
+    /// Each hyperparameter and the values it can take.
+    pub fn dimensions(&self) -> &BTreeMap<String, Dimension> {
+        &self.dimensions
+    }
+
+    /// The names of the hyperparameters, in the order they appear in `Params`.
+    pub fn names(&self) -> Vec<&str> {
+        self.dimensions.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// Every combination of the values.
+    pub fn grid(&self) -> Result<Vec<Params>> {
+        let mut grid = vec![Params::new()];
+        for (name, dimension) in &self.dimensions {
+            let values = dimension.values(name)?;
+            grid = grid
+                .iter()
+                .flat_map(|params| {
+                    values.iter().map(move |value| {
+                        let mut params = params.clone();
+                        params.insert(name.clone(), value.clone());
+                        params
+                    })
+                })
+                .collect();
+        }
+        Ok(grid)
+    }
+
+    /// A number of randomly chosen combinations.
+    pub fn sample(&self, trials: usize, seed: u64) -> Vec<Params> {
+        let mut rng = Rng::new(seed);
+        (0..trials).map(|_| self.sample_with(&mut rng)).collect()
+    }
+
+    /// One randomly chosen combination.
+    pub fn sample_with(&self, rng: &mut Rng) -> Params {
+        self.dimensions
+            .iter()
+            .map(|(name, dimension)| (name.clone(), dimension.sample(rng)))
+            .collect()
+    }
+}
+
+/// How well one combination of hyperparameters did.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trial {
+    pub params: Params,
+    /// The score on each fold.
+    pub scores: Vec<f64>,
+    pub mean: f64,
+    /// The population standard deviation of the scores.
+    pub std: f64,
+}
+
+impl Trial {
+    pub fn new(params: Params, scores: Vec<f64>) -> Self {
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / scores.len() as f64;
+        Trial {
+            params,
+            scores,
+            mean,
+            std: variance.sqrt(),
+        }
+    }
+}
+
+/// The result of a search.
+#[derive(Debug)]
+pub struct Search<M = Node> {
+    /// Every trial, best first.
+    pub leaderboard: Vec<Trial>,
+    /// The best hyperparameters trained again on all of the rows.
+    pub best: M,
+}
+
+///
+/// Cross validate each of the candidates, in parallel, then train the best on all the rows.
+///
+/// #Arguments
+///
+/// * `rows` - The rows to train and validate on.
+/// * `folds` - The number of cross validation folds.
+/// * `candidates` - The hyperparameters to try.
+/// * `train_fn` - Trains a tree on some rows with the given hyperparameters.
+///
+pub fn search<T, M, F>(rows: &[&T], folds: usize, candidates: Vec<Params>, train_fn: F) -> Result<Search<M>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    if candidates.is_empty() {
+        return Err(DecisionsError::Config("There aren't any hyperparameters to try".to_string()));
+    }
+
+    let mut leaderboard = candidates
+        .into_par_iter()
+        .map(|params| {
+            let scores = fold_scores(rows, folds, |train| train_fn(&params, train))?;
+            Ok(Trial::new(params, scores))
+        })
+        .collect::<Result<Vec<Trial>>>()?;
+
+    // Best first, keeping the order we were given for ties.
+    leaderboard.sort_by(|a, b| b.mean.partial_cmp(&a.mean).unwrap_or(::std::cmp::Ordering::Equal));
+
+    let best = train_fn(&leaderboard[0].params, rows.to_vec())?;
+    Ok(Search { leaderboard, best })
+}
+
+/// Search every combination of the hyperparameters.
+pub fn grid_search<T, M, F>(rows: &[&T], folds: usize, space: &Space, train_fn: F) -> Result<Search<M>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    search(rows, folds, space.grid()?, train_fn)
+}
+
+/// Search a number of random combinations of the hyperparameters.
+pub fn random_search<T, M, F>(
+    rows: &[&T],
+    folds: usize,
+    space: &Space,
+    trials: usize,
+    seed: u64,
+    train_fn: F,
+) -> Result<Search<M>>
+where
+    T: DataRow + Sync,
+    M: Model,
+    F: Fn(&Params, Vec<&T>) -> Result<M> + Sync,
+{
+    search(rows, folds, space.sample(trials, seed), train_fn)
+}
+
+/// What the search chose on one of the outer folds of a nested cross validation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OuterFold {
+    /// The hyperparameters the inner search chose.
+    pub params: Params,
+    /// Their mean score in the inner search.
+    pub inner: f64,
+    /// The score of the tree they trained on the held out outer fold.
+    pub score: f64,
+}
+
+/// The result of a nested cross validation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Nested {
+    pub folds: Vec<OuterFold>,
+    /// The mean of the outer scores, an estimate of how well the search does on unseen data.
+    pub mean: f64,
+    /// The population standard deviation of the outer scores.
+    pub std: f64,
+}
+
+///
+/// Nested cross validation, to see how well a search really does.
+///
+/// The score of the best candidate of a search is optimistic, as it was chosen
+/// for doing well on those folds. So for each outer fold we run the whole search
+/// on the rest of the rows and score the tree it chooses on the held out fold,
+/// which the search never saw.
+///
+/// #Arguments
+///
+/// * `rows` - The rows to train and validate on.
+/// * `folds` - The number of outer folds.
+/// * `search_fn` - Runs the inner search on the training rows of an outer fold.
+///
+pub fn nested_cross_validation<T, M, S>(rows: &[&T], folds: usize, search_fn: S) -> Result<Nested>
+where
+    T: DataRow,
+    M: Model,
+    S: Fn(&[&T]) -> Result<Search<M>>,
+{
+    let mut outer = Vec::new();
+    for fold in 0..folds {
+        let (train, test) = fold_dataset(rows, folds, fold)?;
+        let search = search_fn(&train)?;
+        let best = &search.leaderboard[0];
+        outer.push(OuterFold {
+            params: best.params.clone(),
+            inner: best.mean,
+            score: accuracy(&search.best, &test)?,
+        });
+    }
+
+    let scores = Trial::new(Params::new(), outer.iter().map(|fold| fold.score).collect());
+    Ok(Nested {
+        folds: outer,
+        mean: scores.mean,
+        std: scores.std,
+    })
+}
+
+fn int(name: &str, value: &Value, min: i64) -> Result<i64> {
+    match value {
+        Value::Int(v) if *v >= min => Ok(*v),
+        _ => Err(DecisionsError::Config(format!(
+            "{} should be a whole number of at least {}, not {}",
+            name, min, value
+        ))),
+    }
+}
+
+///
+/// The settings for a tree from the hyperparameters `depth`,
+/// `min_samples_split`, `min_samples_leaf` and `criterion`.
+/// Anything not given is left at its default.
+///
+pub fn tree_params(params: &Params) -> Result<TreeParams> {
+    let mut tree = TreeParams::default();
+    for (name, value) in params {
+        match name.as_str() {
+            "depth" => tree.max_depth = Some(int(name, value, 1)? as u64),
+            "min_samples_split" => tree.min_samples_split = int(name, value, 2)? as usize,
+            "min_samples_leaf" => tree.min_samples_leaf = int(name, value, 1)? as usize,
+            "criterion" => {
+                tree.criterion = match value {
+                    Value::Text(ref c) if c == "gini" => Criterion::Gini,
+                    Value::Text(ref c) if c == "entropy" => Criterion::Entropy,
+                    _ => {
+                        return Err(DecisionsError::Config(format!(
+                            "criterion should be gini or entropy, not {}",
+                            value
+                        )))
+                    }
+                }
+            }
+            "class_weight" => tree.class_weight = value.to_string().parse()?,
+            _ => {
+                return Err(DecisionsError::Config(format!(
+                    "{} isn't a tree parameter, expected depth, min_samples_split, min_samples_leaf, criterion or class_weight",
+                    name
+                )))
+            }
+        }
+    }
+    Ok(tree)
+}
+
+/// Train a tree with the settings from `tree_params`, for use as the training function of a search.
+pub fn train_tree<T>(params: &Params, rows: Vec<&T>) -> Result<Node>
+where
+    T: DataRow,
+{
+    tree_params(params)?.fit(&rows)
+}
+
+/// The hyperparameters that only make sense for a forest.
+const FOREST_PARAMS: [&str; 3] = ["trees", "max_features", "seed"];
+
+///
+/// The settings for a forest of extra trees from the hyperparameters
+/// `trees`, `max_features` and `seed`, with the rest passed on to
+/// `tree_params` for each of the trees. Anything not given is left at its default.
+///
+pub fn extra_trees_params(params: &Params) -> Result<ExtraTreesParams> {
+    let mut tree = Params::new();
+    let mut forest = ExtraTreesParams::default();
+    for (name, value) in params {
+        match name.as_str() {
+            "trees" => forest.trees = int(name, value, 1)? as usize,
+            "max_features" => forest.max_features = Some(int(name, value, 1)? as usize),
+            "seed" => forest.seed = int(name, value, 0)? as u64,
+            _ => {
+                tree.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    forest.tree = tree_params(&tree)?;
+    Ok(forest)
+}
+
+///
+/// Train a forest with the settings from `extra_trees_params` if any of
+/// `trees`, `max_features` or `seed` are given, otherwise a single tree.
+/// Either can be used as the training function of a search.
+///
+pub fn train_learner<T>(params: &Params, rows: Vec<&T>) -> Result<Learner>
+where
+    T: DataRow,
+{
+    if FOREST_PARAMS.iter().any(|name| params.contains_key(*name)) {
+        Ok(Learner::Forest(extra_trees_params(params)?.fit(&rows)?))
+    } else {
+        Ok(Learner::Tree(train_tree(params, rows)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tree::ClassWeight;
+    use row::test::{training_data, Fruit};
+
+    #[test]
+    fn test_parse_dimension() {
+        assert_eq!(Dimension::Int { low: 1, high: 11 }, "1..=10".parse().unwrap());
+        assert_eq!(Dimension::Int { low: 1, high: 10 }, "1..10".parse().unwrap());
+        assert_eq!(Dimension::Float { low: 0.01, high: 0.3 }, "0.01..0.3".parse().unwrap());
+        assert_eq!(
+            Dimension::Choice(vec![Value::Text("gini".to_string()), Value::Text("entropy".to_string())]),
+            "gini,entropy".parse().unwrap()
+        );
+        assert_eq!(Dimension::Choice(vec![Value::Int(5)]), "5".parse().unwrap());
+        assert!("5..1".parse::<Dimension>().is_err());
+        assert!("a..b".parse::<Dimension>().is_err());
+    }
+
+    #[test]
+    fn test_grid() {
+        let space = Space::parse(&["depth=1..=3", "criterion=gini,entropy"]).unwrap();
+        let grid = space.grid().unwrap();
+        assert_eq!(6, grid.len());
+        assert_eq!(Some(&Value::Int(1)), grid[0].get("depth"));
+        assert_eq!(Some(&Value::Text("gini".to_string())), grid[0].get("criterion"));
+
+        let space = Space::parse(&["learning_rate=0.01..0.3"]).unwrap();
+        assert!(space.grid().is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let space = Space::parse(&["depth=1..=3", "learning_rate=0.01..0.3"]).unwrap();
+        let samples = space.sample(20, 42);
+        assert_eq!(samples, space.sample(20, 42));
+        for params in samples {
+            match (&params["depth"], &params["learning_rate"]) {
+                (Value::Int(depth), Value::Float(rate)) => {
+                    assert!((1..=3).contains(depth));
+                    assert!((0.01..0.3).contains(rate));
+                }
+                other => panic!("Unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+        let space = Space::parse(&["depth=1..=3"]).unwrap();
+
+        let search = grid_search(&data, 5, &space, train_tree).unwrap();
+
+        assert_eq!(3, search.leaderboard.len());
+        assert!(search
+            .leaderboard
+            .windows(2)
+            .all(|pair| pair[0].mean >= pair[1].mean));
+        for trial in &search.leaderboard {
+            assert_eq!(5, trial.scores.len());
+            assert!(trial.std >= 0.0);
+        }
+        assert_eq!(Value::Int(2), search.leaderboard[0].params["depth"]);
+        assert_eq!(2, search.best.depth());
+    }
+
+    #[test]
+    fn test_nested_cross_validation() {
+        let mut data = training_data();
+        data.extend(training_data());
+        let data: Vec<&Fruit> = data.iter().collect();
+        let space = Space::parse(&["depth=1..=3"]).unwrap();
+
+        let nested = nested_cross_validation(&data, 5, |train| grid_search(train, 4, &space, train_tree)).unwrap();
+
+        assert_eq!(5, nested.folds.len());
+        for fold in &nested.folds {
+            assert!(fold.params.contains_key("depth"));
+            assert!((0.0..=1.0).contains(&fold.score));
+        }
+        let mean = nested.folds.iter().map(|fold| fold.score).sum::<f64>() / 5.0;
+        assert!((mean - nested.mean).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tree_params() {
+        let space = Space::parse(&["depth=4", "criterion=entropy", "min_samples_leaf=3", "class_weight=balanced"]).unwrap();
+        let params = tree_params(&space.grid().unwrap()[0]).unwrap();
+        assert_eq!(Some(4), params.max_depth);
+        assert_eq!(Criterion::Entropy, params.criterion);
+        assert_eq!(3, params.min_samples_leaf);
+        assert_eq!(2, params.min_samples_split);
+        assert_eq!(ClassWeight::Balanced, params.class_weight);
+
+        for spec in &["depth=0", "criterion=chaos", "class_weight=heavy", "trees=10"] {
+            let space = Space::parse(&[spec]).unwrap();
+            assert!(tree_params(&space.grid().unwrap()[0]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_extra_trees_params() {
+        let space = Space::parse(&["trees=7", "max_features=2", "seed=3", "depth=4"]).unwrap();
+        let params = extra_trees_params(&space.grid().unwrap()[0]).unwrap();
+        assert_eq!(7, params.trees);
+        assert_eq!(Some(2), params.max_features);
+        assert_eq!(3, params.seed);
+        assert_eq!(Some(4), params.tree.max_depth);
+
+        for spec in &["trees=0", "max_features=0", "learning_rate=0.1"] {
+            let space = Space::parse(&[spec]).unwrap();
+            assert!(extra_trees_params(&space.grid().unwrap()[0]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_train_learner() {
+        let data = training_data();
+        let data: Vec<&Fruit> = data.iter().collect();
+
+        let space = Space::parse(&["trees=3,5", "depth=2"]).unwrap();
+        let search = grid_search(&data, 5, &space, train_learner).unwrap();
+        assert_eq!(2, search.leaderboard.len());
+        match search.best {
+            Learner::Forest(ref forest) => assert_eq!(search.leaderboard[0].params["trees"], Value::Int(forest.trees.len() as i64)),
+            Learner::Tree(_) => panic!("Expected a forest"),
+        }
+
+        let space = Space::parse(&["depth=1..=3"]).unwrap();
+        let search = grid_search(&data, 5, &space, train_learner).unwrap();
+        assert_eq!(1, search.best.trees().len());
+        match search.best {
+            Learner::Tree(ref tree) => assert_eq!(2, tree.depth()),
+            Learner::Forest(_) => panic!("Expected a tree"),
+        }
+    }
+}